@@ -0,0 +1,48 @@
+//! Compares a single-lock in-memory storage with the default sharded one when many
+//! threads check distinct users concurrently, and the default hasher with FxHash.
+//!
+//! Run with `cargo bench --bench in_memory_contention`.
+
+use ratelimiter_rs::{InMemoryStorage, RateLimiter, Storage};
+use rustc_hash::FxBuildHasher;
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+
+const THREADS: usize = 8;
+const USERS_PER_THREAD: usize = 1000;
+const ITERATIONS: usize = 50;
+
+fn main() {
+    run("1 shard", InMemoryStorage::with_shards(1));
+    run("default shards", InMemoryStorage::new());
+    run("fxhash", InMemoryStorage::with_hasher(FxBuildHasher));
+}
+
+fn run(name: &str, storage: impl Storage + 'static) {
+    let mut limiter = RateLimiter::with_storage(storage);
+    limiter.add_config("bench", 10, 60000);
+    let limiter = Arc::new(limiter);
+
+    let start = Instant::now();
+    let handles: Vec<_> = (0..THREADS)
+        .map(|thread_id| {
+            let limiter = Arc::clone(&limiter);
+            thread::spawn(move || {
+                let user_ids: Vec<String> = (0..USERS_PER_THREAD).map(|user| format!("user{}-{}", thread_id, user)).collect();
+                for _ in 0..ITERATIONS {
+                    for user_id in &user_ids {
+                        limiter.allowed(user_id, "bench").unwrap();
+                    }
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    let elapsed = start.elapsed();
+    let operations = THREADS * USERS_PER_THREAD * ITERATIONS;
+    println!("{:<16} {:>10.0} ops/s", name, operations as f64 / elapsed.as_secs_f64());
+}
@@ -0,0 +1,54 @@
+//! Compares opening a Redis connection per call, as `allowed` used to, with the pooled
+//! connections `RateLimiter::with_redis_pool` reuses, and measures the script hashing that
+//! `allowed` used to repeat on every call before scripts were cached.
+//!
+//! Needs a running Redis server: `REDIS_URL=redis://127.0.0.1/ cargo bench --bench redis_pool`.
+//! Only the script hashing runs without one.
+
+use ratelimiter_rs::RateLimiter;
+use std::time::Instant;
+
+const ITERATIONS: u32 = 2000;
+
+fn main() {
+    // about the size of the sliding window script.
+    let source = "redis.call('ZCOUNT', KEYS[1], ARGV[1], ARGV[2])\n".repeat(40);
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        std::hint::black_box(redis::Script::new(std::hint::black_box(&source)));
+    }
+    report("script hashed per call", start);
+
+    let redis_url = std::env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1/".to_string());
+    let client = redis::Client::open(redis_url.as_str()).expect("invalid REDIS_URL");
+    if client.get_connection().is_err() {
+        eprintln!("skipping benchmark: no Redis server at {}", redis_url);
+        return;
+    }
+
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        let mut con = client.get_connection().unwrap();
+        redis::cmd("PING").query::<()>(&mut con).unwrap();
+    }
+    report("connection per call (PING only)", start);
+
+    let mut limiter = RateLimiter::with_redis_pool(&redis_url, 1).unwrap();
+    limiter.set_key_prefix("ratelimiter-bench").add_config("bench", u32::MAX, 60000);
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        limiter.allowed("user1", "bench").unwrap();
+    }
+    report("pooled allowed", start);
+    limiter.reset("user1", "bench").unwrap();
+}
+
+fn report(name: &str, start: Instant) {
+    let elapsed = start.elapsed();
+    println!(
+        "{:<32} {:>8.0} ops/s ({:?} per op)",
+        name,
+        ITERATIONS as f64 / elapsed.as_secs_f64(),
+        elapsed / ITERATIONS
+    );
+}
@@ -0,0 +1,114 @@
+use crate::error::RateLimiterError;
+use crate::storage::Storage;
+use crate::RateLimiter;
+
+/// The storage backend selected on a [`RateLimiterBuilder`].
+enum Backend {
+    InMemory,
+    #[cfg(feature = "redis")]
+    Redis(String),
+    Custom(Box<dyn Storage>),
+}
+
+/// Assembles a [`RateLimiter`] in a single expression, e.g. for a `once_cell::sync::Lazy` static.
+///
+/// # Examples
+///
+/// ```
+/// use ratelimiter_rs::RateLimiter;
+///
+/// let limiter = RateLimiter::builder()
+///     .in_memory()
+///     .config("type1", 5, 60000)
+///     .config("type2", 10, 30000)
+///     .build()
+///     .unwrap();
+///
+/// assert!(limiter.allowed("user1", "type1").unwrap());
+/// ```
+#[derive(Default)]
+pub struct RateLimiterBuilder {
+    backend: Option<Backend>,
+    key_prefix: Option<String>,
+    // request type, capacity, window time in milliseconds
+    configs: Vec<(String, u32, u64)>,
+}
+
+impl RateLimiterBuilder {
+    /// Creates a builder with no storage backend and no configs.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keeps request logs in memory, replacing any previously selected backend.
+    pub fn in_memory(mut self) -> Self {
+        self.backend = Some(Backend::InMemory);
+        self
+    }
+
+    /// Keeps request logs in Redis, replacing any previously selected backend.
+    ///
+    /// # Arguments
+    ///
+    /// * `redis_url` - The URL of the Redis server, validated by [`build`](RateLimiterBuilder::build).
+    #[cfg(feature = "redis")]
+    pub fn redis(mut self, redis_url: &str) -> Self {
+        self.backend = Some(Backend::Redis(redis_url.to_string()));
+        self
+    }
+
+    /// Keeps request logs in a custom storage backend, replacing any previously selected backend.
+    ///
+    /// # Arguments
+    ///
+    /// * `storage` - The backend that keeps the request logs.
+    pub fn storage(mut self, storage: impl Storage + 'static) -> Self {
+        self.backend = Some(Backend::Custom(Box::new(storage)));
+        self
+    }
+
+    /// Sets the namespace prepended to every storage key.
+    ///
+    /// # Arguments
+    ///
+    /// * `prefix` - The namespace, e.g. `myapp` for `myapp:user1:type1`.
+    pub fn key_prefix(mut self, prefix: &str) -> Self {
+        self.key_prefix = Some(prefix.to_string());
+        self
+    }
+
+    /// Adds a sliding window configuration for a request type, as [`RateLimiter::add_config`] does.
+    ///
+    /// # Arguments
+    ///
+    /// * `request_type` - The type of request to configure.
+    /// * `capacity` - The maximum number of requests allowed in the window time.
+    /// * `window_time_millis` - The length of the sliding window in milliseconds.
+    pub fn config(mut self, request_type: &str, capacity: u32, window_time_millis: u64) -> Self {
+        self.configs.push((request_type.to_string(), capacity, window_time_millis));
+        self
+    }
+
+    /// Builds the rate limiter.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no storage backend was selected or the Redis URL is invalid.
+    pub fn build(self) -> Result<RateLimiter, RateLimiterError> {
+        let mut limiter = match self.backend {
+            Some(Backend::InMemory) => RateLimiter::with_in_memory(),
+            #[cfg(feature = "redis")]
+            Some(Backend::Redis(redis_url)) => RateLimiter::with_redis(&redis_url)?,
+            Some(Backend::Custom(storage)) => RateLimiter::with_boxed_storage(storage),
+            None => return Err(RateLimiterError::Message("no storage backend selected".to_string())),
+        };
+
+        if let Some(prefix) = &self.key_prefix {
+            limiter.set_key_prefix(prefix);
+        }
+        for (request_type, capacity, window_time_millis) in &self.configs {
+            limiter.add_config(request_type, *capacity, *window_time_millis);
+        }
+        Ok(limiter)
+    }
+}
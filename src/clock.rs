@@ -0,0 +1,140 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// A source of the current time used to place requests in their window.
+pub trait Clock: Send + Sync {
+    /// Returns the current time in milliseconds since the unix epoch.
+    fn now_millis(&self) -> u64;
+
+    /// Returns the current time in microseconds since the unix epoch, for microsecond configs.
+    ///
+    /// The default implementation scales [`now_millis`](Clock::now_millis), so clocks that can
+    /// read a finer time should override it.
+    fn now_micros(&self) -> u64 {
+        self.now_millis().saturating_mul(1000)
+    }
+}
+
+/// The default clock, reading the system wall-clock time.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_millis(&self) -> u64 {
+        // a system clock set before the unix epoch is treated as the epoch itself.
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_millis() as u64)
+            .unwrap_or(0)
+    }
+
+    fn now_micros(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_micros() as u64)
+            .unwrap_or(0)
+    }
+}
+
+/// A clock that never goes backwards, for the in-memory backend.
+///
+/// It reads the wall-clock time once when created and then advances with [`Instant`], so
+/// NTP adjustments of the system clock cannot reorder timestamps. Its time drifts from the
+/// wall clock of other nodes, so the Redis backend should keep using [`SystemClock`] to stay
+/// consistent across app servers.
+///
+/// # Examples
+///
+/// ```
+/// use ratelimiter_rs::{MonotonicClock, RateLimiter};
+///
+/// let mut limiter = RateLimiter::with_in_memory_clock(MonotonicClock::new());
+/// limiter.add_config("type1", 5, 60000);
+/// assert!(limiter.allowed("user1", "type1").unwrap());
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct MonotonicClock {
+    epoch_millis: u64,
+    epoch: Instant,
+}
+
+impl MonotonicClock {
+    /// Creates a clock anchored to the current wall-clock time.
+    pub fn new() -> Self {
+        Self { epoch_millis: SystemClock.now_millis(), epoch: Instant::now() }
+    }
+}
+
+impl Default for MonotonicClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for MonotonicClock {
+    fn now_millis(&self) -> u64 {
+        self.epoch_millis.saturating_add(self.epoch.elapsed().as_millis() as u64)
+    }
+
+    fn now_micros(&self) -> u64 {
+        self.epoch_millis.saturating_mul(1000).saturating_add(self.epoch.elapsed().as_micros() as u64)
+    }
+}
+
+/// A clock that only moves when told to, for deterministic tests.
+///
+/// Clones share the same time, so a test can keep one handle and advance it
+/// after moving another into the rate limiter.
+///
+/// # Examples
+///
+/// ```
+/// use ratelimiter_rs::{Clock, MockClock};
+/// use std::time::Duration;
+///
+/// let clock = MockClock::new(1000);
+/// clock.advance(Duration::from_millis(500));
+/// assert_eq!(clock.now_millis(), 1500);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct MockClock {
+    // microseconds since the unix epoch
+    now: Arc<AtomicU64>,
+}
+
+impl MockClock {
+    /// Creates a clock stopped at `now_millis`.
+    pub fn new(now_millis: u64) -> Self {
+        Self { now: Arc::new(AtomicU64::new(now_millis.saturating_mul(1000))) }
+    }
+
+    /// Moves the clock forward by `duration`, down to the microsecond.
+    pub fn advance(&self, duration: Duration) {
+        self.now.fetch_add(duration.as_micros() as u64, Ordering::SeqCst);
+    }
+
+    /// Sets the clock to `now_millis`.
+    pub fn set(&self, now_millis: u64) {
+        self.now.store(now_millis.saturating_mul(1000), Ordering::SeqCst);
+    }
+}
+
+impl Clock for MockClock {
+    fn now_millis(&self) -> u64 {
+        self.now.load(Ordering::SeqCst) / 1000
+    }
+
+    fn now_micros(&self) -> u64 {
+        self.now.load(Ordering::SeqCst)
+    }
+}
+
+/// A clock stopped at a time the caller supplies, for [`RateLimiter::allowed_at`](crate::RateLimiter::allowed_at).
+pub(crate) struct FixedClock(pub(crate) u64);
+
+impl Clock for FixedClock {
+    fn now_millis(&self) -> u64 {
+        self.0
+    }
+}
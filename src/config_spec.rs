@@ -0,0 +1,70 @@
+/// A rate limit configuration for one request type, as data, e.g. to load all configs from a
+/// file at startup and pass them to [`RateLimiter::add_configs`](crate::RateLimiter::add_configs).
+///
+/// With the `serde` feature, specs can be deserialized, tagged by their `algorithm`:
+///
+/// ```toml
+/// [[configs]]
+/// algorithm = "sliding_window"
+/// request_type = "api"
+/// capacity = 100
+/// window_time_millis = 60000
+///
+/// [[configs]]
+/// algorithm = "token_bucket"
+/// request_type = "upload"
+/// capacity = 10
+/// refill_per_sec = 0.5
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(tag = "algorithm", rename_all = "snake_case"))]
+pub enum ConfigSpec {
+    /// See [`RateLimiter::add_config`](crate::RateLimiter::add_config).
+    SlidingWindow { request_type: String, capacity: u32, window_time_millis: u64 },
+    /// See [`RateLimiter::add_config_token_bucket`](crate::RateLimiter::add_config_token_bucket).
+    TokenBucket { request_type: String, capacity: u32, refill_per_sec: f64 },
+    /// See [`RateLimiter::add_config_leaky_bucket`](crate::RateLimiter::add_config_leaky_bucket).
+    LeakyBucket { request_type: String, rate_per_sec: f64, bucket_size: u32 },
+    /// See [`RateLimiter::add_config_gcra`](crate::RateLimiter::add_config_gcra).
+    Gcra { request_type: String, rate_per_sec: f64, burst: u32 },
+    /// See [`RateLimiter::add_config_fixed_window`](crate::RateLimiter::add_config_fixed_window).
+    FixedWindow { request_type: String, capacity: u32, window_time_millis: u64 },
+    /// See [`RateLimiter::add_config_sliding_counter`](crate::RateLimiter::add_config_sliding_counter).
+    SlidingCounter { request_type: String, capacity: u32, window_time_millis: u64 },
+}
+
+impl ConfigSpec {
+    /// Returns the request type this spec configures.
+    pub fn request_type(&self) -> &str {
+        match self {
+            ConfigSpec::SlidingWindow { request_type, .. }
+            | ConfigSpec::TokenBucket { request_type, .. }
+            | ConfigSpec::LeakyBucket { request_type, .. }
+            | ConfigSpec::Gcra { request_type, .. }
+            | ConfigSpec::FixedWindow { request_type, .. }
+            | ConfigSpec::SlidingCounter { request_type, .. } => request_type,
+        }
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deserialize() {
+        let json = r#"[
+            {"algorithm": "sliding_window", "request_type": "api", "capacity": 100, "window_time_millis": 60000},
+            {"algorithm": "gcra", "request_type": "login", "rate_per_sec": 0.5, "burst": 3}
+        ]"#;
+        let specs: Vec<ConfigSpec> = serde_json::from_str(json).unwrap();
+        assert_eq!(specs, vec![
+            ConfigSpec::SlidingWindow { request_type: "api".to_string(), capacity: 100, window_time_millis: 60000 },
+            ConfigSpec::Gcra { request_type: "login".to_string(), rate_per_sec: 0.5, burst: 3 },
+        ]);
+
+        let unknown = r#"[{"algorithm": "magic", "request_type": "api"}]"#;
+        assert!(serde_json::from_str::<Vec<ConfigSpec>>(unknown).is_err());
+    }
+}
@@ -0,0 +1,16 @@
+use std::time::Duration;
+
+/// The outcome of [`RateLimiter::check`](crate::RateLimiter::check), with everything needed to
+/// fill in the `X-RateLimit-*` response headers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Decision {
+    /// Whether the request was allowed, and recorded.
+    pub allowed: bool,
+    /// The maximum number of requests allowed in the window.
+    pub limit: u32,
+    /// How many more requests are allowed in the current window.
+    pub remaining: u32,
+    /// How long until the oldest request in the window expires and frees up capacity, or
+    /// zero if the window is empty.
+    pub reset_after: Duration,
+}
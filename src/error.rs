@@ -1,16 +1,148 @@
 use std::{error::Error, fmt};
+use std::time::Duration;
 
-#[derive(Debug, PartialEq)]
-pub(crate) enum RateLimiterError {
-    Message(String)
+/// Everything that can go wrong in a [`RateLimiter`](crate::RateLimiter) call.
+///
+/// New variants may be added, so matches need a wildcard arm.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum RateLimiterError {
+    /// A misuse of the API, e.g. an operation a config does not support.
+    Message(String),
+    /// The request type has no config, and no default config is set.
+    UnknownRequestType(String),
+    /// A thread panicked while holding a lock of a storage that cannot recover from it. The
+    /// built-in storages take over the poisoned state instead.
+    LockPoisoned,
+    /// Redis could not be reached or answered with an error.
+    #[cfg(feature = "redis")]
+    Redis(redis::RedisError),
+    /// The storage did not answer in time, e.g. within the timeout set with
+    /// [`RedisStorage::with_timeout`](crate::RedisStorage::with_timeout).
+    Timeout,
+    /// Any other storage backend failed, e.g. a custom [`Storage`](crate::Storage).
+    Storage(Box<dyn Error + Send + Sync>),
+    /// The request was denied by [`RateLimiter::consume`](crate::RateLimiter::consume).
+    LimitExceeded {
+        /// How long until a request would be allowed, if the limiter can tell.
+        retry_after: Option<Duration>,
+    },
 }
 
 impl fmt::Display for RateLimiterError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             RateLimiterError::Message(e) => write!(f, "{}", e),
+            RateLimiterError::UnknownRequestType(request_type) => write!(f, "unknown request type: {}", request_type),
+            RateLimiterError::LockPoisoned => write!(f, "unable to acquire lock"),
+            #[cfg(feature = "redis")]
+            RateLimiterError::Redis(e) => write!(f, "redis error: {}", e),
+            RateLimiterError::Timeout => write!(f, "storage timed out"),
+            RateLimiterError::Storage(e) => write!(f, "storage error: {}", e),
+            RateLimiterError::LimitExceeded { retry_after: Some(retry_after) } => write!(f, "rate limit exceeded, retry after {:?}", retry_after),
+            RateLimiterError::LimitExceeded { retry_after: None } => write!(f, "rate limit exceeded"),
         }
     }
 }
 
-impl Error for RateLimiterError {}
\ No newline at end of file
+impl Error for RateLimiterError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            #[cfg(feature = "redis")]
+            RateLimiterError::Redis(e) => Some(e),
+            RateLimiterError::Storage(e) => Some(&**e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "redis")]
+impl RateLimiterError {
+    /// Maps a pool that ran out of time handing out a connection to an error, keeping the
+    /// error it last ran into while connecting, e.g. a refused connection, if there was one.
+    fn from_pool_error(e: r2d2::Error) -> Self {
+        match e.to_string() {
+            // without a connection error, every connection was simply busy.
+            message if message == "timed out waiting for connection" => RateLimiterError::Timeout,
+            message => RateLimiterError::Storage(message.into()),
+        }
+    }
+}
+
+#[cfg(feature = "redis")]
+impl From<redis::RedisError> for RateLimiterError {
+    fn from(e: redis::RedisError) -> Self {
+        if e.is_timeout() {
+            return RateLimiterError::Timeout;
+        }
+        RateLimiterError::Redis(e)
+    }
+}
+
+impl From<Box<dyn Error>> for RateLimiterError {
+    /// Recovers the cause of an error returned by a [`Storage`](crate::Storage).
+    fn from(e: Box<dyn Error>) -> Self {
+        let e = match e.downcast::<RateLimiterError>() {
+            Ok(e) => return *e,
+            Err(e) => e,
+        };
+        #[cfg(feature = "redis")]
+        let e = match e.downcast::<redis::RedisError>() {
+            Ok(e) => return RateLimiterError::from(*e),
+            Err(e) => e,
+        };
+        #[cfg(feature = "redis")]
+        let e = match e.downcast::<r2d2::Error>() {
+            Ok(e) => return RateLimiterError::from_pool_error(*e),
+            Err(e) => e,
+        };
+        // storage errors need not be `Send`, so only their message is kept.
+        RateLimiterError::Storage(e.to_string().into())
+    }
+}
+
+impl From<Box<dyn Error + Send + Sync>> for RateLimiterError {
+    /// Recovers the cause of an error returned by an async storage method.
+    fn from(e: Box<dyn Error + Send + Sync>) -> Self {
+        let e = match e.downcast::<RateLimiterError>() {
+            Ok(e) => return *e,
+            Err(e) => e,
+        };
+        #[cfg(feature = "redis")]
+        let e = match e.downcast::<redis::RedisError>() {
+            Ok(e) => return RateLimiterError::from(*e),
+            Err(e) => e,
+        };
+        #[cfg(feature = "redis")]
+        let e = match e.downcast::<r2d2::Error>() {
+            Ok(e) => return RateLimiterError::from_pool_error(*e),
+            Err(e) => e,
+        };
+        RateLimiterError::Storage(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_storage_error() {
+        let e: Box<dyn Error> = Box::new(RateLimiterError::UnknownRequestType("type1".to_string()));
+        assert!(matches!(RateLimiterError::from(e), RateLimiterError::UnknownRequestType(request_type) if request_type == "type1"));
+
+        #[cfg(feature = "redis")]
+        {
+            let e: Box<dyn Error> = Box::new(redis::RedisError::from((redis::ErrorKind::IoError, "connection refused")));
+            assert!(matches!(RateLimiterError::from(e), RateLimiterError::Redis(_)));
+
+            let e: Box<dyn Error> = Box::new(redis::RedisError::from(std::io::Error::from(std::io::ErrorKind::TimedOut)));
+            assert!(matches!(RateLimiterError::from(e), RateLimiterError::Timeout));
+        }
+
+        let e: Box<dyn Error> = "disk full".into();
+        let e = RateLimiterError::from(e);
+        assert_eq!(e.to_string(), "storage error: disk full");
+        assert!(e.source().is_some());
+    }
+}
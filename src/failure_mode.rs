@@ -0,0 +1,12 @@
+/// What [`RateLimiter::allowed`](crate::RateLimiter::allowed) answers when the storage backend
+/// fails, e.g. while Redis is unreachable.
+///
+/// Set it with [`RateLimiter::with_failure_mode`](crate::RateLimiter::with_failure_mode).
+/// Without one, storage errors are returned to the caller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureMode {
+    /// Allow every request until the backend recovers, favoring availability.
+    Open,
+    /// Deny every request until the backend recovers, favoring protection of the service.
+    Closed,
+}
@@ -0,0 +1,66 @@
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+// How long dropping a handle waits for a sweep in progress before leaving the thread to finish
+// it on its own, so shutdown cannot hang on a slow storage.
+const JOIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Keeps the background cleaner of
+/// [`RateLimiter::start_gc`](crate::RateLimiter::start_gc) running, and stops it when dropped.
+///
+/// Dropping the handle wakes the thread right away and joins it, waiting at most a second for
+/// a sweep in progress. Call [`detach`](GcHandle::detach) to keep it running for as long as the
+/// limiter lives instead.
+pub struct GcHandle {
+    // sending on it tells the thread to stop, dropping it detaches the thread
+    stop: Sender<()>,
+    // disconnects when the thread has exited
+    done: Receiver<()>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl GcHandle {
+    /// Spawns a thread that calls `sweep` every `interval` until it returns `false` or the
+    /// handle is dropped.
+    pub(crate) fn spawn(interval: Duration, mut sweep: impl FnMut() -> bool + Send + 'static) -> Self {
+        let (stop, stop_requested) = mpsc::channel::<()>();
+        let (done_sender, done) = mpsc::channel::<()>();
+        let thread = thread::spawn(move || {
+            let _done = done_sender;
+            loop {
+                match stop_requested.recv_timeout(interval) {
+                    Ok(()) => return,
+                    Err(RecvTimeoutError::Timeout) => {},
+                    Err(RecvTimeoutError::Disconnected) => thread::sleep(interval),
+                }
+                if !sweep() {
+                    return;
+                }
+            }
+        });
+        Self { stop, done, thread: Some(thread) }
+    }
+
+    /// Returns `true` once the thread has exited, e.g. after the limiter was dropped.
+    pub fn is_finished(&self) -> bool {
+        self.thread.as_ref().is_none_or(JoinHandle::is_finished)
+    }
+
+    /// Lets the thread run on without the handle, until the last `Arc` of the limiter is dropped.
+    pub fn detach(mut self) {
+        self.thread.take();
+    }
+}
+
+impl Drop for GcHandle {
+    fn drop(&mut self) {
+        let Some(thread) = self.thread.take() else {
+            return;
+        };
+        let _ = self.stop.send(());
+        if let Err(RecvTimeoutError::Disconnected) = self.done.recv_timeout(JOIN_TIMEOUT) {
+            let _ = thread.join();
+        }
+    }
+}
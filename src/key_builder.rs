@@ -0,0 +1,98 @@
+use std::borrow::Cow;
+
+/// Builds the part of a storage key that identifies a user and request type, e.g. to hash
+/// user ids that are personal data before they reach Redis.
+///
+/// Register a builder with [`RateLimiter::with_key_builder`](crate::RateLimiter::with_key_builder).
+/// The key prefix, if any, is still prepended to what it builds. Closures taking the user id
+/// and request type implement it too.
+///
+/// User ids and request types arrive with every `%` and `:` percent-encoded, e.g. `a%3Ab` for
+/// `a:b`, so neither contains a `:` of its own. Tiers, tenants and penalties decorate user ids
+/// after that, e.g. `user1:60000ms`, so distinct users and decorations never reach a builder
+/// as the same input, and a builder must keep distinct inputs apart. Shared budgets take
+/// `budget:{budget_id}` as their request type, which no escaped request type can equal.
+/// [`purge_request_type`](crate::RateLimiter::purge_request_type) and
+/// [`clear`](crate::RateLimiter::clear) find keys by their `:{request_type}` suffix, so they
+/// only cover builders that keep it.
+///
+/// # Examples
+///
+/// ```
+/// use ratelimiter_rs::{KeyBuilder, RateLimiter};
+///
+/// struct TenantKeys;
+///
+/// impl KeyBuilder for TenantKeys {
+///     fn build(&self, user_id: &str, request_type: &str) -> String {
+///         match user_id.split_once('/') {
+///             Some((tenant, user)) => format!("{{{}}}:{}:{}", tenant, user, request_type),
+///             None => format!("{}:{}", user_id, request_type),
+///         }
+///     }
+/// }
+///
+/// let mut limiter = RateLimiter::with_in_memory().with_key_builder(TenantKeys);
+/// limiter.add_config("type1", 1, 60000);
+/// assert!(limiter.allowed("acme/user1", "type1").unwrap());
+/// ```
+pub trait KeyBuilder: Send + Sync {
+    /// Returns the storage key of `user_id` for `request_type`.
+    fn build(&self, user_id: &str, request_type: &str) -> String;
+}
+
+/// The key layout used unless another [`KeyBuilder`] is set: `user_id:request_type`.
+///
+/// As request types arrive escaped, the last `:` always starts the request type, so a user
+/// `a:b` with request type `c` gets the key `a%3Ab:c`, and user `a` with request type `b:c`
+/// gets `a:b%3Ac`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DefaultKeyBuilder;
+
+impl KeyBuilder for DefaultKeyBuilder {
+    fn build(&self, user_id: &str, request_type: &str) -> String {
+        format!("{}:{}", user_id, request_type)
+    }
+}
+
+impl<F> KeyBuilder for F
+where
+    F: Fn(&str, &str) -> String + Send + Sync,
+{
+    fn build(&self, user_id: &str, request_type: &str) -> String {
+        self(user_id, request_type)
+    }
+}
+
+/// Percent-encodes every `%` and `:` in a user-supplied part of a key, so it cannot be
+/// mistaken for a separator, nor end in one that a key is matched by.
+pub(crate) fn escape(part: &str) -> Cow<'_, str> {
+    if !part.contains(['%', ':']) {
+        return Cow::Borrowed(part);
+    }
+    let mut escaped = String::with_capacity(part.len() + 4);
+    for c in part.chars() {
+        match c {
+            '%' => escaped.push_str("%25"),
+            ':' => escaped.push_str("%3A"),
+            c => escaped.push(c),
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("user1"), "user1");
+        assert_eq!(escape("a:b"), "a%3Ab");
+        // an encoded `%` keeps an id that looks encoded from meeting the one it looks like.
+        assert_eq!(escape("a%3Ab"), "a%253Ab");
+        assert_ne!(DefaultKeyBuilder.build(&escape("a:b"), &escape("c")), DefaultKeyBuilder.build(&escape("a"), &escape("b:c")));
+        assert_ne!(DefaultKeyBuilder.build(&escape("a%3A"), &escape("b")), DefaultKeyBuilder.build(&escape("a:"), &escape("b")));
+        assert!(!DefaultKeyBuilder.build(&escape("a"), &escape("b:c")).ends_with(":c"));
+    }
+}
@@ -0,0 +1,178 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use http::{header, HeaderValue, Request, Response, StatusCode};
+use tower::{Layer, Service};
+
+use crate::RateLimiter;
+
+/// A [`tower::Layer`] that rate limits requests before they reach the inner service.
+///
+/// Requests are identified by a user-supplied closure and checked with [`RateLimiter::allowed`]
+/// against a single request type. Denied requests get a `429 Too Many Requests` response, with a
+/// `Retry-After` header (in whole seconds) when the limiter can tell how long to wait. If the
+/// limiter itself fails, the request gets a `500 Internal Server Error` response.
+///
+/// # Examples
+///
+/// ```
+/// use std::sync::Arc;
+/// use ratelimiter_rs::{RateLimitLayer, RateLimiter};
+///
+/// let mut limiter = RateLimiter::with_in_memory();
+/// limiter.add_config("api", 100, 60000);
+///
+/// let layer = RateLimitLayer::new(Arc::new(limiter), "api", |request: &http::Request<()>| {
+///     request.headers().get("x-api-key").and_then(|value| value.to_str().ok()).unwrap_or("anonymous").to_string()
+/// });
+/// ```
+pub struct RateLimitLayer<F> {
+    limiter: Arc<RateLimiter>,
+    request_type: Arc<str>,
+    user_id: Arc<F>,
+}
+
+impl<F> RateLimitLayer<F> {
+    /// Creates a layer that checks every request against `request_type`.
+    ///
+    /// # Arguments
+    ///
+    /// * `limiter` - The rate limiter, shared with the rest of the application.
+    /// * `request_type` - The configured request type to check requests against.
+    /// * `user_id` - Extracts the ID of the user making a request.
+    pub fn new(limiter: Arc<RateLimiter>, request_type: &str, user_id: F) -> Self {
+        Self {
+            limiter,
+            request_type: request_type.into(),
+            user_id: Arc::new(user_id),
+        }
+    }
+}
+
+impl<F> Clone for RateLimitLayer<F> {
+    fn clone(&self) -> Self {
+        Self {
+            limiter: self.limiter.clone(),
+            request_type: self.request_type.clone(),
+            user_id: self.user_id.clone(),
+        }
+    }
+}
+
+impl<S, F> Layer<S> for RateLimitLayer<F> {
+    type Service = RateLimit<S, F>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimit { inner, layer: self.clone() }
+    }
+}
+
+/// The service produced by [`RateLimitLayer`].
+pub struct RateLimit<S, F> {
+    inner: S,
+    layer: RateLimitLayer<F>,
+}
+
+impl<S: Clone, F> Clone for RateLimit<S, F> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            layer: self.layer.clone(),
+        }
+    }
+}
+
+impl<S, F, ReqBody, ResBody> Service<Request<ReqBody>> for RateLimit<S, F>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+    S::Future: Send + 'static,
+    F: Fn(&Request<ReqBody>) -> String,
+    ResBody: Default + Send + 'static,
+{
+    type Response = Response<ResBody>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<ReqBody>) -> Self::Future {
+        let user_id = (self.layer.user_id)(&request);
+        let limiter = &self.layer.limiter;
+        let request_type = &*self.layer.request_type;
+
+        let response = match limiter.allowed(&user_id, request_type) {
+            Ok(true) => return Box::pin(self.inner.call(request)),
+            Ok(false) => {
+                let mut response = empty_response(StatusCode::TOO_MANY_REQUESTS);
+                // the header is best effort: some algorithms cannot tell how long to wait.
+                if let Ok(Some(retry_after)) = limiter.retry_after(&user_id, request_type) {
+                    let seconds = retry_after.as_millis().div_ceil(1000);
+                    response.headers_mut().insert(header::RETRY_AFTER, HeaderValue::from(seconds as u64));
+                }
+                response
+            },
+            Err(_) => empty_response(StatusCode::INTERNAL_SERVER_ERROR),
+        };
+        Box::pin(async move { Ok(response) })
+    }
+}
+
+/// Builds a response with an empty body and the given status.
+fn empty_response<B: Default>(status: StatusCode) -> Response<B> {
+    let mut response = Response::new(B::default());
+    *response.status_mut() = status;
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MockClock;
+    use std::convert::Infallible;
+    use tower::{service_fn, ServiceExt};
+
+    fn user_header(request: &Request<()>) -> String {
+        request.headers().get("x-user").and_then(|value| value.to_str().ok()).unwrap_or_default().to_string()
+    }
+
+    fn request(user_id: &str) -> Request<()> {
+        Request::builder().header("x-user", user_id).body(()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_layer_denies_with_retry_after() {
+        let clock = MockClock::new(1_000_000);
+        let mut limiter = RateLimiter::with_in_memory_clock(clock.clone());
+        limiter.add_config("api", 1, 2500);
+
+        let layer = RateLimitLayer::new(Arc::new(limiter), "api", user_header);
+        let service = layer.layer(service_fn(|_: Request<()>| async { Ok::<_, Infallible>(Response::new(String::from("ok"))) }));
+
+        let response = service.clone().oneshot(request("user1")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.body(), "ok");
+
+        let response = service.clone().oneshot(request("user1")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "3");
+        assert!(response.body().is_empty());
+
+        // other users have their own budget.
+        let response = service.oneshot(request("user2")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_layer_unknown_request_type() {
+        let limiter = RateLimiter::with_in_memory();
+        let layer = RateLimitLayer::new(Arc::new(limiter), "api", user_header);
+        let service = layer.layer(service_fn(|_: Request<()>| async { Ok::<_, Infallible>(Response::new(String::new())) }));
+
+        let response = service.oneshot(request("user1")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+}
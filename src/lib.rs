@@ -1,5 +1,84 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
+mod builder;
+#[cfg(feature = "std")]
+mod clock;
+#[cfg(feature = "std")]
+mod config_spec;
+#[cfg(feature = "std")]
+mod decision;
+#[cfg(feature = "std")]
 mod error;
+#[cfg(feature = "std")]
+mod failure_mode;
+#[cfg(feature = "std")]
+mod gc;
+#[cfg(feature = "std")]
+mod key_builder;
+#[cfg(feature = "std")]
+mod metrics;
+#[cfg(feature = "std")]
+mod quota;
+#[cfg(feature = "std")]
+mod reservation;
+#[cfg(feature = "std")]
+mod schedule;
+#[cfg(feature = "std")]
+mod stats;
+#[cfg(feature = "std")]
 mod storage;
+#[cfg(feature = "std")]
 mod rate_limiter;
+pub mod sliding_window;
+#[cfg(feature = "std")]
+mod threshold;
+#[cfg(feature = "std")]
+mod window_kind;
+#[cfg(feature = "tower")]
+mod layer;
+#[cfg(feature = "prometheus")]
+mod prometheus_metrics;
 
-pub use rate_limiter::RateLimiter;
\ No newline at end of file
+#[cfg(feature = "std")]
+pub use builder::RateLimiterBuilder;
+#[cfg(feature = "std")]
+pub use clock::{Clock, MockClock, MonotonicClock, SystemClock};
+#[cfg(feature = "std")]
+pub use config_spec::ConfigSpec;
+#[cfg(feature = "std")]
+pub use decision::Decision;
+#[cfg(feature = "std")]
+pub use error::RateLimiterError;
+#[cfg(feature = "std")]
+pub use failure_mode::FailureMode;
+#[cfg(feature = "std")]
+pub use gc::GcHandle;
+#[cfg(feature = "std")]
+pub use key_builder::{DefaultKeyBuilder, KeyBuilder};
+#[cfg(feature = "std")]
+pub use metrics::MetricsSink;
+#[cfg(feature = "std")]
+pub use quota::Quota;
+#[cfg(feature = "std")]
+pub use reservation::Reservation;
+#[cfg(feature = "std")]
+pub use schedule::Schedule;
+#[cfg(feature = "std")]
+pub use stats::LimiterStats;
+#[cfg(feature = "std")]
+pub use rate_limiter::RateLimiter;
+#[cfg(feature = "std")]
+pub use window_kind::WindowKind;
+#[cfg(feature = "tower")]
+pub use layer::{RateLimit, RateLimitLayer};
+#[cfg(feature = "std")]
+pub use storage::{CachedStorage, EvictionPolicy, InMemoryStorage, Storage, WindowEviction};
+#[cfg(feature = "redis")]
+pub use storage::RedisStorage;
+#[cfg(feature = "sled")]
+pub use storage::SledStorage;
+#[cfg(feature = "async")]
+pub use storage::{AsyncStorage, StorageFuture};
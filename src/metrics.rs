@@ -0,0 +1,47 @@
+use std::error::Error;
+
+/// Receives the outcome of every rate limiting decision, e.g. to feed Prometheus counters.
+///
+/// Register a sink with [`RateLimiter::with_metrics`](crate::RateLimiter::with_metrics).
+/// Requests that fail with an error are not reported, unless a
+/// [`FailureMode`](crate::FailureMode) turns a storage error into a decision. In
+/// [shadow mode](crate::RateLimiter::with_shadow_mode), the sink sees the decisions that would
+/// have been enforced.
+///
+/// # Examples
+///
+/// ```
+/// use std::sync::atomic::{AtomicU64, Ordering};
+/// use ratelimiter_rs::MetricsSink;
+///
+/// #[derive(Default)]
+/// struct Counters {
+///     allowed: AtomicU64,
+///     denied: AtomicU64,
+/// }
+///
+/// impl MetricsSink for Counters {
+///     fn on_allowed(&self, _request_type: &str) {
+///         self.allowed.fetch_add(1, Ordering::Relaxed);
+///     }
+///
+///     fn on_denied(&self, _request_type: &str) {
+///         self.denied.fetch_add(1, Ordering::Relaxed);
+///     }
+/// }
+/// ```
+pub trait MetricsSink: Send + Sync {
+    /// Called when a request of `request_type` is allowed.
+    fn on_allowed(&self, request_type: &str);
+
+    /// Called when a request of `request_type` is denied.
+    fn on_denied(&self, request_type: &str);
+
+    /// Called when the storage fails on a request of `request_type` and a
+    /// [`FailureMode`](crate::FailureMode) decides it instead; the decision is reported as well.
+    ///
+    /// The default implementation ignores the error.
+    fn on_storage_error(&self, request_type: &str, error: &dyn Error) {
+        let _ = (request_type, error);
+    }
+}
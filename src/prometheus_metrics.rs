@@ -0,0 +1,62 @@
+use std::sync::Arc;
+
+use prometheus::core::{Collector, Desc};
+use prometheus::proto::MetricFamily;
+use prometheus::{IntCounterVec, IntGauge, Opts, Registry};
+
+use crate::metrics::MetricsSink;
+use crate::storage::Storage;
+
+/// Counts decisions as `ratelimiter_requests_total{type, decision}`.
+pub(crate) struct PrometheusSink {
+    requests: IntCounterVec,
+}
+
+impl MetricsSink for PrometheusSink {
+    fn on_allowed(&self, request_type: &str) {
+        self.requests.with_label_values(&[request_type, "allowed"]).inc();
+    }
+
+    fn on_denied(&self, request_type: &str) {
+        self.requests.with_label_values(&[request_type, "denied"]).inc();
+    }
+}
+
+/// Reports `ratelimiter_tracked_users`, read from the storage whenever the registry is gathered.
+struct TrackedUsers {
+    storage: Arc<dyn Storage>,
+    gauge: IntGauge,
+}
+
+impl Collector for TrackedUsers {
+    fn desc(&self) -> Vec<&Desc> {
+        self.gauge.desc()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        // storages that cannot tell, like Redis, leave the gauge out rather than report zero.
+        match self.storage.usage() {
+            Ok((tracked_users, _)) => {
+                self.gauge.set(i64::try_from(tracked_users).unwrap_or(i64::MAX));
+                self.gauge.collect()
+            },
+            Err(_) => Vec::new(),
+        }
+    }
+}
+
+/// Registers the limiter's metrics with `registry`, returning the sink that updates them.
+pub(crate) fn register(registry: &Registry, storage: Arc<dyn Storage>) -> prometheus::Result<PrometheusSink> {
+    let requests = IntCounterVec::new(
+        Opts::new("ratelimiter_requests_total", "Rate limiting decisions by request type and decision."),
+        &["type", "decision"],
+    )?;
+    let gauge = IntGauge::new("ratelimiter_tracked_users", "Keys the rate limiter storage holds state for.")?;
+
+    registry.register(Box::new(requests.clone()))?;
+    if let Err(e) = registry.register(Box::new(TrackedUsers { storage, gauge })) {
+        registry.unregister(Box::new(requests))?;
+        return Err(e);
+    }
+    Ok(PrometheusSink { requests })
+}
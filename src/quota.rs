@@ -0,0 +1,90 @@
+use std::num::NonZeroU32;
+use std::time::Duration;
+
+use crate::error::RateLimiterError;
+
+/// A sliding window limit whose capacity and window are checked when it is built, for
+/// [`RateLimiter::add_config_quota`](crate::RateLimiter::add_config_quota).
+///
+/// A zero capacity, which would deny every request, cannot be expressed, and windows are at
+/// least a millisecond long.
+///
+/// # Examples
+///
+/// ```
+/// use ratelimiter_rs::Quota;
+/// use std::num::NonZeroU32;
+/// use std::time::Duration;
+///
+/// let quota = Quota::per_minute(NonZeroU32::new(100).unwrap());
+/// assert_eq!(quota.window(), Duration::from_secs(60));
+///
+/// assert!(Quota::try_from((0, Duration::from_secs(1))).is_err());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quota {
+    capacity: NonZeroU32,
+    window: Duration,
+}
+
+impl Quota {
+    /// Allows `capacity` requests per second.
+    pub const fn per_second(capacity: NonZeroU32) -> Self {
+        Self { capacity, window: Duration::from_secs(1) }
+    }
+
+    /// Allows `capacity` requests per minute.
+    pub const fn per_minute(capacity: NonZeroU32) -> Self {
+        Self { capacity, window: Duration::from_secs(60) }
+    }
+
+    /// Allows `capacity` requests per hour.
+    pub const fn per_hour(capacity: NonZeroU32) -> Self {
+        Self { capacity, window: Duration::from_secs(3600) }
+    }
+
+    /// Allows `capacity` requests per `window`, or returns `None` if the window is shorter
+    /// than a millisecond.
+    pub fn with_period(capacity: NonZeroU32, window: Duration) -> Option<Self> {
+        (window >= Duration::from_millis(1)).then_some(Self { capacity, window })
+    }
+
+    /// Returns the maximum number of requests allowed in the window.
+    pub fn capacity(&self) -> NonZeroU32 {
+        self.capacity
+    }
+
+    /// Returns the length of the sliding window.
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+}
+
+impl TryFrom<(u32, Duration)> for Quota {
+    type Error = RateLimiterError;
+
+    /// Checks a `(capacity, window)` pair, e.g. read from a config file.
+    fn try_from((capacity, window): (u32, Duration)) -> Result<Self, Self::Error> {
+        let capacity = NonZeroU32::new(capacity).ok_or_else(|| RateLimiterError::Message("quota capacity must not be zero".to_string()))?;
+        Self::with_period(capacity, window).ok_or_else(|| RateLimiterError::Message("quota window must be at least a millisecond".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rejects_zero() {
+        assert!(matches!(
+            Quota::try_from((0, Duration::from_secs(1))),
+            Err(RateLimiterError::Message(e)) if e == "quota capacity must not be zero"
+        ));
+        assert!(Quota::try_from((5, Duration::ZERO)).is_err());
+        assert!(Quota::with_period(NonZeroU32::MIN, Duration::from_micros(999)).is_none());
+
+        let quota = Quota::try_from((5, Duration::from_millis(1500))).unwrap();
+        assert_eq!((quota.capacity().get(), quota.window()), (5, Duration::from_millis(1500)));
+        assert_eq!(Quota::per_hour(NonZeroU32::MIN).window(), Duration::from_secs(3600));
+    }
+}
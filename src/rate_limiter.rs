@@ -1,186 +1,5718 @@
 // Rate limiter using sliding window technique
 
-use std::{collections::HashMap, error::Error};
-use std::sync::Mutex;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::{collections::HashMap, error::Error, fmt};
+use std::hash::{BuildHasher, DefaultHasher, Hash, Hasher};
+use std::sync::{Arc, PoisonError, RwLock};
+use std::time::{Duration, Instant};
+use crate::builder::RateLimiterBuilder;
+use crate::clock::{Clock, FixedClock, SystemClock};
+use crate::config_spec::ConfigSpec;
+use crate::decision::Decision;
 use crate::error::RateLimiterError;
-use crate::storage::Storage;
-use std::sync::Arc;
+use crate::failure_mode::FailureMode;
+use crate::gc::GcHandle;
+use crate::key_builder::{self, DefaultKeyBuilder, KeyBuilder};
+use crate::metrics::MetricsSink;
+use crate::quota::Quota;
+use crate::reservation::Reservation;
+use crate::schedule::{self, Schedule};
+use crate::stats::LimiterStats;
+use crate::threshold::Threshold;
+use crate::storage::{InMemoryStorage, Storage};
+#[cfg(feature = "redis")]
+use crate::storage::{CachedStorage, RedisStorage};
+use crate::window_kind::WindowKind;
+#[cfg(feature = "async")]
+use crate::storage::AsyncOnlyStorage;
+
+/// Clones share the storage, clock and metrics sink of the original, so they enforce the same
+/// limits on the same state, but each clone owns a copy of the configs to change independently.
+///
+/// # Thread safety
+///
+/// `RateLimiter` is `Send + Sync`: storage, clocks and metrics sinks are all required to be,
+/// and checks only take `&self`, so a limiter can live in a `static` or behind an `Arc` and be
+/// used from any number of threads. Config changes take `&mut self`, so they need exclusive
+/// access, e.g. a `RwLock` or setting everything up before sharing it.
+///
+/// # Typed request types
+///
+/// Request types are stored as strings, but every method takes anything that implements
+/// `AsRef<str>`, so an enum of request types catches typos at compile time, and an exhaustive
+/// `match` in its `as_ref` makes sure every variant has a name:
+///
+/// ```
+/// use ratelimiter_rs::RateLimiter;
+///
+/// enum RequestType {
+///     Login,
+///     Search,
+/// }
+///
+/// impl AsRef<str> for RequestType {
+///     fn as_ref(&self) -> &str {
+///         match self {
+///             RequestType::Login => "login",
+///             RequestType::Search => "search",
+///         }
+///     }
+/// }
+///
+/// let mut limiter = RateLimiter::with_in_memory();
+/// limiter.add_config(RequestType::Login, 5, 60000).add_config(RequestType::Search, 100, 60000);
+/// assert!(limiter.allowed("user1", RequestType::Login).unwrap());
+/// ```
+#[derive(Clone)]
 pub struct RateLimiter {
+    table: SharedTable,
+    // request type -> user id -> config
+    overrides: HashMap<String, HashMap<String, Config>>,
+    storage: Arc<dyn Storage>,
+    clock: Arc<dyn Clock>,
+    // when the limiter was created by its clock, in milliseconds; warm-ups ramp up from here
+    created_at: u64,
+    key_prefix: Option<String>,
+    key_builder: Arc<dyn KeyBuilder>,
+    metrics: Option<Arc<dyn MetricsSink>>,
+    // applies to request types that have no config of their own
+    default_config: Option<Config>,
+    enabled: bool,
+    // decides requests when the storage fails, instead of returning the error
+    failure_mode: Option<FailureMode>,
+    // the most a sliding window is lengthened per key, spreading out expirations
+    eviction_jitter_millis: u64,
+    // request type -> how long its warm-up ramps up for
+    warmups: HashMap<String, Duration>,
+    // request type -> aggregate limit of each tenant, on top of the limits of its users
+    tenant_configs: HashMap<String, Config>,
+    // reports decisions to the metrics sink, but allows every request
+    shadow_mode: bool,
+    // request type -> (denials in a window that trigger a block, length of the block)
+    penalties: HashMap<String, (u32, Duration)>,
+    // request type -> schedule, and the config of each of its slots
+    schedules: HashMap<String, (Schedule, Vec<Config>)>,
+    // budget id -> the sliding window every user has to share across its request types
+    budgets: HashMap<String, Config>,
+    // request type -> utilization callbacks of its users
+    thresholds: HashMap<String, Vec<Threshold>>,
+}
+
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<RateLimiter>();
+};
+
+// how long `allowed_blocking` waits between checks when the limiter cannot tell when a request would be
+// allowed, and `allowed_wait_async` waits between every two checks.
+const BLOCKING_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The configs added with `add_config*` and the shared budgets their request types draw
+/// from, which [`RateLimiter::replace_configs`] swaps as one.
+#[derive(Debug, Clone, Default)]
+struct ConfigTable {
     configs: HashMap<String, Config>,
-    storage: Storage,
+    // request type -> id of the budget it draws from
+    budget_members: HashMap<String, String>,
+}
+
+/// A [`ConfigTable`] that can be replaced through a shared limiter.
+///
+/// Decisions work on a snapshot taken once with [`load`](SharedTable::load), so a concurrent
+/// replace never shows them a part of both tables. Clones of a limiter start out with the same
+/// snapshot and copy it on their first change, so they never see each other's configs.
+#[derive(Default)]
+struct SharedTable(RwLock<Arc<ConfigTable>>);
+
+impl SharedTable {
+    fn load(&self) -> Arc<ConfigTable> {
+        // a panic while holding the lock cannot leave a half-written table, as writes swap it whole.
+        Arc::clone(&self.0.read().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    fn get_mut(&mut self) -> &mut ConfigTable {
+        Arc::make_mut(self.0.get_mut().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    /// Swaps in the table `f` builds from the current one, holding the lock throughout so
+    /// concurrent updates do not overwrite each other.
+    fn update(&self, f: impl FnOnce(&ConfigTable) -> ConfigTable) {
+        let mut table = self.0.write().unwrap_or_else(PoisonError::into_inner);
+        *table = Arc::new(f(&table));
+    }
+}
+
+impl Clone for SharedTable {
+    fn clone(&self) -> Self {
+        Self(RwLock::new(self.load()))
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct Config {
     capacity: u32,
-    window_time: Duration,
+    algorithm: Algorithm,
+    resolution: Resolution,
+    // further sliding windows that must all have room as well, as (capacity, window) pairs
+    tiers: Vec<(u32, Duration)>,
+}
+
+/// The unit of the timestamps a config records in storage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Resolution {
+    Millis,
+    Micros,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Algorithm {
+    SlidingWindow { window_time: Duration },
+    TokenBucket { refill_per_sec: f64 },
+    FixedWindow { window_time: Duration },
+    SlidingCounter { window_time: Duration },
+    LeakyBucket { leak_per_sec: f64 },
+    Gcra { rate_per_sec: f64 },
+}
+
+impl Config {
+    /// Returns a millisecond config without tiers.
+    fn new(capacity: u32, algorithm: Algorithm) -> Self {
+        Self { capacity, algorithm, resolution: Resolution::Millis, tiers: Vec::new() }
+    }
+
+    /// Returns the request type a spec configures and the config that the `add_config*`
+    /// method it stands for adds.
+    fn from_spec(spec: ConfigSpec) -> (String, Self) {
+        match spec {
+            ConfigSpec::SlidingWindow { request_type, capacity, window_time_millis } => (request_type, Self::new(capacity, Algorithm::SlidingWindow { window_time: Duration::from_millis(window_time_millis) })),
+            ConfigSpec::TokenBucket { request_type, capacity, refill_per_sec } => (request_type, Self::new(capacity, Algorithm::TokenBucket { refill_per_sec })),
+            ConfigSpec::LeakyBucket { request_type, rate_per_sec, bucket_size } => (request_type, Self::new(bucket_size, Algorithm::LeakyBucket { leak_per_sec: rate_per_sec })),
+            ConfigSpec::Gcra { request_type, rate_per_sec, burst } => {
+                assert!(rate_per_sec > 0.0, "a GCRA config needs a positive rate");
+                (request_type, Self::new(burst, Algorithm::Gcra { rate_per_sec }))
+            },
+            ConfigSpec::FixedWindow { request_type, capacity, window_time_millis } => (request_type, Self::new(capacity, Algorithm::FixedWindow { window_time: Duration::from_millis(window_time_millis) })),
+            ConfigSpec::SlidingCounter { request_type, capacity, window_time_millis } => (request_type, Self::new(capacity, Algorithm::SlidingCounter { window_time: Duration::from_millis(window_time_millis) })),
+        }
+    }
+
+    /// Returns the time it takes the algorithm to fully replenish `capacity`: the window for
+    /// windowed algorithms, and the time to refill or drain the whole bucket for buckets.
+    fn period(&self) -> Duration {
+        match self.algorithm {
+            Algorithm::SlidingWindow { window_time }
+            | Algorithm::FixedWindow { window_time }
+            | Algorithm::SlidingCounter { window_time } => window_time,
+            Algorithm::TokenBucket { refill_per_sec: rate_per_sec }
+            | Algorithm::LeakyBucket { leak_per_sec: rate_per_sec }
+            | Algorithm::Gcra { rate_per_sec } => {
+                // a bucket that never refills never replenishes its capacity.
+                Duration::try_from_secs_f64(self.capacity as f64 / rate_per_sec).unwrap_or(Duration::MAX)
+            },
+        }
+    }
+
+    /// Returns whether this config has a window of zero, which means the request type is not
+    /// limited at all.
+    fn is_unlimited(&self) -> bool {
+        match self.algorithm {
+            Algorithm::SlidingWindow { window_time }
+            | Algorithm::FixedWindow { window_time }
+            | Algorithm::SlidingCounter { window_time } => window_time.is_zero(),
+            _ => false,
+        }
+    }
+
+    /// Returns the longest time any state of this config matters for, across all its tiers.
+    fn longest_period(&self) -> Duration {
+        let period = match self.algorithm {
+            // the count of the previous window still weighs in during the current one.
+            Algorithm::SlidingCounter { window_time } => window_time.saturating_mul(2),
+            _ => self.period(),
+        };
+        self.tiers.iter().map(|&(_, window_time)| window_time).fold(period, Duration::max)
+    }
+
+    /// Returns the current time in the unit this config records timestamps in.
+    fn now(&self, clock: &dyn Clock) -> u64 {
+        match self.resolution {
+            Resolution::Millis => clock.now_millis(),
+            Resolution::Micros => clock.now_micros(),
+        }
+    }
+
+    /// Scales `window` so that storage, which reads durations as milliseconds, measures it in
+    /// the unit of this config's timestamps.
+    fn storage_window(&self, window: Duration) -> Duration {
+        match self.resolution {
+            Resolution::Millis => window,
+            Resolution::Micros => Duration::from_millis(window.as_micros() as u64),
+        }
+    }
+
+    /// Converts a span of `units` of this config's timestamps into a duration.
+    fn duration(&self, units: u64) -> Duration {
+        match self.resolution {
+            Resolution::Millis => Duration::from_millis(units),
+            Resolution::Micros => Duration::from_micros(units),
+        }
+    }
+
+    /// Returns the sliding window length as passed to storage, or an error naming `operation`
+    /// for other algorithms.
+    fn window_time(&self, operation: &str) -> Result<Duration, RateLimiterError> {
+        match self.algorithm {
+            Algorithm::SlidingWindow { window_time } => Ok(self.storage_window(window_time)),
+            _ => Err(RateLimiterError::Message(format!("{} is only supported for sliding window configs", operation))),
+        }
+    }
 }
 
 impl RateLimiter {
     /// Creates a new rate limiter with in-memory storage.
     pub fn with_in_memory() -> Self {
+        Self::with_storage(InMemoryStorage::new())
+    }
+
+    /// Creates a new rate limiter with in-memory storage pre-sized for about `keys` keys,
+    /// avoiding rehashes while a known number of users ramps up.
+    ///
+    /// # Arguments
+    ///
+    /// * `keys` - The expected number of keys, i.e. users times request types.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::RateLimiter;
+    ///
+    /// let mut limiter = RateLimiter::with_in_memory_capacity(100_000);
+    /// limiter.add_config("type1", 5, 60000);
+    /// assert!(limiter.allowed("user1", "type1").unwrap());
+    /// ```
+    pub fn with_in_memory_capacity(keys: usize) -> Self {
+        Self::with_storage(InMemoryStorage::with_capacity(keys))
+    }
+
+    /// Creates a new rate limiter with in-memory storage bounded to about `max_users` keys,
+    /// evicting the state of the least recently used one to make room for a new one.
+    ///
+    /// An evicted user starts over with a fresh quota, so the bound should comfortably exceed
+    /// the number of users active within a window. See [`InMemoryStorage::with_max_keys`] for
+    /// how the bound is applied.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_users` - The most keys to hold, i.e. users times request types.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_users` is 0.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::RateLimiter;
+    ///
+    /// let mut limiter = RateLimiter::with_in_memory_lru(100_000);
+    /// limiter.add_config("type1", 5, 60000);
+    /// assert!(limiter.allowed("user1", "type1").unwrap());
+    /// ```
+    pub fn with_in_memory_lru(max_users: usize) -> Self {
+        Self::with_storage(InMemoryStorage::new().with_max_keys(max_users))
+    }
+
+    /// Creates a new rate limiter with in-memory storage that hashes keys with `hasher`, e.g.
+    /// a faster one than the default for trusted user ids.
+    ///
+    /// See [`InMemoryStorage::with_hasher`] for when a faster hasher is safe to use.
+    ///
+    /// # Arguments
+    ///
+    /// * `hasher` - Builds the hasher for the in-memory maps.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::RateLimiter;
+    /// use std::hash::{BuildHasherDefault, DefaultHasher};
+    ///
+    /// let mut limiter = RateLimiter::with_in_memory_hasher(BuildHasherDefault::<DefaultHasher>::default());
+    /// limiter.add_config("type1", 5, 60000);
+    /// assert!(limiter.allowed("user1", "type1").unwrap());
+    /// ```
+    pub fn with_in_memory_hasher<S: BuildHasher + Clone + Send + Sync + 'static>(hasher: S) -> Self {
+        Self::with_storage(InMemoryStorage::with_hasher(hasher))
+    }
+
+    /// Creates a new rate limiter with in-memory storage that reads the time from `clock`.
+    ///
+    /// # Arguments
+    ///
+    /// * `clock` - The clock used to timestamp requests.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::{MockClock, RateLimiter};
+    /// use std::time::Duration;
+    ///
+    /// let clock = MockClock::new(0);
+    /// let mut limiter = RateLimiter::with_in_memory_clock(clock.clone());
+    /// limiter.add_config("type1", 1, 1000);
+    ///
+    /// assert!(limiter.allowed("user1", "type1").unwrap());
+    /// assert!(!limiter.allowed("user1", "type1").unwrap());
+    ///
+    /// clock.advance(Duration::from_millis(1001));
+    /// assert!(limiter.allowed("user1", "type1").unwrap());
+    /// ```
+    pub fn with_in_memory_clock(clock: impl Clock + 'static) -> Self {
         Self {
-            configs: HashMap::new(),
-            storage: Storage::InMemory(Arc::new(Mutex::new(HashMap::new()))),
+            created_at: clock.now_millis(),
+            clock: Arc::new(clock),
+            ..Self::with_in_memory()
         }
     }
+
     /// Creates a new rate limiter with Redis storage.
     ///
     /// # Arguments
     ///
     /// * `redis_url` - The URL of the Redis server.
-    pub fn with_redis(redis_url: &str) -> Self {
-        let client = redis::Client::open(redis_url).unwrap();
-        Self {
-            configs: HashMap::new(),
-            storage: Storage::Redis(client),
-        }
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `redis_url` is not a valid Redis URL. No connection is made here.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::RateLimiter;
+    ///
+    /// let limiter = RateLimiter::with_redis("redis://127.0.0.1/").unwrap();
+    /// ```
+    #[cfg(feature = "redis")]
+    pub fn with_redis(redis_url: &str) -> Result<Self, RateLimiterError> {
+        Ok(Self::from_redis_client(redis::Client::open(redis_url)?))
     }
 
-    /// Adds a configuration for a request type.
+    /// Creates a new rate limiter with Redis storage on top of an existing client, so timeouts
+    /// and auth can be configured in one place for the whole app.
     ///
     /// # Arguments
     ///
-    /// * `request_type` - The type of request to configure.
-    /// * `capacity` - The maximum number of requests allowed in the window time.
-    /// * `window_time_millis` - The length of the sliding window in milliseconds.
+    /// * `client` - The Redis client to open pooled connections from.
     ///
     /// # Examples
     ///
     /// ```
     /// use ratelimiter_rs::RateLimiter;
-    /// 
-    /// let mut limiter = RateLimiter::with_in_memory();
-    /// limiter.add_config("type1", 5, 60000);
+    ///
+    /// let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    /// let limiter = RateLimiter::from_redis_client(client);
     /// ```
-    pub fn add_config(&mut self, request_type: &str, capacity: u32, window_time_millis: u64) -> &mut Self {
-        self.configs.insert(
-            request_type.to_string(),
-            Config {
-                capacity,
-                window_time: Duration::from_millis(window_time_millis),
-            },
-        );
-        self
+    #[cfg(feature = "redis")]
+    pub fn from_redis_client(client: redis::Client) -> Self {
+        Self::with_storage(RedisStorage::new(client))
     }
 
-    /// Checks if a request is allowed.
+    /// Creates a new rate limiter with Redis storage configured by `connection_info`, for
+    /// setting auth, the database index, or TLS without building a URL.
+    ///
+    /// TLS connections, whether configured here or with a `rediss://` URL, need the `tls` feature.
     ///
     /// # Arguments
     ///
-    /// * `user_id` - The ID of the user making the request.
-    /// * `request_type` - The type of request.
+    /// * `connection_info` - The address and connection settings of the Redis server.
     ///
-    /// # Returns
+    /// # Errors
     ///
-    /// * `Ok(true)` if the request is allowed.
-    /// * `Ok(false)` if the request is not allowed.
-    /// * `Err` if an error occurs.
+    /// Returns an error if the settings are invalid, e.g. TLS without the `tls` feature. No
+    /// connection is made here.
     ///
     /// # Examples
     ///
     /// ```
     /// use ratelimiter_rs::RateLimiter;
-    /// 
-    /// let mut limiter = RateLimiter::with_in_memory();
-    /// let is_allowed = limiter.allowed("user1", "type1").unwrap();
+    /// use redis::{ConnectionAddr, ConnectionInfo, RedisConnectionInfo};
+    ///
+    /// let connection_info = ConnectionInfo {
+    ///     addr: ConnectionAddr::Tcp("127.0.0.1".to_string(), 6379),
+    ///     redis: RedisConnectionInfo { db: 2, username: None, password: Some("secret".to_string()) },
+    /// };
+    /// let limiter = RateLimiter::with_redis_config(connection_info).unwrap();
     /// ```
-    pub fn allowed(&self, user_id: &str, request_type: &str) -> Result<bool, Box<dyn Error>> {
-        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as u64;
-        let config = match self.configs.get(request_type) {
-            Some(config) => config,
-            None => return Ok(false),
-        };
+    #[cfg(feature = "redis")]
+    pub fn with_redis_config(connection_info: redis::ConnectionInfo) -> Result<Self, RateLimiterError> {
+        Ok(Self::from_redis_client(redis::Client::open(connection_info)?))
+    }
 
-        let start_time_in_millis = now - config.window_time.as_millis() as u64;
-        let end_time_in_millis = now;
-        let eviction_time_in_millis = now - config.window_time.as_millis() as u64;
-
-        match &self.storage {
-            Storage::InMemory(storage) => {
-                let mut storage = storage.lock().map_err(|_| RateLimiterError::Message("unable to acquire lock".to_string()))?;
-                let user_request_logs = storage.entry(user_id.to_string()).or_insert_with(Vec::new);
-
-                // evict expired entries by retaining timestamp greater than the eviction time.
-                user_request_logs.retain(|&timestamp| timestamp >= eviction_time_in_millis);
-                
-                // count number of requests in the last window
-                let request_count = user_request_logs.iter().filter(|&&timestamp| timestamp <= end_time_in_millis).count();
-
-                if request_count < config.capacity as usize {
-                    user_request_logs.push(now);
-                    Ok(true)
-                } else {
-                    Ok(false)
-                }
-            },
-            Storage::Redis(client) => {
-                let mut con = client.get_connection()?;
-                // Reference: https://engineering.grab.com/frequency-capping
-                let script = redis::Script::new(r"
-                    local user_redis_key = KEYS[1]
-                    local limit_value = tonumber(ARGV[1])
-                    local start_time_in_millis = tonumber(ARGV[2])
-                    local end_time_in_millis = tonumber(ARGV[3])
-                    local current_time_in_millis = tonumber(ARGV[4])
-                    local eviction_time_in_millis = tonumber(ARGV[5])
-
-                    local request_count = redis.call('ZCOUNT', user_redis_key, start_time_in_millis, end_time_in_millis)
-
-                    if tonumber(request_count) < limit_value then
-                        redis.call('ZADD', user_redis_key, current_time_in_millis, current_time_in_millis)
-                        redis.call('ZREMRANGEBYSCORE', user_redis_key, '-inf', eviction_time_in_millis)
-                        return 1
-                    else
-                        return 0
-                    end
-                ");
-
-                let key = format!("{}:{}", user_id, request_type);
-                let result: i32 = script.arg(config.capacity)
-                                        .arg(start_time_in_millis)
-                                        .arg(end_time_in_millis)
-                                        .arg(now)
-                                        .arg(eviction_time_in_millis)
-                                        .key(key)
-                                        .invoke(&mut con)?;
-                if result == 1 {
-                    Ok(true)
-                } else {
-                    Ok(false)
-                }
-            },
-        }
+    /// Creates a new rate limiter with Redis storage that reuses up to `max_size` connections.
+    ///
+    /// [`with_redis`](RateLimiter::with_redis) uses a pool of
+    /// [`RedisStorage::DEFAULT_POOL_SIZE`] connections.
+    ///
+    /// # Arguments
+    ///
+    /// * `redis_url` - The URL of the Redis server.
+    /// * `max_size` - The maximum number of pooled connections.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_size` is 0.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::RateLimiter;
+    ///
+    /// let limiter = RateLimiter::with_redis_pool("redis://127.0.0.1/", 32).unwrap();
+    /// ```
+    #[cfg(feature = "redis")]
+    pub fn with_redis_pool(redis_url: &str, max_size: u32) -> Result<Self, RateLimiterError> {
+        let client = redis::Client::open(redis_url)?;
+        Ok(Self::with_storage(RedisStorage::with_pool_size(client, max_size)))
     }
 
-}
+    /// Creates a new rate limiter with Redis storage behind a local pre-check, which allows
+    /// requests of users far below their sliding window limit without a round trip.
+    ///
+    /// A count read from Redis is trusted for `ttl`, during which each app server may allow up
+    /// to half the capacity locally before syncing. This trades exactness for throughput; see
+    /// [`CachedStorage`] for the details.
+    ///
+    /// # Arguments
+    ///
+    /// * `redis_url` - The URL of the Redis server.
+    /// * `ttl` - How long a count read from Redis is trusted.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::RateLimiter;
+    /// use std::time::Duration;
+    ///
+    /// let limiter = RateLimiter::with_redis_local_cache("redis://127.0.0.1/", Duration::from_millis(500)).unwrap();
+    /// ```
+    #[cfg(feature = "redis")]
+    pub fn with_redis_local_cache(redis_url: &str, ttl: Duration) -> Result<Self, RateLimiterError> {
+        let client = redis::Client::open(redis_url)?;
+        Ok(Self::with_storage(CachedStorage::new(RedisStorage::new(client), ttl)))
+    }
 
+    /// Creates a new rate limiter with Redis storage that counts sliding window requests in
+    /// `bucket_millis` long buckets, so a log holds one hash field per bucket instead of one
+    /// sorted set member per request.
+    ///
+    /// This saves a lot of memory for high capacities, but requests free up capacity up to one
+    /// bucket late; see [`RedisStorage::with_buckets`] for the details.
+    ///
+    /// # Arguments
+    ///
+    /// * `redis_url` - The URL of the Redis server.
+    /// * `bucket_millis` - The length of each bucket in milliseconds, e.g. a hundredth of the window.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bucket_millis` is 0.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::RateLimiter;
+    ///
+    /// let mut limiter = RateLimiter::with_redis_bucketed("redis://127.0.0.1/", 1000).unwrap();
+    /// limiter.add_config("type1", 100_000, 3_600_000);
+    /// ```
+    #[cfg(feature = "redis")]
+    pub fn with_redis_bucketed(redis_url: &str, bucket_millis: u64) -> Result<Self, RateLimiterError> {
+        let client = redis::Client::open(redis_url)?;
+        Ok(Self::with_storage(RedisStorage::new(client).with_buckets(bucket_millis)))
+    }
 
-#[cfg(test)]
-mod tests {
-    use std::{thread, time::Duration};
-    use super::*;
+    /// Creates a new rate limiter with Redis storage whose calls fail with
+    /// [`RateLimiterError::Timeout`] once they take longer than `timeout`, instead of blocking
+    /// until Redis answers.
+    ///
+    /// # Arguments
+    ///
+    /// * `redis_url` - The URL of the Redis server.
+    /// * `timeout` - How long connecting, waiting for a pooled connection, and each read and
+    ///   write may take.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use ratelimiter_rs::{FailureMode, RateLimiter};
+    /// use std::time::Duration;
+    ///
+    /// // a slow Redis lets requests through instead of hanging the handler.
+    /// let mut limiter = RateLimiter::with_redis_timeout("redis://127.0.0.1/", Duration::from_millis(50))
+    ///     .unwrap()
+    ///     .with_failure_mode(FailureMode::Open);
+    /// limiter.add_config("type1", 5, 60000);
+    /// ```
+    #[cfg(feature = "redis")]
+    pub fn with_redis_timeout(redis_url: &str, timeout: Duration) -> Result<Self, RateLimiterError> {
+        let client = redis::Client::open(redis_url)?;
+        Ok(Self::with_storage(RedisStorage::new(client).with_timeout(timeout)))
+    }
 
-    #[test]
-    fn test_with_in_memory(){
-        let mut limiter = RateLimiter::with_in_memory();
-        limiter.add_config("type1", 2, 5000).add_config("type2", 10, 30000);
+    /// Creates a new rate limiter with Redis storage that timestamps requests with the clock of
+    /// the Redis server, so app servers with skewed clocks still agree on every window.
+    ///
+    /// The local clock is still used by the helpers that compare stored timestamps with the
+    /// current time, like [`retry_after`](RateLimiter::retry_after), so those are only as
+    /// accurate as the skew between the app server and Redis.
+    ///
+    /// # Arguments
+    ///
+    /// * `redis_url` - The URL of the Redis server.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::RateLimiter;
+    ///
+    /// let limiter = RateLimiter::with_redis_server_time("redis://127.0.0.1/").unwrap();
+    /// ```
+    #[cfg(feature = "redis")]
+    pub fn with_redis_server_time(redis_url: &str) -> Result<Self, RateLimiterError> {
+        let client = redis::Client::open(redis_url)?;
+        Ok(Self::with_storage(RedisStorage::new(client).with_server_time(true)))
+    }
 
-        let user_id = "user12345";
-        for _ in 0..2 {
-            let is_allowed = limiter.allowed(user_id, "type1").unwrap();
-            thread::sleep(Duration::from_millis(1000));
-            assert!(is_allowed);
-        }
+    /// Creates a new rate limiter backed by a Redis Cluster.
+    ///
+    /// A request to a single sliding window, token bucket or other single-key config touches one
+    /// key, so its script runs within one hash slot. Tiered configs, shared budgets,
+    /// [`allowed_all`](RateLimiter::allowed_all) and
+    /// [`allowed_hierarchical`](RateLimiter::allowed_hierarchical) check several keys in one
+    /// script, and return an error here when those keys fall in different hash slots.
+    ///
+    /// # Arguments
+    ///
+    /// * `nodes` - The URLs of one or more cluster nodes; the rest of the cluster is discovered from them.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `nodes` is empty or contains an invalid Redis URL. No connection is made here.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::RateLimiter;
+    ///
+    /// let limiter = RateLimiter::with_redis_cluster(&["redis://127.0.0.1:7000/", "redis://127.0.0.1:7001/"]).unwrap();
+    /// ```
+    #[cfg(feature = "cluster")]
+    pub fn with_redis_cluster(nodes: &[&str]) -> Result<Self, RateLimiterError> {
+        let client = redis::cluster::ClusterClient::new(nodes.to_vec())?;
+        Ok(Self::with_storage(RedisStorage::cluster(client)))
+    }
 
-        // Previous request was two and maximum capacity is 2 within 2s with 5s window. Below we ensure that request 
-        // are no longer allowed because 5s overlaps and there is no enough capacity to handle new requests.
-        for _ in 0..2 {
-            let is_allowed = limiter.allowed(user_id, "type1").unwrap();
-            assert_eq!(is_allowed, false);
-            thread::sleep(Duration::from_millis(1000));
-        }
+    /// Creates a new rate limiter whose state is persisted in a sled database at `path`.
+    ///
+    /// State that expired while the database was closed is evicted on open.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The directory of the database, created if missing.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database cannot be opened.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use ratelimiter_rs::RateLimiter;
+    ///
+    /// let mut limiter = RateLimiter::with_sled("/var/lib/myapp/ratelimits").unwrap();
+    /// limiter.add_config("type1", 5, 60000);
+    /// ```
+    #[cfg(feature = "sled")]
+    pub fn with_sled(path: impl AsRef<std::path::Path>) -> Result<Self, RateLimiterError> {
+        Ok(Self::with_storage(crate::storage::SledStorage::open(path)?))
+    }
+
+    /// Creates a new rate limiter with Redis storage whose keys are namespaced by `prefix`.
+    ///
+    /// # Arguments
+    ///
+    /// * `redis_url` - The URL of the Redis server.
+    /// * `prefix` - The namespace prepended to every key, e.g. `myapp` for `myapp:user1:type1`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::RateLimiter;
+    ///
+    /// let limiter = RateLimiter::with_redis_prefix("redis://127.0.0.1/", "myapp").unwrap();
+    /// ```
+    #[cfg(feature = "redis")]
+    pub fn with_redis_prefix(redis_url: &str, prefix: &str) -> Result<Self, RateLimiterError> {
+        let mut limiter = Self::with_redis(redis_url)?;
+        limiter.set_key_prefix(prefix);
+        Ok(limiter)
+    }
+
+    /// Creates a new rate limiter on top of a custom storage backend.
+    ///
+    /// # Arguments
+    ///
+    /// * `storage` - The backend that keeps the request logs.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::{InMemoryStorage, RateLimiter};
+    ///
+    /// let limiter = RateLimiter::with_storage(InMemoryStorage::new());
+    /// ```
+    pub fn with_storage(storage: impl Storage + 'static) -> Self {
+        Self::with_boxed_storage(Box::new(storage))
+    }
+
+    /// Creates a new rate limiter on top of an async-only storage backend.
+    ///
+    /// Only [`allowed_async`](RateLimiter::allowed_async) of sliding window configs works with
+    /// such a limiter; every synchronous method returns an error from the storage, and so does
+    /// `allowed_async` for request types with several tiers, a penalty, a shared budget or a
+    /// threshold, which are decided synchronously.
+    ///
+    /// # Arguments
+    ///
+    /// * `storage` - The backend that keeps the request logs.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::{InMemoryStorage, RateLimiter};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let mut limiter = RateLimiter::with_async_storage(InMemoryStorage::new());
+    /// limiter.add_config("type1", 5, 60000);
+    /// assert!(limiter.allowed_async("user1", "type1").await.unwrap());
+    /// # }
+    /// ```
+    #[cfg(feature = "async")]
+    pub fn with_async_storage(storage: impl crate::storage::AsyncStorage + 'static) -> Self {
+        Self::with_storage(AsyncOnlyStorage(storage))
+    }
+
+    /// Creates a new rate limiter on top of an already boxed storage backend.
+    pub(crate) fn with_boxed_storage(storage: Box<dyn Storage>) -> Self {
+        Self {
+            table: SharedTable::default(),
+            overrides: HashMap::new(),
+            storage: Arc::from(storage),
+            clock: Arc::new(SystemClock),
+            created_at: SystemClock.now_millis(),
+            key_prefix: None,
+            key_builder: Arc::new(DefaultKeyBuilder),
+            metrics: None,
+            default_config: None,
+            enabled: true,
+            failure_mode: None,
+            eviction_jitter_millis: 0,
+            warmups: HashMap::new(),
+            tenant_configs: HashMap::new(),
+            penalties: HashMap::new(),
+            thresholds: HashMap::new(),
+            schedules: HashMap::new(),
+            budgets: HashMap::new(),
+            shadow_mode: false,
+        }
+    }
+
+    /// Creates a rate limiter that allows every request, for tests and environments where
+    /// rate limiting is switched off.
+    ///
+    /// Configs can still be added, and take effect once the limiter is turned on with
+    /// [`set_enabled`](RateLimiter::set_enabled).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::RateLimiter;
+    ///
+    /// let limiter = RateLimiter::disabled();
+    /// assert!(limiter.allowed("user1", "unconfigured").unwrap());
+    /// ```
+    pub fn disabled() -> Self {
+        Self {
+            enabled: false,
+            ..Self::with_in_memory()
+        }
+    }
+
+    /// Creates a rate limiter with in-memory storage and the given configs, e.g. as loaded
+    /// from a config file. Use [`add_configs`](RateLimiter::add_configs) for other storages.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::{ConfigSpec, RateLimiter};
+    ///
+    /// let limiter = RateLimiter::from_configs(vec![
+    ///     ConfigSpec::SlidingWindow { request_type: "type1".to_string(), capacity: 5, window_time_millis: 60000 },
+    /// ]);
+    /// assert!(limiter.allowed("user1", "type1").unwrap());
+    /// ```
+    pub fn from_configs(specs: Vec<ConfigSpec>) -> Self {
+        let mut limiter = Self::with_in_memory();
+        limiter.add_configs(specs);
+        limiter
+    }
+
+    /// Returns a builder that sets up storage and configs in a single expression.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::RateLimiter;
+    ///
+    /// let limiter = RateLimiter::builder().in_memory().config("type1", 5, 60000).build().unwrap();
+    /// ```
+    pub fn builder() -> RateLimiterBuilder {
+        RateLimiterBuilder::new()
+    }
+
+    /// Reports every allowed and denied request to `sink`.
+    ///
+    /// # Arguments
+    ///
+    /// * `sink` - The metrics sink to notify.
+    pub fn with_metrics(mut self, sink: impl MetricsSink + 'static) -> Self {
+        self.metrics = Some(Arc::new(sink));
+        self
+    }
+
+    /// Registers Prometheus metrics with `registry` and keeps them up to date, replacing any
+    /// sink set with [`with_metrics`](RateLimiter::with_metrics).
+    ///
+    /// Decisions are counted as `ratelimiter_requests_total{type, decision}`, where `decision`
+    /// is `allowed` or `denied`. Whenever the registry is gathered, `ratelimiter_tracked_users`
+    /// reports how many keys the storage holds state for; storages that cannot tell, like
+    /// Redis, leave it out.
+    ///
+    /// # Arguments
+    ///
+    /// * `registry` - The registry to add the metrics to.
+    ///
+    /// # Errors
+    ///
+    /// Fails if `registry` already has metrics of the same names.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::RateLimiter;
+    ///
+    /// let registry = prometheus::Registry::new();
+    /// let mut limiter = RateLimiter::with_in_memory();
+    /// limiter.register_metrics(&registry).unwrap();
+    /// limiter.add_config("type1", 5, 60000);
+    /// limiter.allowed("user1", "type1").unwrap();
+    ///
+    /// let names: Vec<_> = registry.gather().iter().map(|family| family.get_name().to_string()).collect();
+    /// assert_eq!(names, vec!["ratelimiter_requests_total", "ratelimiter_tracked_users"]);
+    /// ```
+    #[cfg(feature = "prometheus")]
+    pub fn register_metrics(&mut self, registry: &prometheus::Registry) -> prometheus::Result<()> {
+        let sink = crate::prometheus_metrics::register(registry, Arc::clone(&self.storage))?;
+        self.metrics = Some(Arc::new(sink));
+        Ok(())
+    }
+
+    /// Builds storage keys with `builder` instead of the default `user_id:request_type`.
+    ///
+    /// Switching builders on a live deployment starts every user over, since their state
+    /// stays under the old keys until it expires.
+    ///
+    /// # Arguments
+    ///
+    /// * `builder` - The key builder, or a closure taking the user id and request type.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::RateLimiter;
+    ///
+    /// let mut limiter = RateLimiter::with_in_memory()
+    ///     .with_key_builder(|user_id: &str, request_type: &str| format!("{}:{}", request_type, user_id.to_lowercase()));
+    /// limiter.add_config("type1", 1, 60000);
+    ///
+    /// assert!(limiter.allowed("Alice", "type1").unwrap());
+    /// assert!(!limiter.allowed("alice", "type1").unwrap());
+    /// ```
+    pub fn with_key_builder(mut self, builder: impl KeyBuilder + 'static) -> Self {
+        self.key_builder = Arc::new(builder);
+        self
+    }
+
+    /// Decides requests by `mode` when the storage fails, instead of returning the error.
+    ///
+    /// This covers [`allowed`](RateLimiter::allowed), [`allowed_n`](RateLimiter::allowed_n),
+    /// [`allowed_all`](RateLimiter::allowed_all), [`allowed_many`](RateLimiter::allowed_many)
+    /// and `allowed_async`. The error is passed to
+    /// [`MetricsSink::on_storage_error`](crate::MetricsSink::on_storage_error). Unknown request
+    /// types are still errors, as are failures of queries like [`remaining`](RateLimiter::remaining).
+    ///
+    /// # Arguments
+    ///
+    /// * `mode` - Whether to allow or deny requests while the storage fails.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use ratelimiter_rs::{FailureMode, RateLimiter};
+    ///
+    /// # #[cfg(feature = "redis")] {
+    /// let mut limiter = RateLimiter::with_redis("redis://127.0.0.1:1/").unwrap().with_failure_mode(FailureMode::Open);
+    /// limiter.add_config("type1", 5, 60000);
+    /// // nothing listens on port 1, so the request is allowed without a limit.
+    /// assert!(limiter.allowed("user1", "type1").unwrap());
+    /// # }
+    /// ```
+    pub fn with_failure_mode(mut self, mode: FailureMode) -> Self {
+        self.failure_mode = Some(mode);
+        self
+    }
+
+    /// Turns on shadow mode, in which every request is allowed but still counted and decided as
+    /// usual, e.g. to tune a new limit against production traffic before enforcing it.
+    ///
+    /// What the verdict would have been is reported to the [`MetricsSink`], which sees a would-be
+    /// denial as a denial. Requests that would have been denied are not recorded, exactly as
+    /// when enforcing, so turning shadow mode off enforces the limit the metrics showed.
+    /// Queries such as [`peek`](RateLimiter::peek) and [`remaining`](RateLimiter::remaining)
+    /// keep reporting the real state.
+    ///
+    /// # Arguments
+    ///
+    /// * `shadow_mode` - Whether to allow every request instead of enforcing the decisions.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::RateLimiter;
+    ///
+    /// let mut limiter = RateLimiter::with_in_memory().with_shadow_mode(true);
+    /// limiter.add_config("type1", 1, 60000);
+    ///
+    /// assert!(limiter.allowed("user1", "type1").unwrap());
+    /// // denied as far as the metrics and the window are concerned, but let through.
+    /// assert!(limiter.allowed("user1", "type1").unwrap());
+    /// assert!(!limiter.peek("user1", "type1").unwrap());
+    /// ```
+    pub fn with_shadow_mode(mut self, shadow_mode: bool) -> Self {
+        self.shadow_mode = shadow_mode;
+        self
+    }
+
+    /// Lengthens each key's sliding window by a stable offset of up to `max_jitter_millis`, so
+    /// requests recorded in the same burst by many users do not all expire at once.
+    ///
+    /// The offset is derived from the storage key, so it is the same on every app server and
+    /// for every request of a key. Windows only ever get longer, so no user is allowed more
+    /// than their configured capacity in the configured window.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_jitter_millis` - The largest offset added to a window, in milliseconds.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::RateLimiter;
+    ///
+    /// let mut limiter = RateLimiter::with_in_memory().with_eviction_jitter(500);
+    /// limiter.add_config("type1", 5, 60000);
+    /// assert!(limiter.allowed("user1", "type1").unwrap());
+    /// ```
+    pub fn with_eviction_jitter(mut self, max_jitter_millis: u64) -> Self {
+        self.eviction_jitter_millis = max_jitter_millis;
+        self
+    }
+
+    /// Ramps the capacity of a request type up over `ramp_duration`, so a freshly started
+    /// process is not flooded while its caches are cold.
+    ///
+    /// The capacity starts at a tenth of the configured one, at least 1, and grows linearly
+    /// to the full capacity over `ramp_duration` from when the limiter was created. It applies
+    /// to every algorithm, to user overrides and to the default config.
+    ///
+    /// # Arguments
+    ///
+    /// * `request_type` - The type of request to warm up.
+    /// * `ramp_duration` - How long it takes to reach the full capacity.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::RateLimiter;
+    /// use std::time::Duration;
+    ///
+    /// let mut limiter = RateLimiter::with_in_memory().with_warmup("type1", Duration::from_secs(60));
+    /// limiter.add_config("type1", 10, 60000);
+    ///
+    /// // just after startup, only a tenth of the capacity is allowed.
+    /// assert!(limiter.allowed("user1", "type1").unwrap());
+    /// assert!(!limiter.allowed("user1", "type1").unwrap());
+    /// ```
+    pub fn with_warmup(mut self, request_type: impl AsRef<str>, ramp_duration: Duration) -> Self {
+        let request_type = request_type.as_ref();
+        self.warmups.insert(request_type.to_string(), ramp_duration);
+        self
+    }
+
+    /// Blocks users of a request type for `block_duration` once they are denied
+    /// `violations_threshold` times within the config's window, whatever their quota says.
+    ///
+    /// Denials are counted, and blocks kept, in the storage next to the user's other state, so
+    /// every limiter sharing the storage enforces them. Blocked requests are denied without
+    /// touching the user's quota and do not count as further violations. It applies to
+    /// [`allowed`](RateLimiter::allowed), [`allowed_n`](RateLimiter::allowed_n) and
+    /// `allowed_async`, and [`reset`](RateLimiter::reset) lifts a block.
+    ///
+    /// # Arguments
+    ///
+    /// * `request_type` - The type of request to penalize violations of.
+    /// * `violations_threshold` - How many denials within the window trigger a block.
+    /// * `block_duration` - How long a block lasts.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `violations_threshold` is zero.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::{MockClock, RateLimiter};
+    /// use std::time::Duration;
+    ///
+    /// let clock = MockClock::new(0);
+    /// let mut limiter = RateLimiter::with_in_memory_clock(clock.clone()).with_penalty("login", 2, Duration::from_secs(600));
+    /// limiter.add_config("login", 1, 1000);
+    ///
+    /// assert!(limiter.allowed("user1", "login").unwrap());
+    /// assert!(!limiter.allowed("user1", "login").unwrap());
+    /// assert!(!limiter.allowed("user1", "login").unwrap());
+    ///
+    /// // the window is over, but the second denial blocked the user for ten minutes.
+    /// clock.advance(Duration::from_secs(1));
+    /// assert!(!limiter.allowed("user1", "login").unwrap());
+    /// ```
+    pub fn with_penalty(mut self, request_type: impl AsRef<str>, violations_threshold: u32, block_duration: Duration) -> Self {
+        assert!(violations_threshold > 0, "a penalty needs a violations threshold of at least 1");
+        self.penalties.insert(request_type.as_ref().to_string(), (violations_threshold, block_duration));
+        self
+    }
+
+    /// Calls `callback` with the user id and their utilization, the share of the capacity in
+    /// use, when a user's requests of a request type reach `fraction` of it, e.g. to warn heavy
+    /// users before they are denied.
+    ///
+    /// The callback fires on the request that reaches the threshold, and then at most once
+    /// per window and user, however many requests stay above it. Only single sliding window
+    /// configs report their utilization, through [`allowed`](RateLimiter::allowed),
+    /// [`allowed_n`](RateLimiter::allowed_n) and their variants; others never fire. Which
+    /// users have been notified is kept in memory, so every limiter sharing a Redis storage
+    /// notifies them on its own. Several thresholds may be set for the same request type.
+    ///
+    /// # Arguments
+    ///
+    /// * `request_type` - The type of request to watch.
+    /// * `fraction` - The share of the capacity that triggers the callback.
+    /// * `callback` - Called with the user id and their utilization.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `fraction` is not more than 0 and at most 1.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::RateLimiter;
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// let warned = Arc::new(Mutex::new(Vec::new()));
+    /// let mut limiter = RateLimiter::with_in_memory();
+    /// let sink = Arc::clone(&warned);
+    /// limiter.add_config("type1", 5, 60000).on_threshold("type1", 0.8, move |user_id, utilization| {
+    ///     sink.lock().unwrap().push((user_id.to_string(), utilization));
+    /// });
+    ///
+    /// for _ in 0..5 {
+    ///     limiter.allowed("user1", "type1").unwrap();
+    /// }
+    /// // the fourth request reached 80%, the fifth did not fire again.
+    /// assert_eq!(*warned.lock().unwrap(), vec![("user1".to_string(), 0.8)]);
+    /// ```
+    pub fn on_threshold(&mut self, request_type: impl AsRef<str>, fraction: f64, callback: impl Fn(&str, f64) + Send + Sync + 'static) -> &mut Self {
+        assert!(fraction > 0.0 && fraction <= 1.0, "a threshold must be a fraction of the capacity");
+        self.thresholds.entry(request_type.as_ref().to_string()).or_default().push(Threshold::new(fraction, callback));
+        self
+    }
+
+    /// Turns rate limiting on or off.
+    ///
+    /// While disabled, [`allowed`](RateLimiter::allowed), [`allowed_n`](RateLimiter::allowed_n),
+    /// [`allowed_all`](RateLimiter::allowed_all) and `allowed_async` return `Ok(true)` for every
+    /// request, even of unconfigured request types, without touching storage or metrics.
+    ///
+    /// # Arguments
+    ///
+    /// * `enabled` - Whether requests are checked against their configs.
+    pub fn set_enabled(&mut self, enabled: bool) -> &mut Self {
+        self.enabled = enabled;
+        self
+    }
+
+    /// Sets the namespace prepended to every storage key, so several apps can share one Redis.
+    ///
+    /// # Arguments
+    ///
+    /// * `prefix` - The namespace, e.g. `myapp` for `myapp:user1:type1`.
+    pub fn set_key_prefix(&mut self, prefix: &str) -> &mut Self {
+        self.key_prefix = Some(prefix.to_string());
+        self
+    }
+
+    /// Adds a configuration for a request type, replacing any previous config for it.
+    ///
+    /// A capacity of zero denies every request, and a window of zero allows every request, so
+    /// a control plane can block or unlimit a request type without removing its config. If
+    /// both are zero, requests are denied.
+    ///
+    /// # Arguments
+    ///
+    /// * `request_type` - The type of request to configure.
+    /// * `capacity` - The maximum number of requests allowed in the window time.
+    /// * `window_time_millis` - The length of the sliding window in milliseconds.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::RateLimiter;
+    /// 
+    /// let mut limiter = RateLimiter::with_in_memory();
+    /// limiter.add_config("type1", 5, 60000);
+    /// ```
+    pub fn add_config(&mut self, request_type: impl AsRef<str>, capacity: u32, window_time_millis: u64) -> &mut Self {
+        let request_type = request_type.as_ref();
+        self.table.get_mut().configs.insert(request_type.to_string(), Config::new(capacity, Algorithm::SlidingWindow { window_time: Duration::from_millis(window_time_millis) }));
+        self
+    }
+
+    /// Adds a configuration that allows `count` requests per `per`, replacing any previous
+    /// config for the request type.
+    ///
+    /// The same as [`add_config`](RateLimiter::add_config) with a window of `per`, so a count
+    /// of zero denies every request and a window of zero allows every request. Windows are
+    /// enforced to the millisecond, so `per` is rounded up to the next whole one, e.g. 500µs
+    /// to 1ms and 1.5ms to 2ms.
+    ///
+    /// # Arguments
+    ///
+    /// * `request_type` - The type of request to configure.
+    /// * `count` - The maximum number of requests allowed per window.
+    /// * `per` - The length of the sliding window.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::RateLimiter;
+    /// use std::time::Duration;
+    ///
+    /// let mut limiter = RateLimiter::with_in_memory();
+    /// // 100 requests per 5 minutes.
+    /// limiter.add_config_per("type1", 100, Duration::from_secs(300));
+    /// assert_eq!(limiter.get_config("type1"), Some((100, Duration::from_secs(300))));
+    /// assert!(limiter.allowed("user1", "type1").unwrap());
+    /// ```
+    pub fn add_config_per(&mut self, request_type: impl AsRef<str>, count: u32, per: Duration) -> &mut Self {
+        let window_time_millis = u64::try_from(per.as_nanos().div_ceil(1_000_000)).unwrap_or(u64::MAX);
+        self.add_config(request_type, count, window_time_millis)
+    }
+
+    /// Adds a sliding window configuration from a [`Quota`], whose capacity cannot be zero,
+    /// replacing any previous config for the request type.
+    ///
+    /// # Arguments
+    ///
+    /// * `request_type` - The type of request to configure.
+    /// * `quota` - The capacity and window to enforce.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::{Quota, RateLimiter};
+    /// use std::num::NonZeroU32;
+    ///
+    /// let mut limiter = RateLimiter::with_in_memory();
+    /// limiter.add_config_quota("type1", Quota::per_minute(NonZeroU32::new(5).unwrap()));
+    /// assert!(limiter.allowed("user1", "type1").unwrap());
+    /// ```
+    pub fn add_config_quota(&mut self, request_type: impl AsRef<str>, quota: impl Into<Quota>) -> &mut Self {
+        let request_type = request_type.as_ref();
+        let quota = quota.into();
+        self.table.get_mut().configs.insert(request_type.to_string(), Config::new(quota.capacity().get(), Algorithm::SlidingWindow { window_time: quota.window() }));
+        self
+    }
+
+    /// Adds a sliding window configuration whose window is measured in microseconds, replacing
+    /// any previous config for the request type.
+    ///
+    /// Requests are timestamped in microseconds, for windows of a few milliseconds where
+    /// millisecond timestamps are too coarse. [`evict_idle`](RateLimiter::evict_idle) leaves
+    /// the state of these configs alone, and the Redis server clock of
+    /// [`with_redis_server_time`](RateLimiter::with_redis_server_time) only counts milliseconds,
+    /// so use the local clock with them.
+    ///
+    /// # Arguments
+    ///
+    /// * `request_type` - The type of request to configure.
+    /// * `capacity` - The maximum number of requests allowed in the window time.
+    /// * `window_time_micros` - The length of the sliding window in microseconds.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::RateLimiter;
+    ///
+    /// let mut limiter = RateLimiter::with_in_memory();
+    /// // at most 100 fan-out calls every 2.5 milliseconds
+    /// limiter.add_config_micros("fan_out", 100, 2500);
+    /// assert!(limiter.allowed("user1", "fan_out").unwrap());
+    /// ```
+    pub fn add_config_micros(&mut self, request_type: impl AsRef<str>, capacity: u32, window_time_micros: u64) -> &mut Self {
+        let request_type = request_type.as_ref();
+        self.table.get_mut().configs.insert(
+            request_type.to_string(),
+            Config {
+                capacity,
+                algorithm: Algorithm::SlidingWindow { window_time: Duration::from_micros(window_time_micros) },
+                resolution: Resolution::Micros,
+                tiers: Vec::new(),
+            },
+        );
+        self
+    }
+
+    /// Adds a configuration that enforces several sliding windows at once, e.g. 10 per second
+    /// and 100 per minute, replacing any previous config for the request type.
+    ///
+    /// A request is only allowed if every tier has room for it, and is then recorded in all of
+    /// them; a denial records nothing. The first tier shares its state with a plain
+    /// [`add_config`](RateLimiter::add_config) of the same request type. Checks that cost
+    /// more than one request are rejected, `allowed_async` checks tiers synchronously, and
+    /// [`check`](RateLimiter::check) is not supported. [`get_config`](RateLimiter::get_config)
+    /// reports the first tier.
+    ///
+    /// # Arguments
+    ///
+    /// * `request_type` - The type of request to configure.
+    /// * `tiers` - The `(capacity, window_time_millis)` of every window to enforce.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `tiers` is empty.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::RateLimiter;
+    ///
+    /// let mut limiter = RateLimiter::with_in_memory();
+    /// // 2 per second, and 3 per minute
+    /// limiter.add_config_tiers("api", &[(2, 1000), (3, 60000)]);
+    ///
+    /// assert!(limiter.allowed("user1", "api").unwrap());
+    /// assert!(limiter.allowed("user1", "api").unwrap());
+    /// assert!(!limiter.allowed("user1", "api").unwrap());
+    /// ```
+    pub fn add_config_tiers(&mut self, request_type: impl AsRef<str>, tiers: &[(u32, u64)]) -> &mut Self {
+        let request_type = request_type.as_ref();
+        assert!(!tiers.is_empty(), "a tiered config needs at least one tier");
+        let (capacity, window_time_millis) = tiers[0];
+        self.table.get_mut().configs.insert(
+            request_type.to_string(),
+            Config {
+                tiers: tiers[1..].iter().map(|&(capacity, window_time_millis)| (capacity, Duration::from_millis(window_time_millis))).collect(),
+                ..Config::new(capacity, Algorithm::SlidingWindow { window_time: Duration::from_millis(window_time_millis) })
+            },
+        );
+        self
+    }
+
+    /// Sets the sliding window config used for request types that have no config of their own.
+    ///
+    /// Each request type governed by the default still gets its own window per user. Configs
+    /// added with [`add_config`](RateLimiter::add_config) and friends take precedence.
+    ///
+    /// # Arguments
+    ///
+    /// * `capacity` - The maximum number of requests allowed in the window time.
+    /// * `window_time_millis` - The length of the sliding window in milliseconds.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::RateLimiter;
+    ///
+    /// let mut limiter = RateLimiter::with_in_memory();
+    /// limiter.set_default_config(1, 60000).add_config("uploads", 2, 60000);
+    ///
+    /// assert!(limiter.allowed("user1", "search").unwrap());
+    /// assert!(!limiter.allowed("user1", "search").unwrap());
+    /// assert!(limiter.allowed("user1", "uploads").unwrap());
+    /// assert!(limiter.allowed("user1", "uploads").unwrap());
+    /// ```
+    pub fn set_default_config(&mut self, capacity: u32, window_time_millis: u64) -> &mut Self {
+        self.default_config = Some(Config::new(capacity, Algorithm::SlidingWindow { window_time: Duration::from_millis(window_time_millis) }));
+        self
+    }
+
+    /// Removes the default config, so unconfigured request types are rejected again.
+    ///
+    /// # Returns
+    ///
+    /// `true` if a default config was removed, `false` if none was set.
+    pub fn clear_default_config(&mut self) -> bool {
+        self.default_config.take().is_some()
+    }
+
+    /// Adds a token bucket configuration for a request type.
+    ///
+    /// Instead of a log of timestamps, each user only keeps the number of tokens left and the
+    /// time of the last refill, which keeps memory constant for large capacities. The bucket
+    /// starts full, refills continuously and every allowed request takes one token.
+    ///
+    /// # Arguments
+    ///
+    /// * `request_type` - The type of request to configure.
+    /// * `capacity` - The maximum number of tokens the bucket holds, i.e. the allowed burst.
+    /// * `refill_per_sec` - The number of tokens added back per second.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::RateLimiter;
+    /// 
+    /// let mut limiter = RateLimiter::with_in_memory();
+    /// limiter.add_config_token_bucket("type1", 1000, 100.0);
+    /// ```
+    pub fn add_config_token_bucket(&mut self, request_type: impl AsRef<str>, capacity: u32, refill_per_sec: f64) -> &mut Self {
+        let request_type = request_type.as_ref();
+        self.table.get_mut().configs.insert(request_type.to_string(), Config::new(capacity, Algorithm::TokenBucket { refill_per_sec }));
+        self
+    }
+
+    /// Adds a leaky bucket configuration for a request type.
+    ///
+    /// Each user's bucket drains at a steady `rate_per_sec` and every request adds to it,
+    /// so requests are admitted while there is room. Unlike a sliding window, this smooths
+    /// traffic instead of allowing a burst at each window edge.
+    ///
+    /// # Arguments
+    ///
+    /// * `request_type` - The type of request to configure.
+    /// * `rate_per_sec` - How many requests drain from the bucket per second.
+    /// * `bucket_size` - The maximum number of requests the bucket holds.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::RateLimiter;
+    ///
+    /// let mut limiter = RateLimiter::with_in_memory();
+    /// limiter.add_config_leaky_bucket("type1", 10.0, 20);
+    /// ```
+    pub fn add_config_leaky_bucket(&mut self, request_type: impl AsRef<str>, rate_per_sec: f64, bucket_size: u32) -> &mut Self {
+        let request_type = request_type.as_ref();
+        self.table.get_mut().configs.insert(request_type.to_string(), Config::new(bucket_size, Algorithm::LeakyBucket { leak_per_sec: rate_per_sec }));
+        self
+    }
+
+    /// Adds a GCRA (generic cell rate algorithm) configuration for a request type.
+    ///
+    /// Requests are spaced `1 / rate_per_sec` seconds apart on average, with up to `burst` of
+    /// them allowed at once. Only a single timestamp (the theoretical arrival time) is stored
+    /// per user, which makes this much cheaper than a sliding window at high rates.
+    ///
+    /// # Arguments
+    ///
+    /// * `request_type` - The type of request to configure.
+    /// * `rate_per_sec` - The sustained number of requests allowed per second.
+    /// * `burst` - The maximum number of requests allowed at once.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `rate_per_sec` is not positive.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::RateLimiter;
+    ///
+    /// let mut limiter = RateLimiter::with_in_memory();
+    /// limiter.add_config_gcra("type1", 10.0, 5);
+    /// ```
+    pub fn add_config_gcra(&mut self, request_type: impl AsRef<str>, rate_per_sec: f64, burst: u32) -> &mut Self {
+        let request_type = request_type.as_ref();
+        assert!(rate_per_sec > 0.0, "a GCRA config needs a positive rate");
+        self.table.get_mut().configs.insert(request_type.to_string(), Config::new(burst, Algorithm::Gcra { rate_per_sec }));
+        self
+    }
+
+    /// Adds a fixed window configuration for a request type.
+    ///
+    /// Time is split into consecutive windows of `window_time_millis` aligned to the unix epoch,
+    /// and each user only keeps a counter for the current one. This is cheaper than the sliding
+    /// window but approximate: up to twice the capacity can pass around a window boundary.
+    ///
+    /// # Arguments
+    ///
+    /// * `request_type` - The type of request to configure.
+    /// * `capacity` - The maximum number of requests allowed per window.
+    /// * `window_time_millis` - The length of each window in milliseconds.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::RateLimiter;
+    /// 
+    /// let mut limiter = RateLimiter::with_in_memory();
+    /// limiter.add_config_fixed_window("type1", 100, 60000);
+    /// ```
+    pub fn add_config_fixed_window(&mut self, request_type: impl AsRef<str>, capacity: u32, window_time_millis: u64) -> &mut Self {
+        let request_type = request_type.as_ref();
+        self.table.get_mut().configs.insert(request_type.to_string(), Config::new(capacity, Algorithm::FixedWindow { window_time: Duration::from_millis(window_time_millis) }));
+        self
+    }
+
+    /// Adds a sliding window counter configuration for a request type.
+    ///
+    /// Like the fixed window, each user only keeps counters for the current and the previous
+    /// window, aligned to the unix epoch. A request is allowed if the current count plus the
+    /// previous one, weighted by how much of the previous window the sliding window still
+    /// covers, stays within the capacity. This approximates the sliding window in constant
+    /// memory, assuming requests are spread evenly over the previous window.
+    ///
+    /// # Arguments
+    ///
+    /// * `request_type` - The type of request to configure.
+    /// * `capacity` - The maximum number of requests allowed per window.
+    /// * `window_time_millis` - The length of each window in milliseconds.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::RateLimiter;
+    /// 
+    /// let mut limiter = RateLimiter::with_in_memory();
+    /// limiter.add_config_sliding_counter("type1", 100, 60000);
+    /// ```
+    pub fn add_config_sliding_counter(&mut self, request_type: impl AsRef<str>, capacity: u32, window_time_millis: u64) -> &mut Self {
+        let request_type = request_type.as_ref();
+        self.table.get_mut().configs.insert(request_type.to_string(), Config::new(capacity, Algorithm::SlidingCounter { window_time: Duration::from_millis(window_time_millis) }));
+        self
+    }
+
+    /// Adds a configuration for a request type whose window is either rolling, as with
+    /// [`add_config`](RateLimiter::add_config), or aligned to the clock, as with
+    /// [`add_config_fixed_window`](RateLimiter::add_config_fixed_window).
+    ///
+    /// Aligned windows match limits documented as e.g. "resets at the top of the hour": windows
+    /// are aligned to the unix epoch, so hourly ones reset on the hour in UTC, and daily ones
+    /// at midnight UTC.
+    ///
+    /// # Arguments
+    ///
+    /// * `request_type` - The type of request to configure.
+    /// * `capacity` - The maximum number of requests allowed in the window time.
+    /// * `window_time_millis` - The length of the window in milliseconds.
+    /// * `kind` - Whether the window rolls with every request or is aligned to the clock.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::{MockClock, RateLimiter, WindowKind};
+    /// use std::time::Duration;
+    ///
+    /// let clock = MockClock::new(3_599_000);
+    /// let mut limiter = RateLimiter::with_in_memory_clock(clock.clone());
+    /// limiter.add_config_with_kind("type1", 1, 3_600_000, WindowKind::Aligned);
+    /// assert!(limiter.allowed("user1", "type1").unwrap());
+    /// assert!(!limiter.allowed("user1", "type1").unwrap());
+    ///
+    /// // the top of the hour resets the limit.
+    /// clock.advance(Duration::from_secs(1));
+    /// assert!(limiter.allowed("user1", "type1").unwrap());
+    /// ```
+    pub fn add_config_with_kind(&mut self, request_type: impl AsRef<str>, capacity: u32, window_time_millis: u64, kind: WindowKind) -> &mut Self {
+        match kind {
+            WindowKind::Rolling => self.add_config(request_type, capacity, window_time_millis),
+            WindowKind::Aligned => self.add_config_fixed_window(request_type, capacity, window_time_millis),
+        }
+    }
+
+    /// Adds sliding window limits that switch by time of day, replacing any previous schedule
+    /// for the request type.
+    ///
+    /// Each request is checked against the slot that covers the limiter's clock at the time,
+    /// in UTC. Slots cover their start but not their end, so at the exact switch time the
+    /// next slot applies. Times that no slot covers fall back to the config added with
+    /// [`add_config`](RateLimiter::add_config) and friends, then to the default config. User
+    /// overrides take precedence over the schedule. All slots share one log per user, so
+    /// requests made before a switch count against the new limit.
+    ///
+    /// # Arguments
+    ///
+    /// * `request_type` - The type of request to configure.
+    /// * `schedule` - The limit of each time of day.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::{MockClock, RateLimiter, Schedule};
+    /// use std::time::Duration;
+    ///
+    /// let hour = Duration::from_secs(3600);
+    /// let clock = MockClock::new(0);
+    /// let mut limiter = RateLimiter::with_in_memory_clock(clock.clone());
+    /// // 1 request per minute overnight, 100 during business hours.
+    /// limiter.add_config("type1", 1, 60000);
+    /// limiter.add_scheduled_config("type1", Schedule::new().slot(hour * 9, hour * 18, 100, 60000));
+    ///
+    /// assert!(limiter.allowed("user1", "type1").unwrap());
+    /// assert!(!limiter.allowed("user1", "type1").unwrap());
+    ///
+    /// clock.advance(hour * 9);
+    /// assert!(limiter.allowed("user1", "type1").unwrap());
+    /// assert!(limiter.allowed("user1", "type1").unwrap());
+    /// ```
+    pub fn add_scheduled_config(&mut self, request_type: impl AsRef<str>, schedule: Schedule) -> &mut Self {
+        let configs = schedule
+            .limits()
+            .map(|(capacity, window_time_millis)| Config::new(capacity, Algorithm::SlidingWindow { window_time: Duration::from_millis(window_time_millis) }))
+            .collect();
+        self.schedules.insert(request_type.as_ref().to_string(), (schedule, configs));
+        self
+    }
+
+    /// Adds every config in `specs`, as the matching `add_config*` method does, so later specs
+    /// replace earlier ones for the same request type.
+    ///
+    /// # Arguments
+    ///
+    /// * `specs` - The configs to add, e.g. deserialized with the `serde` feature.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::{ConfigSpec, RateLimiter};
+    ///
+    /// let mut limiter = RateLimiter::with_in_memory();
+    /// limiter.add_configs(vec![
+    ///     ConfigSpec::SlidingWindow { request_type: "type1".to_string(), capacity: 5, window_time_millis: 60000 },
+    ///     ConfigSpec::TokenBucket { request_type: "type2".to_string(), capacity: 100, refill_per_sec: 10.0 },
+    /// ]);
+    /// assert!(limiter.is_configured("type2"));
+    /// ```
+    pub fn add_configs(&mut self, specs: impl IntoIterator<Item = ConfigSpec>) -> &mut Self {
+        self.table.get_mut().configs.extend(specs.into_iter().map(Config::from_spec));
+        self
+    }
+
+    /// Replaces every config added with `add_config*` by `new`, keyed by request type, so a
+    /// reload from a config service never leaves a mix of old and new configs behind.
+    ///
+    /// Request types missing from `new` become unknown, unless the default config covers them.
+    /// Request types in `new` keep drawing from the shared budget they were added to, capped by
+    /// their new config instead of their weight, and types missing from it leave their budget.
+    /// Schedules, the budgets themselves, user overrides and the default config are left in
+    /// place, and so is the state stored for every user. The swap takes `&self`, so it works on
+    /// a limiter shared through an `Arc`, e.g. with a `RateLimitLayer`, and concurrent requests
+    /// see either the old configs or the new ones, never a part of both.
+    ///
+    /// # Arguments
+    ///
+    /// * `new` - The configs to enforce from now on.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a spec is keyed by a request type other than its own. Nothing is
+    /// changed then.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::{ConfigSpec, RateLimiter};
+    /// use std::collections::HashMap;
+    ///
+    /// use std::sync::Arc;
+    ///
+    /// let mut limiter = RateLimiter::with_in_memory();
+    /// limiter.add_config("type1", 5, 60000);
+    /// let limiter = Arc::new(limiter);
+    ///
+    /// let spec = ConfigSpec::SlidingWindow { request_type: "type2".to_string(), capacity: 5, window_time_millis: 60000 };
+    /// limiter.replace_configs(HashMap::from([("type2".to_string(), spec)])).unwrap();
+    /// assert_eq!(limiter.configured_types(), vec!["type2"]);
+    /// ```
+    pub fn replace_configs(&self, new: HashMap<String, ConfigSpec>) -> Result<(), RateLimiterError> {
+        if let Some((request_type, spec)) = new.iter().find(|(request_type, spec)| spec.request_type() != request_type.as_str()) {
+            return Err(RateLimiterError::Message(format!("the config of {} is keyed as {}", spec.request_type(), request_type)));
+        }
+
+        // the specs are turned into configs before the swap, so a panic on an invalid one
+        // leaves the current configs untouched.
+        let configs: HashMap<String, Config> = new.into_values().map(Config::from_spec).collect();
+        self.table.update(|table| {
+            let budget_members = table
+                .budget_members
+                .iter()
+                .filter(|(request_type, _)| configs.contains_key(*request_type))
+                .map(|(request_type, budget_id)| (request_type.clone(), budget_id.clone()))
+                .collect();
+            ConfigTable { configs, budget_members }
+        });
+        Ok(())
+    }
+
+    /// Copies the configs of `other` into this limiter, e.g. to combine the request types that
+    /// several modules register on limiters of their own.
+    ///
+    /// Copies the configs added with `add_config*`, schedules and shared budgets. User
+    /// overrides, penalties, warm-ups, tenant configs, the default config and the state stored
+    /// for users stay with `other`.
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - The limiter to copy configs from.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a request type is configured in both limiters, or a shared budget
+    /// id is used by both, since silently replacing either would change a limit that its
+    /// owner relies on. Nothing is changed then.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::RateLimiter;
+    ///
+    /// let mut limiter = RateLimiter::with_in_memory();
+    /// limiter.add_config("login", 5, 60000);
+    ///
+    /// let mut search = RateLimiter::with_in_memory();
+    /// search.add_config("search", 100, 60000);
+    /// limiter.merge_configs(&search).unwrap();
+    /// assert_eq!(limiter.configured_types(), vec!["login", "search"]);
+    ///
+    /// assert!(limiter.merge_configs(&search).is_err());
+    /// ```
+    pub fn merge_configs(&mut self, other: &RateLimiter) -> Result<(), RateLimiterError> {
+        let other_table = other.table.load();
+        if let Some(request_type) = other_table.configs.keys().chain(other.schedules.keys()).find(|request_type| self.is_configured_explicitly(request_type)) {
+            return Err(RateLimiterError::Message(format!("{} is configured in both limiters", request_type)));
+        }
+        if let Some(budget_id) = other.budgets.keys().find(|budget_id| self.budgets.contains_key(*budget_id)) {
+            return Err(RateLimiterError::Message(format!("the shared budget {} exists in both limiters", budget_id)));
+        }
+
+        let table = self.table.get_mut();
+        table.configs.extend(other_table.configs.iter().map(|(request_type, config)| (request_type.clone(), config.clone())));
+        table.budget_members.extend(other_table.budget_members.iter().map(|(request_type, budget_id)| (request_type.clone(), budget_id.clone())));
+        self.schedules.extend(other.schedules.iter().map(|(request_type, schedule)| (request_type.clone(), schedule.clone())));
+        self.budgets.extend(other.budgets.iter().map(|(budget_id, config)| (budget_id.clone(), config.clone())));
+        Ok(())
+    }
+
+    /// Changes the capacity and window of an existing sliding window config while keeping the
+    /// state of its users.
+    ///
+    /// Timestamps already recorded are kept and re-evaluated under the new limits on the
+    /// next check: shrinking the capacity or window can deny a user right away, while growing
+    /// the window counts their logged requests for longer. Requests already evicted under the
+    /// old window are not brought back.
+    ///
+    /// # Arguments
+    ///
+    /// * `request_type` - The type of request to update.
+    /// * `capacity` - The new maximum number of requests allowed in the window time.
+    /// * `window_time_millis` - The new length of the sliding window in milliseconds.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`RateLimiterError::UnknownRequestType`] if the request type has no config of
+    /// its own, or an error if its config is not a single sliding window. Nothing is changed then.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::RateLimiter;
+    ///
+    /// let mut limiter = RateLimiter::with_in_memory();
+    /// limiter.add_config("type1", 5, 60000);
+    /// limiter.allowed("user1", "type1").unwrap();
+    ///
+    /// limiter.update_config("type1", 1, 60000).unwrap();
+    /// assert!(!limiter.allowed("user1", "type1").unwrap());
+    /// ```
+    pub fn update_config(&mut self, request_type: impl AsRef<str>, capacity: u32, window_time_millis: u64) -> Result<(), RateLimiterError> {
+        let request_type = request_type.as_ref();
+        let config = self
+            .table
+            .get_mut()
+            .configs
+            .get_mut(request_type)
+            .ok_or_else(|| RateLimiterError::UnknownRequestType(request_type.to_string()))?;
+        config.window_time("update_config")?;
+        if !config.tiers.is_empty() {
+            return Err(RateLimiterError::Message("update_config is not supported for tiered configs".to_string()));
+        }
+
+        config.capacity = capacity;
+        config.algorithm = Algorithm::SlidingWindow { window_time: Duration::from_millis(window_time_millis) };
+        Ok(())
+    }
+
+    /// Removes the config of a request type, so `allowed` reports it as unknown again.
+    ///
+    /// State already stored for the request type is left in place: it counts again if the
+    /// type is re-added before it expires. Use [`purge_request_type`](RateLimiter::purge_request_type)
+    /// to delete it as well.
+    ///
+    /// # Returns
+    ///
+    /// `true` if a config was removed, `false` if the request type was not configured.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::RateLimiter;
+    /// 
+    /// let mut limiter = RateLimiter::with_in_memory();
+    /// limiter.add_config("type1", 5, 60000);
+    /// assert!(limiter.remove_config("type1"));
+    /// assert!(limiter.allowed("user1", "type1").is_err());
+    /// ```
+    pub fn remove_config(&mut self, request_type: impl AsRef<str>) -> bool {
+        let request_type = request_type.as_ref();
+        let removed_schedule = self.schedules.remove(request_type).is_some();
+        let table = self.table.get_mut();
+        table.budget_members.remove(request_type);
+        table.configs.remove(request_type).is_some() || removed_schedule
+    }
+
+    /// Returns the capacity and window in effect for a request type.
+    ///
+    /// Request types with a schedule report the slot that covers the current time, and request
+    /// types without a config of their own report the default config, if one is set.
+    /// For token and leaky buckets, the window is the time it takes to refill (or drain) the
+    /// whole bucket. Per-user overrides are not taken into account.
+    ///
+    /// # Arguments
+    ///
+    /// * `request_type` - The type of request to look up.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::RateLimiter;
+    /// use std::time::Duration;
+    ///
+    /// let mut limiter = RateLimiter::with_in_memory();
+    /// limiter.add_config("type1", 5, 60000).add_config_token_bucket("type2", 100, 10.0);
+    ///
+    /// assert_eq!(limiter.get_config("type1"), Some((5, Duration::from_secs(60))));
+    /// assert_eq!(limiter.get_config("type2"), Some((100, Duration::from_secs(10))));
+    /// assert_eq!(limiter.get_config("type3"), None);
+    /// ```
+    pub fn get_config(&self, request_type: impl AsRef<str>) -> Option<(u32, Duration)> {
+        let request_type = request_type.as_ref();
+        let table = self.table.load();
+        self.scheduled_config(request_type, self.clock.now_millis())
+            .or_else(|| table.configs.get(request_type))
+            .or(self.default_config.as_ref())
+            .map(|config| (config.capacity, config.period()))
+    }
+
+    /// Returns whether requests of `request_type` are governed by a config, either their own
+    /// or the default one, so that [`allowed`](RateLimiter::allowed) will not reject them as unknown.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::RateLimiter;
+    ///
+    /// let mut limiter = RateLimiter::with_in_memory();
+    /// limiter.add_config("type1", 5, 60000);
+    ///
+    /// assert!(limiter.is_configured("type1"));
+    /// assert!(!limiter.is_configured("type2"));
+    /// ```
+    pub fn is_configured(&self, request_type: impl AsRef<str>) -> bool {
+        let request_type = request_type.as_ref();
+        self.is_configured_explicitly(request_type) || self.default_config.is_some()
+    }
+
+    /// Returns the request types that have a config of their own, in sorted order.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::RateLimiter;
+    ///
+    /// let mut limiter = RateLimiter::with_in_memory();
+    /// limiter.add_config("uploads", 2, 60000).add_config_token_bucket("search", 100, 10.0);
+    ///
+    /// assert_eq!(limiter.configured_types(), vec!["search", "uploads"]);
+    /// ```
+    pub fn configured_types(&self) -> Vec<String> {
+        let mut request_types: Vec<String> = self.table.load().configs.keys().chain(self.schedules.keys()).cloned().collect();
+        request_types.sort();
+        request_types.dedup();
+        request_types
+    }
+
+    /// Deletes the stored state of every user for a request type, returning how many keys were removed.
+    ///
+    /// Only keys under the configured key prefix are touched. For Redis this scans the
+    /// keyspace, so it is meant for maintenance rather than the request path.
+    ///
+    /// # Arguments
+    ///
+    /// * `request_type` - The type of request to purge.
+    pub fn purge_request_type(&self, request_type: impl AsRef<str>) -> Result<u64, RateLimiterError> {
+        let request_type = request_type.as_ref();
+        let prefix = match &self.key_prefix {
+            Some(prefix) => format!("{}:", prefix),
+            None => String::new(),
+        };
+        // escaped request types hold no `:`, so the suffix only matches at the separator.
+        Ok(self.storage.remove_matching(&prefix, &format!(":{}", key_builder::escape(request_type)))?)
+    }
+
+    /// Removes the config of a request type together with its user overrides and tenant config,
+    /// and deletes the stored state of every user for it, e.g. when retiring an endpoint.
+    ///
+    /// Returns how many keys were removed. The state is deleted first, as
+    /// [`purge_request_type`](RateLimiter::purge_request_type) does, so if that fails the
+    /// configs are left in place and the call can be retried.
+    ///
+    /// # Arguments
+    ///
+    /// * `request_type` - The type of request to prune.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::RateLimiter;
+    ///
+    /// let mut limiter = RateLimiter::with_in_memory();
+    /// limiter.add_config("type1", 5, 60000);
+    /// limiter.allowed("user1", "type1").unwrap();
+    ///
+    /// assert_eq!(limiter.prune_config("type1").unwrap(), 1);
+    /// assert!(!limiter.is_configured("type1"));
+    /// ```
+    pub fn prune_config(&mut self, request_type: impl AsRef<str>) -> Result<u64, RateLimiterError> {
+        let request_type = request_type.as_ref();
+        let removed = self.purge_request_type(request_type)?;
+        let table = self.table.get_mut();
+        table.configs.remove(request_type);
+        table.budget_members.remove(request_type);
+        self.overrides.remove(request_type);
+        self.tenant_configs.remove(request_type);
+        self.warmups.remove(request_type);
+        self.penalties.remove(request_type);
+        self.thresholds.remove(request_type);
+        self.schedules.remove(request_type);
+        Ok(removed)
+    }
+
+    /// Deletes the stored state of every user for every request type, leaving configs in place.
+    ///
+    /// With a key prefix, every key under it is removed. Without one, keys of other apps sharing
+    /// the same Redis cannot be told apart, so only request types with a config or user override
+    /// of their own are cleared; set a prefix to also clear the types governed by the default config.
+    /// For Redis this scans the keyspace once per pattern, so it is meant for tests and
+    /// maintenance rather than the request path.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::RateLimiter;
+    ///
+    /// let mut limiter = RateLimiter::with_in_memory();
+    /// limiter.add_config("type1", 1, 60000);
+    /// limiter.allowed("user1", "type1").unwrap();
+    ///
+    /// limiter.clear().unwrap();
+    /// assert!(limiter.allowed("user1", "type1").unwrap());
+    /// ```
+    pub fn clear(&self) -> Result<(), RateLimiterError> {
+        if let Some(prefix) = &self.key_prefix {
+            self.storage.remove_matching(&format!("{}:", prefix), "")?;
+            return Ok(());
+        }
+
+        let overridden_types = self.overrides.keys().filter(|request_type| !self.is_configured_explicitly(request_type));
+        for request_type in self.configured_types().iter().chain(overridden_types) {
+            self.purge_request_type(request_type)?;
+        }
+        Ok(())
+    }
+
+    /// Sets the overall sliding window budget each tenant has for a request type, shared by
+    /// all of its users, for [`allowed_hierarchical`](RateLimiter::allowed_hierarchical).
+    ///
+    /// # Arguments
+    ///
+    /// * `request_type` - The type of request to configure.
+    /// * `capacity` - The maximum number of requests a tenant's users make together in the window time.
+    /// * `window_time_millis` - The length of the sliding window in milliseconds.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::RateLimiter;
+    ///
+    /// let mut limiter = RateLimiter::with_in_memory();
+    /// // each user makes up to 5 requests a minute, and each tenant 100.
+    /// limiter.add_config("api", 5, 60000).add_tenant_config("api", 100, 60000);
+    /// ```
+    pub fn add_tenant_config(&mut self, request_type: impl AsRef<str>, capacity: u32, window_time_millis: u64) -> &mut Self {
+        let request_type = request_type.as_ref();
+        self.tenant_configs.insert(request_type.to_string(), Config::new(capacity, Algorithm::SlidingWindow { window_time: Duration::from_millis(window_time_millis) }));
+        self
+    }
+
+    /// Sets up a sliding window budget that each user shares across the request types added
+    /// to it with [`add_config_shared`](RateLimiter::add_config_shared), replacing any previous
+    /// budget of the same id.
+    ///
+    /// # Arguments
+    ///
+    /// * `budget_id` - The name of the budget, distinct from every request type.
+    /// * `capacity` - The maximum number of requests a user makes across the budget's request types in the window time.
+    /// * `window_time_millis` - The length of the sliding window in milliseconds.
+    pub fn add_shared_budget(&mut self, budget_id: impl AsRef<str>, capacity: u32, window_time_millis: u64) -> &mut Self {
+        self.budgets.insert(budget_id.as_ref().to_string(), Config::new(capacity, Algorithm::SlidingWindow { window_time: Duration::from_millis(window_time_millis) }));
+        self
+    }
+
+    /// Lets a request type draw from a shared budget, using at most `weight` of it, replacing
+    /// any previous config for the request type.
+    ///
+    /// The request type gets a sliding window config of its own with the budget's window and
+    /// `weight` times its capacity, rounded down. [`allowed`](RateLimiter::allowed) then only
+    /// allows a request if both that share and the budget have room, and records it against
+    /// both atomically.
+    ///
+    /// Weights cap a request type, but do not reserve anything for it: requests are served first
+    /// come, first served, so once others have exhausted the budget, a request type is denied
+    /// even if it is far below its share. To guarantee every request type its share, keep the
+    /// weights of a budget from adding up to more than 1. Replacing the config of the request
+    /// type, e.g. with a user override, changes its share but keeps it in the budget until
+    /// [`remove_config`](RateLimiter::remove_config).
+    ///
+    /// # Arguments
+    ///
+    /// * `request_type` - The type of request to configure.
+    /// * `budget_id` - The budget added with [`add_shared_budget`](RateLimiter::add_shared_budget).
+    /// * `weight` - The largest fraction of the budget the request type may use.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no budget of that id, or if `weight` is not in `(0, 1]`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::RateLimiter;
+    ///
+    /// let mut limiter = RateLimiter::with_in_memory();
+    /// limiter.add_shared_budget("api", 10, 60000);
+    /// limiter.add_config_shared("search", "api", 0.7).add_config_shared("export", "api", 0.5);
+    ///
+    /// // search may use 7 of the 10 requests...
+    /// for _ in 0..7 {
+    ///     assert!(limiter.allowed("user1", "search").unwrap());
+    /// }
+    /// assert!(!limiter.allowed("user1", "search").unwrap());
+    ///
+    /// // ...which leaves only 3 of export's 5.
+    /// for _ in 0..3 {
+    ///     assert!(limiter.allowed("user1", "export").unwrap());
+    /// }
+    /// assert!(!limiter.allowed("user1", "export").unwrap());
+    /// ```
+    pub fn add_config_shared(&mut self, request_type: impl AsRef<str>, budget_id: impl AsRef<str>, weight: f64) -> &mut Self {
+        let (request_type, budget_id) = (request_type.as_ref(), budget_id.as_ref());
+        assert!(weight > 0.0 && weight <= 1.0, "the weight of a shared budget must be in (0, 1]");
+        let budget = self.budgets.get(budget_id).unwrap_or_else(|| panic!("no shared budget: {}", budget_id));
+
+        let config = Config { capacity: (budget.capacity as f64 * weight) as u32, ..budget.clone() };
+        let table = self.table.get_mut();
+        table.configs.insert(request_type.to_string(), config);
+        table.budget_members.insert(request_type.to_string(), budget_id.to_string());
+        self
+    }
+
+    /// Adds a per-user sliding window configuration that takes precedence over the request type's.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The ID of the user the override applies to.
+    /// * `request_type` - The type of request to configure.
+    /// * `capacity` - The maximum number of requests allowed in the window time.
+    /// * `window_time_millis` - The length of the sliding window in milliseconds.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::RateLimiter;
+    /// 
+    /// let mut limiter = RateLimiter::with_in_memory();
+    /// limiter.add_config("type1", 5, 60000).add_user_override("premium1", "type1", 50, 60000);
+    /// ```
+    pub fn add_user_override(&mut self, user_id: impl AsRef<str>, request_type: impl AsRef<str>, capacity: u32, window_time_millis: u64) -> &mut Self {
+        let (user_id, request_type) = (user_id.as_ref(), request_type.as_ref());
+        let config = Config::new(capacity, Algorithm::SlidingWindow { window_time: Duration::from_millis(window_time_millis) });
+        self.overrides.entry(request_type.to_string()).or_default().insert(user_id.to_string(), config);
+        self
+    }
+
+    /// Removes a per-user override, reverting the user to the request type's config.
+    ///
+    /// Requests already recorded for the user are kept and count against the request type's config.
+    ///
+    /// # Returns
+    ///
+    /// `true` if an override was removed, `false` if the user had none.
+    pub fn remove_user_override(&mut self, user_id: impl AsRef<str>, request_type: impl AsRef<str>) -> bool {
+        let (user_id, request_type) = (user_id.as_ref(), request_type.as_ref());
+        let Some(overrides) = self.overrides.get_mut(request_type) else {
+            return false;
+        };
+
+        let removed = overrides.remove(user_id).is_some();
+        if overrides.is_empty() {
+            self.overrides.remove(request_type);
+        }
+        removed
+    }
+
+    /// Checks if a request is allowed.
+    ///
+    /// Configs with a capacity of zero deny every request, and windowed configs with a window
+    /// of zero allow every request, both without touching the storage.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The ID of the user making the request.
+    /// * `request_type` - The type of request.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(true)` if the request is allowed.
+    /// * `Ok(false)` if the request is not allowed.
+    /// * `Err(RateLimiterError::UnknownRequestType)` if `request_type` has no config and no default config is set.
+    /// * `Err` if any other error occurs.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::RateLimiter;
+    /// 
+    /// let mut limiter = RateLimiter::with_in_memory();
+    /// limiter.add_config("type1", 5, 60000);
+    /// let is_allowed = limiter.allowed("user1", "type1").unwrap();
+    /// ```
+    pub fn allowed(&self, user_id: impl AsRef<str>, request_type: impl AsRef<str>) -> Result<bool, RateLimiterError> {
+        self.allowed_n(user_id, request_type, 1)
+    }
+
+    /// Checks if a request is allowed, returning [`RateLimiterError::LimitExceeded`] if not, so
+    /// a handler can propagate denials with `?` and turn them into a `429` in one place.
+    ///
+    /// The error carries how long until a request would be allowed, as
+    /// [`retry_after`](RateLimiter::retry_after) reports, or `None` if the limiter cannot tell,
+    /// e.g. for token buckets or a capacity of zero.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The ID of the user making the request.
+    /// * `request_type` - The type of request.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::{RateLimiter, RateLimiterError};
+    /// use std::time::Duration;
+    ///
+    /// let mut limiter = RateLimiter::with_in_memory();
+    /// limiter.add_config("type1", 1, 60000);
+    ///
+    /// limiter.consume("user1", "type1").unwrap();
+    /// match limiter.consume("user1", "type1") {
+    ///     Err(RateLimiterError::LimitExceeded { retry_after: Some(retry_after) }) => assert!(retry_after <= Duration::from_secs(60)),
+    ///     result => panic!("expected a denial, got {:?}", result),
+    /// }
+    /// ```
+    pub fn consume(&self, user_id: impl AsRef<str>, request_type: impl AsRef<str>) -> Result<(), RateLimiterError> {
+        let (user_id, request_type) = (user_id.as_ref(), request_type.as_ref());
+        if self.allowed(user_id, request_type)? {
+            return Ok(());
+        }
+        // the denial stands even if the wait cannot be worked out.
+        let retry_after = self.retry_after(user_id, request_type).ok().flatten();
+        Err(RateLimiterError::LimitExceeded { retry_after })
+    }
+
+    /// Checks if a request is allowed, blocking the current thread until it is or `max_wait`
+    /// has passed, e.g. for background workers that must eventually make a request.
+    ///
+    /// Between checks, the thread sleeps for as long as [`retry_after`](RateLimiter::retry_after)
+    /// reports, or polls every few milliseconds if the limiter cannot tell, e.g. for token
+    /// buckets. The sleep is in real time, so the limiter clock has to follow it; with a
+    /// [`MockClock`](crate::MockClock) nothing frees up until `max_wait` runs out.
+    ///
+    /// This is meant for waiting within a single process, mainly on in-memory storage. Waiters
+    /// are not queued: when a request frees up, every thread waiting on the same key wakes up
+    /// at about the same time and only as many as fit are allowed, while the rest go back to
+    /// sleep, so a waiter may be overtaken by a later one. With Redis, waiters in other
+    /// processes compete the same way, each at the cost of a round trip per check. Async code
+    /// should not call this, as it blocks the thread it runs on.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The ID of the user making the request.
+    /// * `request_type` - The type of request.
+    /// * `max_wait` - The longest to wait for the request to be allowed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::RateLimiter;
+    /// use std::time::Duration;
+    ///
+    /// let mut limiter = RateLimiter::with_in_memory();
+    /// limiter.add_config("type1", 1, 50);
+    ///
+    /// assert!(limiter.allowed("user1", "type1").unwrap());
+    /// // waits about 50 milliseconds for the first request to leave the window.
+    /// assert!(limiter.allowed_blocking("user1", "type1", Duration::from_secs(1)).unwrap());
+    /// assert!(!limiter.allowed_blocking("user1", "type1", Duration::ZERO).unwrap());
+    /// ```
+    pub fn allowed_blocking(&self, user_id: impl AsRef<str>, request_type: impl AsRef<str>, max_wait: Duration) -> Result<bool, RateLimiterError> {
+        let (user_id, request_type) = (user_id.as_ref(), request_type.as_ref());
+        // a wait too long to represent never runs out.
+        let deadline = Instant::now().checked_add(max_wait);
+
+        loop {
+            if self.allowed(user_id, request_type)? {
+                return Ok(true);
+            }
+            let remaining = deadline.map_or(Duration::MAX, |deadline| deadline.saturating_duration_since(Instant::now()));
+            if remaining.is_zero() {
+                return Ok(false);
+            }
+            // a wait of zero means another waiter took the request that freed up, so back off
+            // for a poll interval instead of spinning.
+            let wait = match self.retry_after(user_id, request_type).ok().flatten() {
+                Some(retry_after) if !retry_after.is_zero() => retry_after,
+                _ => BLOCKING_POLL_INTERVAL,
+            };
+            std::thread::sleep(wait.min(remaining));
+        }
+    }
+
+    /// Checks if a request is allowed, counting every call with the same `token` as one
+    /// request, e.g. when both a middleware and a handler check the same logical operation.
+    ///
+    /// The first call that is allowed records the token for the config's longest window, and
+    /// later calls with it are allowed without consuming anything more. A denied call records
+    /// nothing, so the next call with the token is checked again. Calls with the same token are
+    /// meant to follow each other within one operation; concurrent ones may each be counted.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The ID of the user making the request.
+    /// * `request_type` - The type of request.
+    /// * `token` - Identifies the operation, e.g. a request id.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::RateLimiter;
+    ///
+    /// let mut limiter = RateLimiter::with_in_memory();
+    /// limiter.add_config("type1", 2, 60000);
+    ///
+    /// assert!(limiter.allowed_once("user1", "type1", "request-1").unwrap());
+    /// assert!(limiter.allowed_once("user1", "type1", "request-1").unwrap());
+    /// assert_eq!(limiter.remaining("user1", "type1").unwrap(), 1);
+    /// ```
+    pub fn allowed_once(&self, user_id: impl AsRef<str>, request_type: impl AsRef<str>, token: &str) -> Result<bool, RateLimiterError> {
+        let (user_id, request_type) = (user_id.as_ref(), request_type.as_ref());
+        if !self.enabled {
+            return Ok(true);
+        }
+        let table = self.table.load();
+        let config = self.config(&table, user_id, request_type)?;
+        let token_key = self.decorated_key(&format!("{}:token:{}", key_builder::escape(user_id), key_builder::escape(token)), request_type);
+        let period = config.longest_period();
+        let now = self.clock.now_millis();
+        match self.storage.count(&token_key, period, now) {
+            Ok(token_count) if token_count > 0 => return Ok(true),
+            Ok(_) => {},
+            // with a failure mode, the check that follows decides the request instead.
+            Err(_) if self.failure_mode.is_some() => {},
+            Err(e) => return Err(e.into()),
+        }
+
+        let is_allowed = self.allowed(user_id, request_type)?;
+        if is_allowed {
+            match self.storage.check_and_record(&token_key, 1, 1, period, now) {
+                Err(e) if self.failure_mode.is_none() => return Err(e.into()),
+                _ => {},
+            }
+        }
+        Ok(is_allowed)
+    }
+
+    /// Checks if a request is allowed, also returning whether the user had no request in the
+    /// window before it, e.g. to set up per-user state lazily on their first request.
+    ///
+    /// Both come from the same storage call, so concurrent requests of the same user never both
+    /// see themselves as the first. A user counts as new again once their window has emptied.
+    /// Only sliding window configs outside shared budgets are supported.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The ID of the user making the request.
+    /// * `request_type` - The type of request.
+    ///
+    /// # Returns
+    ///
+    /// `(allowed, was_first)`, where `was_first` is false while rate limiting is disabled.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::RateLimiter;
+    ///
+    /// let mut limiter = RateLimiter::with_in_memory();
+    /// limiter.add_config("type1", 5, 60000);
+    ///
+    /// assert_eq!(limiter.allowed_first("user1", "type1").unwrap(), (true, true));
+    /// assert_eq!(limiter.allowed_first("user1", "type1").unwrap(), (true, false));
+    /// ```
+    pub fn allowed_first(&self, user_id: impl AsRef<str>, request_type: impl AsRef<str>) -> Result<(bool, bool), RateLimiterError> {
+        let (user_id, request_type) = (user_id.as_ref(), request_type.as_ref());
+        if !self.enabled {
+            return Ok((true, false));
+        }
+        let table = self.table.load();
+        let config = self.config(&table, user_id, request_type)?;
+        if !config.tiers.is_empty() || table.budget_members.contains_key(request_type) {
+            return Err(RateLimiterError::Message("allowed_first is not supported for tiered and shared configs".to_string()));
+        }
+        let key = self.key(user_id, request_type);
+        let window_time = self.window(config, &key, "allowed_first")?;
+        let capacity = self.capacity(request_type, config.capacity);
+
+        let now = config.now(&*self.clock);
+        let (is_allowed, request_count) = self.storage.check_and_record_with_count(&key, capacity, 1, window_time, now)?;
+        self.record(request_type, is_allowed);
+        // the count is taken after the decision, so it includes this request if it was recorded.
+        let was_first = request_count == u32::from(is_allowed);
+        Ok((self.verdict(is_allowed), was_first))
+    }
+
+    /// Checks if a request is allowed, also returning the capacity and window of the limit that
+    /// applied, e.g. to log "denied user1: 5/5 in 60s".
+    ///
+    /// Per-user overrides are taken into account. For token and leaky buckets and GCRA, the
+    /// window is the time it takes to replenish the whole capacity, as in
+    /// [`get_config`](RateLimiter::get_config), saturating at `u64::MAX` for buckets that never refill.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The ID of the user making the request.
+    /// * `request_type` - The type of request.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::RateLimiter;
+    ///
+    /// let mut limiter = RateLimiter::with_in_memory();
+    /// limiter.add_config("type1", 1, 60000);
+    ///
+    /// assert_eq!(limiter.allowed_with_info("user1", "type1").unwrap(), (true, 1, 60000));
+    /// assert_eq!(limiter.allowed_with_info("user1", "type1").unwrap(), (false, 1, 60000));
+    /// ```
+    pub fn allowed_with_info(&self, user_id: impl AsRef<str>, request_type: impl AsRef<str>) -> Result<(bool, u32, u64), RateLimiterError> {
+        let (user_id, request_type) = (user_id.as_ref(), request_type.as_ref());
+        let table = self.table.load();
+        let config = self.config(&table, user_id, request_type)?;
+        let window_millis = u64::try_from(config.period().as_millis()).unwrap_or(u64::MAX);
+
+        let capacity = self.capacity(request_type, config.capacity);
+        let is_allowed = self.allowed(user_id, request_type)?;
+        Ok((is_allowed, capacity, window_millis))
+    }
+
+    /// Checks if a request is allowed for a combination of keys, e.g. a user and their client IP.
+    ///
+    /// The parts are joined with `:` in the order given, and the result is used as the user id,
+    /// so `allowed_keyed(&["user1", "10.0.0.1"], "type1")` shares its state with
+    /// `allowed("user1:10.0.0.1", "type1")` on every backend. Per-user overrides match the
+    /// joined id as well. Every `%` and `:` within a part is percent-encoded first, so
+    /// `["a:b", "c"]` and `["a", "b:c"]` are told apart; an IPv6 address `::1` joins as `%3A%3A1`.
+    ///
+    /// # Arguments
+    ///
+    /// * `keys` - The parts identifying who makes the request.
+    /// * `request_type` - The type of request.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `keys` is empty, in addition to the errors of [`allowed`](RateLimiter::allowed).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::RateLimiter;
+    ///
+    /// let mut limiter = RateLimiter::with_in_memory();
+    /// limiter.add_config("login", 1, 60000);
+    ///
+    /// assert!(limiter.allowed_keyed(&["user1", "10.0.0.1"], "login").unwrap());
+    /// assert!(!limiter.allowed_keyed(&["user1", "10.0.0.1"], "login").unwrap());
+    /// assert!(limiter.allowed_keyed(&["user1", "10.0.0.2"], "login").unwrap());
+    /// ```
+    pub fn allowed_keyed(&self, keys: &[&str], request_type: impl AsRef<str>) -> Result<bool, RateLimiterError> {
+        if keys.is_empty() {
+            return Err(RateLimiterError::Message("allowed_keyed needs at least one key part".to_string()));
+        }
+        let parts: Vec<_> = keys.iter().map(|part| key_builder::escape(part)).collect();
+        self.allowed(parts.join(":"), request_type)
+    }
+
+    /// Checks if a request is allowed, like [`allowed`](RateLimiter::allowed), and reports the
+    /// state of the window after the decision.
+    ///
+    /// Only sliding window configs are supported. Penalties, the failure mode and shadow mode
+    /// apply as they do to [`allowed`](RateLimiter::allowed). Finding when the window resets
+    /// costs one more storage lookup, so prefer [`allowed`](RateLimiter::allowed) when only the
+    /// verdict matters.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The ID of the user making the request.
+    /// * `request_type` - The type of request.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::RateLimiter;
+    /// use std::time::Duration;
+    ///
+    /// let mut limiter = RateLimiter::with_in_memory();
+    /// limiter.add_config("type1", 5, 60000);
+    ///
+    /// let decision = limiter.check("user1", "type1").unwrap();
+    /// assert!(decision.allowed);
+    /// assert_eq!(decision.limit, 5);
+    /// assert_eq!(decision.remaining, 4);
+    /// assert!(decision.reset_after <= Duration::from_secs(60));
+    /// ```
+    pub fn check(&self, user_id: impl AsRef<str>, request_type: impl AsRef<str>) -> Result<Decision, RateLimiterError> {
+        let (user_id, request_type) = (user_id.as_ref(), request_type.as_ref());
+        if !self.enabled {
+            // like `allowed`, a disabled limiter allows every request without looking up its type.
+            let capacity = self.table.load().configs.get(request_type).map_or(u32::MAX, |config| self.capacity(request_type, config.capacity));
+            return Ok(Decision { allowed: true, limit: capacity, remaining: capacity, reset_after: Duration::ZERO });
+        }
+        let table = self.table.load();
+        let config = self.config(&table, user_id, request_type)?;
+        let key = self.key(user_id, request_type);
+        let window_time = self.window(config, &key, "check")?;
+        if !config.tiers.is_empty() {
+            return Err(RateLimiterError::Message("check is not supported for tiered configs".to_string()));
+        }
+        let capacity = self.capacity(request_type, config.capacity);
+
+        let now = config.now(&*self.clock);
+        let (allowed, request_count) = self.decide(&table, user_id, request_type, 1, &*self.clock, true)?;
+        let remaining = match request_count {
+            Some(request_count) => capacity.saturating_sub(request_count),
+            // blocked users, shared budgets and failed storages decide without counting.
+            None if allowed => match self.storage.count(&key, window_time, now) {
+                Ok(request_count) => capacity.saturating_sub(request_count),
+                Err(_) if self.failure_mode.is_some() => capacity,
+                Err(e) => return Err(e.into()),
+            },
+            None => 0,
+        };
+
+        let reset_after = match self.storage.oldest(&key, window_time, now) {
+            Ok(Some(oldest_timestamp)) => {
+                let expires_at = oldest_timestamp.saturating_add(window_time.as_millis() as u64);
+                config.duration(expires_at.saturating_sub(now))
+            },
+            Ok(None) => Duration::ZERO,
+            Err(_) if self.failure_mode.is_some() => Duration::ZERO,
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(Decision { allowed: self.verdict(allowed), limit: capacity, remaining, reset_after })
+    }
+
+    /// Checks if a request that costs `cost` units is allowed, consuming all of them if so.
+    ///
+    /// A request costing more than the capacity is never allowed, and is rejected without
+    /// touching the storage.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The ID of the user making the request.
+    /// * `request_type` - The type of request.
+    /// * `cost` - The number of units the request consumes.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::RateLimiter;
+    /// 
+    /// let mut limiter = RateLimiter::with_in_memory();
+    /// limiter.add_config("type1", 5, 60000);
+    /// assert!(limiter.allowed_n("user1", "type1", 3).unwrap());
+    /// assert!(!limiter.allowed_n("user1", "type1", 3).unwrap());
+    /// ```
+    pub fn allowed_n(&self, user_id: impl AsRef<str>, request_type: impl AsRef<str>, cost: u32) -> Result<bool, RateLimiterError> {
+        self.allowed_n_with_clock(user_id.as_ref(), request_type.as_ref(), cost, &*self.clock)
+    }
+
+    /// Checks if a request made at `now_millis` is allowed, instead of at the limiter's clock,
+    /// e.g. to replay events from a log with their own timestamps and reproduce the decisions
+    /// made on them back then.
+    ///
+    /// The timestamp places the request in its window, picks the slot of a schedule and decides
+    /// penalties; warm-ups still ramp up by the limiter's clock. Timestamps may arrive out of
+    /// order: an event older than the ones before it is treated like a clock that went backwards,
+    /// so the in-memory storage still counts the later requests against it, and buckets do not
+    /// refill. Redis storages that use the server's clock ignore the timestamp.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The ID of the user making the request.
+    /// * `request_type` - The type of request.
+    /// * `now_millis` - When the request was made, in milliseconds since the unix epoch.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::RateLimiter;
+    ///
+    /// let mut limiter = RateLimiter::with_in_memory();
+    /// limiter.add_config("type1", 1, 1000);
+    ///
+    /// assert!(limiter.allowed_at("user1", "type1", 5000).unwrap());
+    /// assert!(!limiter.allowed_at("user1", "type1", 5999).unwrap());
+    /// assert!(limiter.allowed_at("user1", "type1", 6000).unwrap());
+    /// ```
+    pub fn allowed_at(&self, user_id: impl AsRef<str>, request_type: impl AsRef<str>, now_millis: u64) -> Result<bool, RateLimiterError> {
+        self.allowed_n_with_clock(user_id.as_ref(), request_type.as_ref(), 1, &FixedClock(now_millis))
+    }
+
+    /// Checks a request of `cost` units at the time of `clock`, as [`allowed_n`](RateLimiter::allowed_n) does.
+    fn allowed_n_with_clock(&self, user_id: &str, request_type: &str, cost: u32, clock: &dyn Clock) -> Result<bool, RateLimiterError> {
+        if !self.enabled {
+            return Ok(true);
+        }
+        let (is_allowed, _) = self.decide(&self.table.load(), user_id, request_type, cost, clock, false)?;
+        Ok(self.verdict(is_allowed))
+    }
+
+    /// Decides a request of `cost` units under the config `table` holds for the user at the
+    /// time of `clock`, enforcing penalties and reporting the decision to the metrics sink, for
+    /// every check that records a single request type of a single user.
+    ///
+    /// Returns the decision before shadow mode and, if `counted` is set, how many requests a
+    /// single sliding window holds after it. The count is `None` when the decision was made
+    /// without one, e.g. for a blocked user, a shared budget or a failed storage.
+    fn decide(&self, table: &ConfigTable, user_id: &str, request_type: &str, cost: u32, clock: &dyn Clock, counted: bool) -> Result<(bool, Option<u32>), RateLimiterError> {
+        let config = self.config_at(table, user_id, request_type, clock.now_millis())?;
+        let now = config.now(clock);
+        if config.capacity == 0 || cost > config.capacity {
+            self.record(request_type, false);
+            return Ok((false, None));
+        }
+        if config.is_unlimited() {
+            self.record(request_type, true);
+            return Ok((true, None));
+        }
+        if self.is_blocked(user_id, request_type, clock.now_millis())? {
+            self.record(request_type, false);
+            return Ok((false, None));
+        }
+
+        let (is_allowed, request_count) = if !config.tiers.is_empty() || table.budget_members.contains_key(request_type) {
+            if cost != 1 {
+                return Err(RateLimiterError::Message("tiered and shared configs only support checks of a single request".to_string()));
+            }
+            let owned_checks = self.windows(table, config, user_id, request_type, "allowed")?;
+            let checks: Vec<(&str, u32, Duration)> = owned_checks.iter().map(|(key, capacity, window_time)| (key.as_str(), *capacity, *window_time)).collect();
+            (self.degrade(request_type, self.storage.check_and_record_all(&checks, now))?, None)
+        } else {
+            let key = self.key(user_id, request_type);
+            let capacity = self.capacity(request_type, config.capacity);
+            let result = match config.algorithm {
+                Algorithm::SlidingWindow { window_time } => {
+                    let window = self.jittered(config, &key, config.storage_window(window_time));
+                    if counted || self.thresholds.contains_key(request_type) {
+                        self.storage.check_and_record_with_count(&key, capacity, cost, window, now).map(|(is_allowed, request_count)| (is_allowed, Some(request_count)))
+                    } else {
+                        self.storage.check_and_record(&key, capacity, cost, window, now).map(|is_allowed| (is_allowed, None))
+                    }
+                },
+                Algorithm::TokenBucket { refill_per_sec } => self.storage.take_tokens(&key, capacity, refill_per_sec, cost, now).map(|is_allowed| (is_allowed, None)),
+                Algorithm::FixedWindow { window_time } => self.storage.increment_window(&key, capacity, cost, window_time, now).map(|is_allowed| (is_allowed, None)),
+                Algorithm::SlidingCounter { window_time } => self.storage.increment_sliding_counter(&key, capacity, cost, window_time, now).map(|is_allowed| (is_allowed, None)),
+                Algorithm::LeakyBucket { leak_per_sec } => self.storage.leak_and_fill(&key, capacity, leak_per_sec, cost, now).map(|is_allowed| (is_allowed, None)),
+                Algorithm::Gcra { rate_per_sec } => self.storage.advance_tat(&key, capacity, rate_per_sec, cost, now).map(|is_allowed| (is_allowed, None)),
+            };
+            let request_count = result.as_ref().ok().and_then(|&(_, request_count)| request_count);
+            if let (Some(request_count), Some(thresholds), Algorithm::SlidingWindow { window_time }) = (request_count, self.thresholds.get(request_type), config.algorithm) {
+                let utilization = f64::from(request_count) / f64::from(capacity.max(1));
+                let window_millis = u64::try_from(window_time.as_millis()).unwrap_or(u64::MAX);
+                for threshold in thresholds {
+                    threshold.observe(user_id, utilization, window_millis, clock.now_millis());
+                }
+            }
+            (self.degrade(request_type, result.map(|(is_allowed, _)| is_allowed))?, request_count)
+        };
+        if !is_allowed {
+            self.add_violation(config, user_id, request_type, clock.now_millis())?;
+        }
+        self.record(request_type, is_allowed);
+        Ok((is_allowed, request_count))
+    }
+
+    /// Counts a request tentatively, returning a [`Reservation`] that gives it back when dropped
+    /// unless it is committed, or `None` if the request is denied.
+    ///
+    /// This lets a caller check the limit before doing work that may fail, and only keep the
+    /// request counted if the work happened. A released request frees up its capacity right
+    /// away, as if it had never been made. Only sliding window configs outside shared budgets are
+    /// supported, and Redis storages that use the server's clock cannot release requests.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The ID of the user making the request.
+    /// * `request_type` - The type of request.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::RateLimiter;
+    ///
+    /// let mut limiter = RateLimiter::with_in_memory();
+    /// limiter.add_config("type1", 1, 60000);
+    ///
+    /// // the work failed, so the request is given back.
+    /// let reservation = limiter.reserve("user1", "type1").unwrap();
+    /// drop(reservation);
+    ///
+    /// let reservation = limiter.reserve("user1", "type1").unwrap().expect("the request was given back");
+    /// reservation.commit();
+    /// assert!(limiter.reserve("user1", "type1").unwrap().is_none());
+    /// ```
+    pub fn reserve(&self, user_id: impl AsRef<str>, request_type: impl AsRef<str>) -> Result<Option<Reservation>, RateLimiterError> {
+        let (user_id, request_type) = (user_id.as_ref(), request_type.as_ref());
+        if !self.enabled {
+            return Ok(Some(Reservation::unrecorded()));
+        }
+        let table = self.table.load();
+        let config = self.config(&table, user_id, request_type)?;
+        if !config.tiers.is_empty() || table.budget_members.contains_key(request_type) {
+            return Err(RateLimiterError::Message("reserve is not supported for tiered and shared configs".to_string()));
+        }
+        let key = self.key(user_id, request_type);
+        let window_time = self.window(config, &key, "reserve")?;
+        if config.capacity == 0 || self.is_blocked(user_id, request_type, self.clock.now_millis())? {
+            self.record(request_type, false);
+            return Ok(self.shadow_mode.then(Reservation::unrecorded));
+        }
+        if config.is_unlimited() {
+            self.record(request_type, true);
+            return Ok(Some(Reservation::unrecorded()));
+        }
+
+        let capacity = self.capacity(request_type, config.capacity);
+        let now = config.now(&*self.clock);
+        let result = self.storage.check_and_record(&key, capacity, 1, window_time, now);
+        // a failure mode may allow a request that was never recorded, which has nothing to release.
+        let is_recorded = matches!(result, Ok(true));
+        let is_allowed = self.degrade(request_type, result)?;
+        if !is_allowed {
+            self.add_violation(config, user_id, request_type, self.clock.now_millis())?;
+        }
+        self.record(request_type, is_allowed);
+        Ok(match (is_recorded, self.verdict(is_allowed)) {
+            (true, _) => Some(Reservation::new(Arc::clone(&self.storage), key, now)),
+            (false, true) => Some(Reservation::unrecorded()),
+            (false, false) => None,
+        })
+    }
+
+    /// Takes up to `desired` units of a user's window, as many as are left, and returns how
+    /// many were granted.
+    ///
+    /// Unlike [`allowed_n`](RateLimiter::allowed_n), which is all-or-nothing, a request for more
+    /// than is left is partially granted, e.g. for a proxy that forwards as much of a batch as
+    /// the limit allows. Exactly the granted units are recorded, and a grant of zero counts as a
+    /// denial. In [shadow mode](RateLimiter::with_shadow_mode), `desired` is returned while only
+    /// the units that fit are recorded. Only sliding window configs outside shared
+    /// budgets are supported.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The ID of the user making the request.
+    /// * `request_type` - The type of request.
+    /// * `desired` - The most units to take.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::RateLimiter;
+    ///
+    /// let mut limiter = RateLimiter::with_in_memory();
+    /// limiter.add_config("type1", 5, 60000);
+    ///
+    /// assert_eq!(limiter.try_take("user1", "type1", 3).unwrap(), 3);
+    /// assert_eq!(limiter.try_take("user1", "type1", 3).unwrap(), 2);
+    /// assert_eq!(limiter.try_take("user1", "type1", 3).unwrap(), 0);
+    /// ```
+    pub fn try_take(&self, user_id: impl AsRef<str>, request_type: impl AsRef<str>, desired: u32) -> Result<u32, RateLimiterError> {
+        let (user_id, request_type) = (user_id.as_ref(), request_type.as_ref());
+        if !self.enabled {
+            return Ok(desired);
+        }
+        let table = self.table.load();
+        let config = self.config(&table, user_id, request_type)?;
+        if !config.tiers.is_empty() || table.budget_members.contains_key(request_type) {
+            return Err(RateLimiterError::Message("try_take is not supported for tiered and shared configs".to_string()));
+        }
+        let key = self.key(user_id, request_type);
+        let window_time = self.window(config, &key, "try_take")?;
+        if desired == 0 {
+            return Ok(0);
+        }
+        if config.is_unlimited() && config.capacity > 0 {
+            self.record(request_type, true);
+            return Ok(desired);
+        }
+        if config.capacity == 0 || self.is_blocked(user_id, request_type, self.clock.now_millis())? {
+            self.record(request_type, false);
+            return Ok(if self.shadow_mode { desired } else { 0 });
+        }
+
+        let capacity = self.capacity(request_type, config.capacity);
+        let now = config.now(&*self.clock);
+        let granted = match (self.storage.take_available(&key, capacity, desired, window_time, now), self.failure_mode) {
+            (Ok(granted), _) => granted,
+            (Err(e), Some(mode)) => {
+                if let Some(sink) = &self.metrics {
+                    sink.on_storage_error(request_type, &*e);
+                }
+                if mode == FailureMode::Open { desired } else { 0 }
+            },
+            (Err(e), None) => return Err(e.into()),
+        };
+        if granted == 0 {
+            self.add_violation(config, user_id, request_type, self.clock.now_millis())?;
+        }
+        self.record(request_type, granted > 0);
+        Ok(if self.shadow_mode { desired } else { granted })
+    }
+
+    /// Checks if a request is allowed as [`allowed`](RateLimiter::allowed) does, but against a
+    /// different window for this call only, e.g. to clamp traffic during an incident.
+    ///
+    /// The configured capacity still applies and the stored config is left untouched. Requests
+    /// are recorded in the same log, so a shorter window evicts its older requests, which then
+    /// no longer count once the configured window applies again, and a longer window only
+    /// sees the requests the configured one has kept. Only single sliding window configs outside
+    /// shared budgets are supported. Penalties apply as they do to [`allowed`](RateLimiter::allowed).
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The ID of the user making the request.
+    /// * `request_type` - The type of request.
+    /// * `window_override_millis` - The length of the sliding window to use for this call.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::RateLimiter;
+    ///
+    /// let mut limiter = RateLimiter::with_in_memory();
+    /// limiter.add_config("type1", 1, 1000);
+    ///
+    /// assert!(limiter.allowed_with_window("user1", "type1", 60000).unwrap());
+    /// assert!(!limiter.allowed_with_window("user1", "type1", 60000).unwrap());
+    /// assert_eq!(limiter.get_config("type1").unwrap().1.as_millis(), 1000);
+    /// ```
+    pub fn allowed_with_window(&self, user_id: impl AsRef<str>, request_type: impl AsRef<str>, window_override_millis: u64) -> Result<bool, RateLimiterError> {
+        let (user_id, request_type) = (user_id.as_ref(), request_type.as_ref());
+        if !self.enabled {
+            return Ok(true);
+        }
+        let table = self.table.load();
+        let config = self.config(&table, user_id, request_type)?;
+        config.window_time("allowed_with_window")?;
+        if !config.tiers.is_empty() || table.budget_members.contains_key(request_type) {
+            return Err(RateLimiterError::Message("allowed_with_window is not supported for tiered and shared configs".to_string()));
+        }
+
+        let now_millis = self.clock.now_millis();
+        if self.is_blocked(user_id, request_type, now_millis)? {
+            self.record(request_type, false);
+            return Ok(self.verdict(false));
+        }
+
+        let now = config.now(&*self.clock);
+        let key = self.key(user_id, request_type);
+        let window_time = self.jittered(config, &key, config.storage_window(Duration::from_millis(window_override_millis)));
+        let capacity = self.capacity(request_type, config.capacity);
+        let is_allowed = self.degrade(request_type, self.storage.check_and_record(&key, capacity, 1, window_time, now))?;
+        if !is_allowed {
+            self.add_violation(config, user_id, request_type, now_millis)?;
+        }
+        self.record(request_type, is_allowed);
+        Ok(self.verdict(is_allowed))
+    }
+
+    /// Checks if a request is allowed under every one of `request_types`, recording it against
+    /// all of them or none.
+    ///
+    /// If any type is out of capacity, the request is denied and no log is touched, so a
+    /// request that fails one limit does not use up the others. Only sliding window configs
+    /// are supported, and request types listed more than once are checked once. On a Redis
+    /// Cluster, the keys of all the request types must hash to the same slot.
+    ///
+    /// A user blocked by the penalty of any of the types is denied, and a denial counts as a
+    /// violation towards the penalty of every one of them.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The ID of the user making the request.
+    /// * `request_types` - The types of request that must all allow it.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(true)` if every request type allows the request.
+    /// * `Ok(false)` if at least one request type denies it.
+    /// * `Err(RateLimiterError::UnknownRequestType)` if any of `request_types` has no config and no default config is set.
+    /// * `Err` if any other error occurs.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::RateLimiter;
+    ///
+    /// let mut limiter = RateLimiter::with_in_memory();
+    /// limiter.add_config("per_user", 5, 60000).add_config("per_endpoint", 1, 60000);
+    ///
+    /// assert!(limiter.allowed_all("user1", &["per_user", "per_endpoint"]).unwrap());
+    /// assert!(!limiter.allowed_all("user1", &["per_user", "per_endpoint"]).unwrap());
+    /// assert_eq!(limiter.remaining("user1", "per_user").unwrap(), 4);
+    /// ```
+    pub fn allowed_all(&self, user_id: &str, request_types: &[&str]) -> Result<bool, RateLimiterError> {
+        if !self.enabled {
+            return Ok(true);
+        }
+
+        let mut unique_types: Vec<&str> = Vec::with_capacity(request_types.len());
+        for &request_type in request_types {
+            if !unique_types.contains(&request_type) {
+                unique_types.push(request_type);
+            }
+        }
+
+        let mut configs = Vec::with_capacity(unique_types.len());
+        let mut owned_checks = Vec::with_capacity(unique_types.len());
+        let mut resolution = None;
+        let table = self.table.load();
+        for &request_type in &unique_types {
+            let config = self.config(&table, user_id, request_type)?;
+            if *resolution.get_or_insert(config.resolution) != config.resolution {
+                return Err(RateLimiterError::Message("allowed_all cannot mix millisecond and microsecond configs".to_string()));
+            }
+            owned_checks.extend(self.windows(&table, config, user_id, request_type, "allowed_all")?);
+            configs.push(config);
+        }
+        let now = match resolution {
+            Some(Resolution::Micros) => self.clock.now_micros(),
+            _ => self.clock.now_millis(),
+        };
+
+        let now_millis = self.clock.now_millis();
+        for &request_type in &unique_types {
+            if self.is_blocked(user_id, request_type, now_millis)? {
+                for request_type in unique_types {
+                    self.record(request_type, false);
+                }
+                return Ok(self.verdict(false));
+            }
+        }
+
+        let checks: Vec<(&str, u32, Duration)> = owned_checks.iter().map(|(key, capacity, window_time)| (key.as_str(), *capacity, *window_time)).collect();
+        let is_allowed = checks.is_empty() || self.degrade_all(&unique_types, self.storage.check_and_record_all(&checks, now))?;
+        if !is_allowed {
+            for (config, request_type) in configs.iter().zip(&unique_types) {
+                self.add_violation(config, user_id, request_type, now_millis)?;
+            }
+        }
+
+        for request_type in unique_types {
+            self.record(request_type, is_allowed);
+        }
+        Ok(self.verdict(is_allowed))
+    }
+
+    /// Checks a request of a user within a tenant against both the user's own limit and the
+    /// tenant's aggregate limit from [`add_tenant_config`](RateLimiter::add_tenant_config).
+    ///
+    /// The request is only allowed if both have room for it, and is then recorded against both
+    /// atomically; a denial records nothing. Both limits must be sliding windows in milliseconds.
+    /// Penalties apply to the user as they do in [`allowed`](RateLimiter::allowed).
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant_id` - The ID of the tenant the user belongs to.
+    /// * `user_id` - The ID of the user making the request.
+    /// * `request_type` - The type of request.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request type has no user or tenant config, or either is not a
+    /// sliding window in milliseconds.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::RateLimiter;
+    ///
+    /// let mut limiter = RateLimiter::with_in_memory();
+    /// limiter.add_config("api", 2, 60000).add_tenant_config("api", 3, 60000);
+    ///
+    /// assert!(limiter.allowed_hierarchical("acme", "alice", "api").unwrap());
+    /// assert!(limiter.allowed_hierarchical("acme", "alice", "api").unwrap());
+    /// // alice is at the user limit.
+    /// assert!(!limiter.allowed_hierarchical("acme", "alice", "api").unwrap());
+    /// assert!(limiter.allowed_hierarchical("acme", "bob", "api").unwrap());
+    /// // and now acme is at its limit.
+    /// assert!(!limiter.allowed_hierarchical("acme", "carol", "api").unwrap());
+    /// ```
+    pub fn allowed_hierarchical(&self, tenant_id: impl AsRef<str>, user_id: impl AsRef<str>, request_type: impl AsRef<str>) -> Result<bool, RateLimiterError> {
+        let (tenant_id, user_id, request_type) = (tenant_id.as_ref(), user_id.as_ref(), request_type.as_ref());
+        if !self.enabled {
+            return Ok(true);
+        }
+
+        let table = self.table.load();
+        let config = self.config(&table, user_id, request_type)?;
+        let tenant_config = self
+            .tenant_configs
+            .get(request_type)
+            .ok_or_else(|| RateLimiterError::Message(format!("no tenant config for request type: {}", request_type)))?;
+        if config.resolution != Resolution::Millis {
+            return Err(RateLimiterError::Message("allowed_hierarchical only supports millisecond configs".to_string()));
+        }
+
+        let mut owned_checks = self.windows(&table, config, user_id, request_type, "allowed_hierarchical")?;
+        let tenant_key = self.decorated_key(&format!("tenant:{}", key_builder::escape(tenant_id)), request_type);
+        let tenant_window = self.window(tenant_config, &tenant_key, "allowed_hierarchical")?;
+        owned_checks.push((tenant_key, self.capacity(request_type, tenant_config.capacity), tenant_window));
+
+        let now = self.clock.now_millis();
+        if self.is_blocked(user_id, request_type, now)? {
+            self.record(request_type, false);
+            return Ok(self.verdict(false));
+        }
+        let checks: Vec<(&str, u32, Duration)> = owned_checks.iter().map(|(key, capacity, window_time)| (key.as_str(), *capacity, *window_time)).collect();
+        let is_allowed = self.degrade(request_type, self.storage.check_and_record_all(&checks, now))?;
+        if !is_allowed {
+            self.add_violation(config, user_id, request_type, now)?;
+        }
+        self.record(request_type, is_allowed);
+        Ok(self.verdict(is_allowed))
+    }
+
+    /// Checks one request for each of `user_ids` against a request type, returning the
+    /// decisions in the same order.
+    ///
+    /// Sliding window checks for the whole batch go to storage at once: the in-memory backend
+    /// locks each shard once, and Redis pipelines the scripts into a single round trip. Users
+    /// whose config uses another algorithm or several tiers, and request types with a penalty,
+    /// a shared budget or a threshold, are checked one by one through
+    /// [`allowed`](RateLimiter::allowed). A user id that appears more than once makes one
+    /// request per appearance.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_ids` - The IDs of the users making a request.
+    /// * `request_type` - The type of request.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::RateLimiter;
+    ///
+    /// let mut limiter = RateLimiter::with_in_memory();
+    /// limiter.add_config("export", 1, 60000);
+    ///
+    /// let decisions = limiter.allowed_many(&["user1", "user2", "user1"], "export").unwrap();
+    /// assert_eq!(decisions, vec![true, true, false]);
+    /// ```
+    pub fn allowed_many(&self, user_ids: &[&str], request_type: impl AsRef<str>) -> Result<Vec<bool>, RateLimiterError> {
+        let request_type = request_type.as_ref();
+        if !self.enabled {
+            return Ok(vec![true; user_ids.len()]);
+        }
+
+        let mut results = vec![false; user_ids.len()];
+        let mut owned_checks = Vec::with_capacity(user_ids.len());
+        let mut resolution = None;
+        for (position, &user_id) in user_ids.iter().enumerate() {
+            let table = self.table.load();
+            let config = self.config(&table, user_id, request_type)?;
+            let key = self.key(user_id, request_type);
+            let window_time = match self.window(config, &key, "allowed_many") {
+                Ok(window_time) if !self.decided_alone(&table, config, request_type) => window_time,
+                _ => {
+                    results[position] = self.allowed_n(user_id, request_type, 1)?;
+                    continue;
+                },
+            };
+            if *resolution.get_or_insert(config.resolution) != config.resolution {
+                return Err(RateLimiterError::Message("allowed_many cannot mix millisecond and microsecond configs".to_string()));
+            }
+            owned_checks.push((position, key, self.capacity(request_type, config.capacity), window_time));
+        }
+        let now = match resolution {
+            Some(Resolution::Micros) => self.clock.now_micros(),
+            _ => self.clock.now_millis(),
+        };
+
+        let checks: Vec<(&str, u32, Duration)> = owned_checks.iter().map(|(_, key, capacity, window_time)| (key.as_str(), *capacity, *window_time)).collect();
+        let decisions = match checks.is_empty() {
+            true => Vec::new(),
+            false => match (self.storage.check_and_record_each(&checks, now), self.failure_mode) {
+                (Ok(decisions), _) => decisions,
+                (Err(e), Some(mode)) => {
+                    if let Some(sink) = &self.metrics {
+                        sink.on_storage_error(request_type, &*e);
+                    }
+                    vec![mode == FailureMode::Open; checks.len()]
+                },
+                (Err(e), None) => return Err(e.into()),
+            },
+        };
+
+        for ((position, ..), is_allowed) in owned_checks.iter().zip(decisions) {
+            results[*position] = self.verdict(is_allowed);
+            self.record(request_type, is_allowed);
+        }
+        Ok(results)
+    }
+
+    /// Checks if a request is allowed without blocking the async runtime.
+    ///
+    /// Behaves like [`allowed`](RateLimiter::allowed), but goes through the storage's async
+    /// API; the Redis backend uses a shared `redis::aio::MultiplexedConnection`.
+    ///
+    /// Request types with several tiers, a penalty, a shared budget or a threshold need several
+    /// round trips, and are still decided through the synchronous [`allowed`](RateLimiter::allowed).
+    /// Those calls block the runtime thread while they wait on storage, and fail on a limiter
+    /// created with [`with_async_storage`](RateLimiter::with_async_storage).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::RateLimiter;
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let mut limiter = RateLimiter::with_in_memory();
+    /// limiter.add_config("type1", 5, 60000);
+    /// let is_allowed = limiter.allowed_async("user1", "type1").await.unwrap();
+    /// # }
+    /// ```
+    #[cfg(feature = "async")]
+    pub async fn allowed_async(&self, user_id: impl AsRef<str>, request_type: impl AsRef<str>) -> Result<bool, RateLimiterError> {
+        let (user_id, request_type) = (user_id.as_ref(), request_type.as_ref());
+        if !self.enabled {
+            return Ok(true);
+        }
+        let table = self.table.load();
+        let config = self.config(&table, user_id, request_type)?;
+        // these are decided without a round trip, or need several of them.
+        if config.capacity == 0 || config.is_unlimited() || self.decided_alone(&table, config, request_type) {
+            return self.allowed_n(user_id, request_type, 1);
+        }
+        let now = config.now(&*self.clock);
+        let key = self.key(user_id, request_type);
+        let capacity = self.capacity(request_type, config.capacity);
+        let result = match config.algorithm {
+            Algorithm::SlidingWindow { window_time } => self.storage.check_and_record_async(&key, capacity, 1, self.jittered(config, &key, config.storage_window(window_time)), now).await,
+            Algorithm::TokenBucket { refill_per_sec } => self.storage.take_tokens_async(&key, capacity, refill_per_sec, 1, now).await,
+            Algorithm::FixedWindow { window_time } => self.storage.increment_window_async(&key, capacity, 1, window_time, now).await,
+            Algorithm::SlidingCounter { window_time } => self.storage.increment_sliding_counter_async(&key, capacity, 1, window_time, now).await,
+            Algorithm::LeakyBucket { leak_per_sec } => self.storage.leak_and_fill_async(&key, capacity, leak_per_sec, 1, now).await,
+            Algorithm::Gcra { rate_per_sec } => self.storage.advance_tat_async(&key, capacity, rate_per_sec, 1, now).await,
+        };
+        let is_allowed = self.degrade(request_type, result.map_err(|e| e as Box<dyn Error>))?;
+        self.record(request_type, is_allowed);
+        Ok(self.verdict(is_allowed))
+    }
+
+    /// Checks if a request is allowed, waiting until it is or `max_wait` has passed without
+    /// blocking the async runtime, e.g. to pace outbound calls to an API under its rate limit.
+    ///
+    /// The async counterpart of [`allowed_blocking`](RateLimiter::allowed_blocking): checks go
+    /// through [`allowed_async`](RateLimiter::allowed_async), and the waits in between through
+    /// `tokio::time::sleep`, so the same caveats about concurrent waiters apply. Working out the
+    /// wait with [`retry_after`](RateLimiter::retry_after) would mean a blocking round trip, so
+    /// the request is checked again every few milliseconds instead.
+    ///
+    /// Dropping the future gives up the wait: a request is only recorded by the check that
+    /// allows it, so a waiter cancelled while asleep, e.g. by `tokio::time::timeout`, consumes
+    /// nothing. Only a check already sent to Redis when the future is dropped may still record
+    /// its request.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The ID of the user making the request.
+    /// * `request_type` - The type of request.
+    /// * `max_wait` - The longest to wait for the request to be allowed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::RateLimiter;
+    /// use std::time::Duration;
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let mut limiter = RateLimiter::with_in_memory();
+    /// limiter.add_config("type1", 1, 50);
+    ///
+    /// assert!(limiter.allowed_async("user1", "type1").await.unwrap());
+    /// assert!(limiter.allowed_wait_async("user1", "type1", Duration::from_secs(1)).await.unwrap());
+    /// # }
+    /// ```
+    #[cfg(feature = "async")]
+    pub async fn allowed_wait_async(&self, user_id: impl AsRef<str>, request_type: impl AsRef<str>, max_wait: Duration) -> Result<bool, RateLimiterError> {
+        let (user_id, request_type) = (user_id.as_ref(), request_type.as_ref());
+        // a wait too long to represent never runs out.
+        let deadline = Instant::now().checked_add(max_wait);
+
+        loop {
+            if self.allowed_async(user_id, request_type).await? {
+                return Ok(true);
+            }
+            let remaining = deadline.map_or(Duration::MAX, |deadline| deadline.saturating_duration_since(Instant::now()));
+            if remaining.is_zero() {
+                return Ok(false);
+            }
+            tokio::time::sleep(BLOCKING_POLL_INTERVAL.min(remaining)).await;
+        }
+    }
+
+    /// Checks if a request is allowed, returning `default` for request types that have no config.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The ID of the user making the request.
+    /// * `request_type` - The type of request.
+    /// * `default` - The verdict to return when `request_type` is not configured.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::RateLimiter;
+    /// 
+    /// let limiter = RateLimiter::with_in_memory();
+    /// let is_allowed = limiter.allowed_or_default("user1", "type1", false).unwrap();
+    /// assert!(!is_allowed);
+    /// ```
+    pub fn allowed_or_default(&self, user_id: impl AsRef<str>, request_type: impl AsRef<str>, default: bool) -> Result<bool, RateLimiterError> {
+        let (user_id, request_type) = (user_id.as_ref(), request_type.as_ref());
+        if self.config(&self.table.load(), user_id, request_type).is_err() {
+            return Ok(default);
+        }
+        self.allowed(user_id, request_type)
+    }
+
+    /// Checks if a request would be allowed, without recording it.
+    ///
+    /// Expired entries are evicted first, so the verdict matches what
+    /// [`allowed`](RateLimiter::allowed) would return right now.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The ID of the user making the request.
+    /// * `request_type` - The type of request.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::RateLimiter;
+    ///
+    /// let mut limiter = RateLimiter::with_in_memory();
+    /// limiter.add_config("type1", 1, 60000);
+    /// assert!(limiter.peek("user1", "type1").unwrap());
+    /// assert!(limiter.peek("user1", "type1").unwrap());
+    ///
+    /// limiter.allowed("user1", "type1").unwrap();
+    /// assert!(!limiter.peek("user1", "type1").unwrap());
+    /// ```
+    pub fn peek(&self, user_id: impl AsRef<str>, request_type: impl AsRef<str>) -> Result<bool, RateLimiterError> {
+        let (user_id, request_type) = (user_id.as_ref(), request_type.as_ref());
+        let table = self.table.load();
+        let config = self.config(&table, user_id, request_type)?;
+        let now = config.now(&*self.clock);
+        for (key, capacity, window_time) in self.windows(&table, config, user_id, request_type, "peek")? {
+            if self.storage.count(&key, window_time, now)? >= capacity {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Returns the timestamps of the requests counted in a user's window, oldest first, e.g. to
+    /// show a user who complains about being throttled exactly which requests count against them.
+    ///
+    /// Expired entries are evicted first, and nothing is recorded. Timestamps are in milliseconds
+    /// since the unix epoch, or microseconds for [`add_config_micros`](RateLimiter::add_config_micros);
+    /// bucketed Redis logs list every request at the end of its bucket. Only sliding window
+    /// configs are supported.
+    ///
+    /// This is a diagnostic: it copies the whole window, which for Redis means transferring a
+    /// sorted set that may hold up to `capacity` members, so keep it off hot paths.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The ID of the user making the request.
+    /// * `request_type` - The type of request.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::{MockClock, RateLimiter};
+    /// use std::time::Duration;
+    ///
+    /// let clock = MockClock::new(1000);
+    /// let mut limiter = RateLimiter::with_in_memory_clock(clock.clone());
+    /// limiter.add_config("type1", 5, 60000);
+    /// limiter.allowed("user1", "type1").unwrap();
+    /// clock.advance(Duration::from_millis(250));
+    /// limiter.allowed("user1", "type1").unwrap();
+    ///
+    /// assert_eq!(limiter.inspect("user1", "type1").unwrap(), vec![1000, 1250]);
+    /// ```
+    pub fn inspect(&self, user_id: impl AsRef<str>, request_type: impl AsRef<str>) -> Result<Vec<u64>, RateLimiterError> {
+        let (user_id, request_type) = (user_id.as_ref(), request_type.as_ref());
+        let table = self.table.load();
+        let config = self.config(&table, user_id, request_type)?;
+        if !config.tiers.is_empty() {
+            return Err(RateLimiterError::Message("inspect is not supported for tiered configs".to_string()));
+        }
+        let key = self.key(user_id, request_type);
+        let window_time = self.window(config, &key, "inspect")?;
+        let now = config.now(&*self.clock);
+        Ok(self.storage.timestamps(&key, window_time, now)?)
+    }
+
+    /// Returns how many more requests a user can make in the current window.
+    ///
+    /// Expired entries are evicted, but no request is recorded, so this does not consume quota.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The ID of the user making the request.
+    /// * `request_type` - The type of request.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::RateLimiter;
+    /// 
+    /// let mut limiter = RateLimiter::with_in_memory();
+    /// limiter.add_config("type1", 5, 60000);
+    /// limiter.allowed("user1", "type1").unwrap();
+    /// assert_eq!(limiter.remaining("user1", "type1").unwrap(), 4);
+    /// ```
+    pub fn remaining(&self, user_id: impl AsRef<str>, request_type: impl AsRef<str>) -> Result<u32, RateLimiterError> {
+        let (user_id, request_type) = (user_id.as_ref(), request_type.as_ref());
+        let table = self.table.load();
+        let config = self.config(&table, user_id, request_type)?;
+        let now = config.now(&*self.clock);
+        let mut remaining = u32::MAX;
+        for (key, capacity, window_time) in self.windows(&table, config, user_id, request_type, "remaining")? {
+            let request_count = self.storage.count(&key, window_time, now)?;
+            remaining = remaining.min(capacity.saturating_sub(request_count));
+        }
+        Ok(remaining)
+    }
+
+    /// Returns the capacity a user's requests are currently checked against, e.g. for an
+    /// `X-RateLimit-Limit` header.
+    ///
+    /// This resolves the user's override, the schedule slot in effect and the warm-up ramp to
+    /// the number [`allowed`](RateLimiter::allowed) enforces right now. For tiered configs it is
+    /// the capacity of the first window, and for token and leaky buckets the bucket size.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The ID of the user making the request.
+    /// * `request_type` - The type of request.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::RateLimiter;
+    ///
+    /// let mut limiter = RateLimiter::with_in_memory();
+    /// limiter.add_config("type1", 5, 60000).add_user_override("user2", "type1", 50, 60000);
+    ///
+    /// assert_eq!(limiter.effective_capacity("user1", "type1").unwrap(), 5);
+    /// assert_eq!(limiter.effective_capacity("user2", "type1").unwrap(), 50);
+    /// ```
+    pub fn effective_capacity(&self, user_id: impl AsRef<str>, request_type: impl AsRef<str>) -> Result<u32, RateLimiterError> {
+        let (user_id, request_type) = (user_id.as_ref(), request_type.as_ref());
+        let table = self.table.load();
+        let config = self.config(&table, user_id, request_type)?;
+        Ok(self.capacity(request_type, config.capacity))
+    }
+
+    /// Returns how long a user has to wait before their next request would be allowed.
+    ///
+    /// Mirrors the `Retry-After` HTTP header: the wait is the time until the oldest
+    /// request in the window expires, or in the last of the full windows of a tiered config
+    /// or a shared budget.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The ID of the user making the request.
+    /// * `request_type` - The type of request.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(None)` if a request would currently be allowed.
+    /// * `Ok(Some(duration))` if the user has to wait `duration` before retrying.
+    /// * `Err` if an error occurs.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::RateLimiter;
+    /// 
+    /// let mut limiter = RateLimiter::with_in_memory();
+    /// limiter.add_config("type1", 1, 60000);
+    /// assert!(limiter.retry_after("user1", "type1").unwrap().is_none());
+    ///
+    /// limiter.allowed("user1", "type1").unwrap();
+    /// assert!(limiter.retry_after("user1", "type1").unwrap().is_some());
+    /// ```
+    pub fn retry_after(&self, user_id: impl AsRef<str>, request_type: impl AsRef<str>) -> Result<Option<Duration>, RateLimiterError> {
+        let (user_id, request_type) = (user_id.as_ref(), request_type.as_ref());
+        let table = self.table.load();
+        let config = self.config(&table, user_id, request_type)?;
+        let now = config.now(&*self.clock);
+        let unblocked_at = self.unblocked_at(&table, config, user_id, request_type, now, "retry_after")?;
+        Ok(unblocked_at.map(|unblocked_at| config.duration(unblocked_at.saturating_sub(now))))
+    }
+
+    /// Returns the unix time in milliseconds at which a user's next request would be allowed,
+    /// like the `X-RateLimit-Reset` header.
+    ///
+    /// This is the absolute counterpart of [`retry_after`](RateLimiter::retry_after), on the
+    /// limiter's clock.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The ID of the user making the request.
+    /// * `request_type` - The type of request.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(None)` if a request would currently be allowed.
+    /// * `Ok(Some(timestamp))` if the user has to wait until `timestamp` before retrying.
+    /// * `Err` if an error occurs.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::{MockClock, RateLimiter};
+    ///
+    /// let mut limiter = RateLimiter::with_in_memory_clock(MockClock::new(1_000_000));
+    /// limiter.add_config("type1", 1, 60000);
+    /// assert_eq!(limiter.reset_at("user1", "type1").unwrap(), None);
+    ///
+    /// limiter.allowed("user1", "type1").unwrap();
+    /// assert_eq!(limiter.reset_at("user1", "type1").unwrap(), Some(1_060_000));
+    /// ```
+    pub fn reset_at(&self, user_id: impl AsRef<str>, request_type: impl AsRef<str>) -> Result<Option<u64>, RateLimiterError> {
+        let (user_id, request_type) = (user_id.as_ref(), request_type.as_ref());
+        let table = self.table.load();
+        let config = self.config(&table, user_id, request_type)?;
+        let now = config.now(&*self.clock);
+        let unblocked_at = self.unblocked_at(&table, config, user_id, request_type, now, "reset_at")?;
+        Ok(unblocked_at.map(|unblocked_at| u64::try_from(config.duration(unblocked_at).as_millis()).unwrap_or(u64::MAX)))
+    }
+
+    /// Clears a user's state for a request type, immediately restoring their full capacity.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The ID of the user to reset.
+    /// * `request_type` - The type of request to reset.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::RateLimiter;
+    /// 
+    /// let mut limiter = RateLimiter::with_in_memory();
+    /// limiter.add_config("type1", 1, 60000);
+    /// limiter.allowed("user1", "type1").unwrap();
+    ///
+    /// limiter.reset("user1", "type1").unwrap();
+    /// assert!(limiter.allowed("user1", "type1").unwrap());
+    /// ```
+    pub fn reset(&self, user_id: impl AsRef<str>, request_type: impl AsRef<str>) -> Result<(), RateLimiterError> {
+        let (user_id, request_type) = (user_id.as_ref(), request_type.as_ref());
+        let table = self.table.load();
+        if let Ok(config) = self.config(&table, user_id, request_type) {
+            for &(_, window_time) in &config.tiers {
+                self.storage.remove(&self.tier_key(user_id, request_type, window_time))?;
+            }
+        }
+        if self.penalties.contains_key(request_type) {
+            self.storage.remove(&self.decorated_key(&format!("{}:violations", key_builder::escape(user_id)), request_type))?;
+            self.storage.remove(&self.decorated_key(&format!("{}:blocked", key_builder::escape(user_id)), request_type))?;
+        }
+        Ok(self.storage.remove(&self.key(user_id, request_type))?)
+    }
+
+    /// Clears a user's state for every configured request type, immediately restoring their
+    /// full capacity everywhere.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The ID of the user to reset.
+    pub fn reset_all(&self, user_id: impl AsRef<str>) -> Result<(), RateLimiterError> {
+        let user_id = user_id.as_ref();
+        let overridden_types = self
+            .overrides
+            .iter()
+            .filter(|(request_type, overrides)| overrides.contains_key(user_id) && !self.is_configured_explicitly(request_type))
+            .map(|(request_type, _)| request_type);
+
+        for request_type in self.configured_types().iter().chain(overridden_types) {
+            self.reset(user_id, request_type)?;
+        }
+        Ok(())
+    }
+
+    /// Returns how many keys and entries the storage holds, and how many request types are
+    /// configured, e.g. to diagnose memory usage.
+    ///
+    /// Expired state still counts until it is evicted, so comparing stats before and after
+    /// [`evict_idle`](RateLimiter::evict_idle) shows whether eviction keeps up. Only the in-memory,
+    /// sled and cached storages support this; Redis returns an error rather than scan its keyspace.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::{LimiterStats, RateLimiter};
+    ///
+    /// let mut limiter = RateLimiter::with_in_memory();
+    /// limiter.add_config("type1", 5, 60000).add_config("type2", 5, 60000);
+    /// limiter.allowed("user1", "type1").unwrap();
+    /// limiter.allowed("user1", "type1").unwrap();
+    /// limiter.allowed("user2", "type1").unwrap();
+    ///
+    /// assert_eq!(limiter.stats().unwrap(), LimiterStats { tracked_users: 2, total_entries: 3, configured_types: 2 });
+    /// ```
+    pub fn stats(&self) -> Result<LimiterStats, RateLimiterError> {
+        let (tracked_users, total_entries) = self.storage.usage()?;
+        Ok(LimiterStats { tracked_users, total_entries, configured_types: self.configured_types().len() })
+    }
+
+    /// Deletes the state of users that have been idle for longer than the longest configured
+    /// window, returning how many keys were removed.
+    ///
+    /// Only state that can no longer affect a decision is removed. For token and leaky
+    /// buckets, the window is the time it takes to refill (or drain) the whole bucket. Backends
+    /// that expire keys on their own, like Redis, remove nothing.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::{MockClock, RateLimiter};
+    /// use std::time::Duration;
+    ///
+    /// let clock = MockClock::new(0);
+    /// let mut limiter = RateLimiter::with_in_memory_clock(clock.clone());
+    /// limiter.add_config("type1", 5, 1000);
+    /// limiter.allowed("user1", "type1").unwrap();
+    ///
+    /// clock.advance(Duration::from_millis(1001));
+    /// assert_eq!(limiter.evict_idle().unwrap(), 1);
+    /// ```
+    pub fn evict_idle(&self) -> Result<u64, RateLimiterError> {
+        let table = self.table.load();
+        let max_idle = table
+            .configs
+            .values()
+            .chain(self.overrides.values().flat_map(|overrides| overrides.values()))
+            .chain(self.default_config.as_ref())
+            .chain(self.tenant_configs.values())
+            .chain(self.schedules.values().flat_map(|(_, configs)| configs))
+            .chain(self.budgets.values())
+            .map(Config::longest_period)
+            .chain(self.penalties.values().map(|&(_, block_duration)| block_duration))
+            .max();
+
+        match max_idle {
+            Some(max_idle) => Ok(self.storage.remove_idle(max_idle, self.clock.now_millis())?),
+            // without any config, there is no telling which state still matters.
+            None => Ok(0),
+        }
+    }
+
+    /// Spawns a thread that calls [`evict_idle`](RateLimiter::evict_idle) every `interval`,
+    /// so the in-memory storage does not keep growing with users that come and go.
+    ///
+    /// The thread stops when the returned [`GcHandle`] is dropped, or on its next wake-up
+    /// after the last `Arc` of the limiter is dropped, as it only holds a weak reference.
+    /// Eviction errors are ignored and retried next time.
+    ///
+    /// # Arguments
+    ///
+    /// * `interval` - How long to wait between two sweeps.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::RateLimiter;
+    /// use std::sync::Arc;
+    /// use std::time::Duration;
+    ///
+    /// let mut limiter = RateLimiter::with_in_memory();
+    /// limiter.add_config("type1", 5, 60000);
+    ///
+    /// let limiter = Arc::new(limiter);
+    /// let gc = limiter.start_gc(Duration::from_secs(60));
+    /// // stops and joins the thread.
+    /// drop(gc);
+    /// ```
+    pub fn start_gc(self: &Arc<Self>, interval: Duration) -> GcHandle {
+        let limiter = Arc::downgrade(self);
+        GcHandle::spawn(interval, move || match limiter.upgrade() {
+            Some(limiter) => {
+                let _ = limiter.evict_idle();
+                true
+            },
+            None => false,
+        })
+    }
+
+    /// Builds the storage key for a user and request type with the key builder, e.g.
+    /// `myapp:user1:type1`.
+    fn key(&self, user_id: &str, request_type: &str) -> String {
+        self.decorated_key(&key_builder::escape(user_id), request_type)
+    }
+
+    /// Builds the storage key of a user id that is already escaped and possibly decorated,
+    /// e.g. `user1:60000ms` for a tier.
+    fn decorated_key(&self, decorated_id: &str, request_type: &str) -> String {
+        self.raw_key(decorated_id, &key_builder::escape(request_type))
+    }
+
+    /// Builds a storage key from a user id and request type that are both already escaped,
+    /// so a `:` left in the request type can only be a decoration, e.g. of a shared budget.
+    fn raw_key(&self, decorated_id: &str, decorated_type: &str) -> String {
+        let key = self.key_builder.build(decorated_id, decorated_type);
+        match &self.key_prefix {
+            Some(prefix) => format!("{}:{}", prefix, key),
+            None => key,
+        }
+    }
+
+    /// Turns a storage error into a decision if a failure mode is set, reporting the error.
+    fn degrade(&self, request_type: &str, result: Result<bool, Box<dyn Error>>) -> Result<bool, RateLimiterError> {
+        self.degrade_all(&[request_type], result)
+    }
+
+    /// Like [`degrade`](RateLimiter::degrade), for a decision covering several request types.
+    fn degrade_all(&self, request_types: &[&str], result: Result<bool, Box<dyn Error>>) -> Result<bool, RateLimiterError> {
+        match (result, self.failure_mode) {
+            (Err(e), Some(mode)) => {
+                if let Some(sink) = &self.metrics {
+                    for request_type in request_types {
+                        sink.on_storage_error(request_type, &*e);
+                    }
+                }
+                Ok(mode == FailureMode::Open)
+            },
+            (result, _) => Ok(result?),
+        }
+    }
+
+    /// Returns the capacity currently in effect for a request type, scaled down while it is
+    /// warming up.
+    fn capacity(&self, request_type: &str, capacity: u32) -> u32 {
+        let Some(&ramp_duration) = self.warmups.get(request_type) else {
+            return capacity;
+        };
+        let ramp_millis = ramp_duration.as_millis();
+        let elapsed_millis = u128::from(self.clock.now_millis().saturating_sub(self.created_at));
+        if elapsed_millis >= ramp_millis {
+            return capacity;
+        }
+
+        let floor = (capacity / 10).max(1).min(capacity);
+        let ramped = u128::from(capacity.saturating_sub(floor)) * elapsed_millis / ramp_millis;
+        floor + ramped as u32
+    }
+
+    /// Returns whether requests of a type need [`decide`](RateLimiter::decide) to themselves,
+    /// because they span several windows or are tracked beyond their own log, so batched and
+    /// async checks fall back to it.
+    fn decided_alone(&self, table: &ConfigTable, config: &Config, request_type: &str) -> bool {
+        !config.tiers.is_empty() || self.penalties.contains_key(request_type) || table.budget_members.contains_key(request_type) || self.thresholds.contains_key(request_type)
+    }
+
+    /// Returns whether a penalty blocks the user from a request type at `now_millis`.
+    fn is_blocked(&self, user_id: &str, request_type: &str, now_millis: u64) -> Result<bool, RateLimiterError> {
+        let Some(&(_, block_duration)) = self.penalties.get(request_type) else {
+            return Ok(false);
+        };
+        let key = self.decorated_key(&format!("{}:blocked", key_builder::escape(user_id)), request_type);
+        match self.storage.count(&key, block_duration, now_millis) {
+            Ok(block_count) => Ok(block_count > 0),
+            // with a failure mode, the check that follows decides the request instead.
+            Err(_) if self.failure_mode.is_some() => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Counts a denial towards the penalty of a request type, blocking the user once they
+    /// reach its threshold within the config's window.
+    ///
+    /// Violations are a sliding window log that holds one less than the threshold, so the
+    /// denial it has no room for is the one that triggers the block.
+    fn add_violation(&self, config: &Config, user_id: &str, request_type: &str, now: u64) -> Result<(), RateLimiterError> {
+        let Some(&(violations_threshold, block_duration)) = self.penalties.get(request_type) else {
+            return Ok(());
+        };
+        let violations_key = self.decorated_key(&format!("{}:violations", key_builder::escape(user_id)), request_type);
+        let result = self.storage.check_and_record(&violations_key, violations_threshold - 1, 1, config.longest_period(), now).and_then(|is_recorded| {
+            if is_recorded {
+                return Ok(());
+            }
+            self.storage.check_and_record(&self.decorated_key(&format!("{}:blocked", key_builder::escape(user_id)), request_type), 1, 1, block_duration, now)?;
+            // the violations that led to the block do not count towards the next one.
+            self.storage.remove(&violations_key)
+        });
+        match result {
+            // with a failure mode, the denial stands even if it could not be counted.
+            Err(_) if self.failure_mode.is_some() => Ok(()),
+            result => Ok(result?),
+        }
+    }
+
+    /// Returns when, in the config's unit, the oldest request of every full tier has expired
+    /// so a request is allowed again, or `None` if one is allowed now.
+    fn unblocked_at(&self, table: &ConfigTable, config: &Config, user_id: &str, request_type: &str, now: u64, operation: &str) -> Result<Option<u64>, RateLimiterError> {
+        let mut unblocked_at = None;
+        for (key, capacity, window_time) in self.windows(table, config, user_id, request_type, operation)? {
+            let request_count = self.storage.count(&key, window_time, now)?;
+            if request_count < capacity {
+                continue;
+            }
+
+            let window_units = window_time.as_millis() as u64;
+            let expires_at = match self.storage.oldest(&key, window_time, now)? {
+                Some(oldest_timestamp) => oldest_timestamp.saturating_add(window_units),
+                None => now.saturating_add(window_units),
+            };
+            unblocked_at = unblocked_at.max(Some(expires_at));
+        }
+        Ok(unblocked_at)
+    }
+
+    /// Builds the storage key of a further tier, e.g. `myapp:user1:60000ms:type1`, which still
+    /// ends with the request type so purging the type covers it.
+    fn tier_key(&self, user_id: &str, request_type: &str, window_time: Duration) -> String {
+        self.decorated_key(&format!("{}:{}ms", key_builder::escape(user_id), window_time.as_millis()), request_type)
+    }
+
+    /// Returns the key of a user's share of the budget a request type draws from, and the
+    /// budget's config, if it draws from one.
+    fn budget(&self, table: &ConfigTable, user_id: &str, request_type: &str) -> Option<(String, &Config)> {
+        let budget_id = table.budget_members.get(request_type)?;
+        let config = self.budgets.get(budget_id)?;
+        Some((self.raw_key(&key_builder::escape(user_id), &format!("budget:{}", key_builder::escape(budget_id))), config))
+    }
+
+    /// Returns the `(key, capacity, window)` of every sliding window a config enforces for a
+    /// user, starting with its main one and ending with the user's share of a shared budget,
+    /// or an error naming `operation` for other algorithms.
+    fn windows(&self, table: &ConfigTable, config: &Config, user_id: &str, request_type: &str, operation: &str) -> Result<Vec<(String, u32, Duration)>, RateLimiterError> {
+        let key = self.key(user_id, request_type);
+        let window_time = self.window(config, &key, operation)?;
+
+        let mut windows = Vec::with_capacity(1 + config.tiers.len());
+        windows.push((key, self.capacity(request_type, config.capacity), window_time));
+        for &(capacity, window_time) in &config.tiers {
+            let key = self.tier_key(user_id, request_type, window_time);
+            let window_time = self.jittered(config, &key, config.storage_window(window_time));
+            windows.push((key, self.capacity(request_type, capacity), window_time));
+        }
+        if let Some((budget_key, budget_config)) = self.budget(table, user_id, request_type) {
+            if config.resolution != Resolution::Millis {
+                return Err(RateLimiterError::Message("shared budgets only support millisecond configs".to_string()));
+            }
+            let budget_window = self.window(budget_config, &budget_key, operation)?;
+            windows.push((budget_key, budget_config.capacity, budget_window));
+        }
+        Ok(windows)
+    }
+
+    /// Returns the sliding window of `key` as passed to storage, including its jitter.
+    fn window(&self, config: &Config, key: &str, operation: &str) -> Result<Duration, RateLimiterError> {
+        Ok(self.jittered(config, key, config.window_time(operation)?))
+    }
+
+    /// Adds the eviction jitter of `key` to a window already scaled for storage.
+    fn jittered(&self, config: &Config, key: &str, window: Duration) -> Duration {
+        if self.eviction_jitter_millis == 0 {
+            return window;
+        }
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let jitter_millis = hasher.finish() % self.eviction_jitter_millis.saturating_add(1);
+        window.saturating_add(config.storage_window(Duration::from_millis(jitter_millis)))
+    }
+
+    /// Reports a decision to the metrics sink, if one is registered.
+    fn record(&self, request_type: &str, is_allowed: bool) {
+        match &self.metrics {
+            Some(sink) if is_allowed => sink.on_allowed(request_type),
+            Some(sink) => sink.on_denied(request_type),
+            None => {},
+        }
+    }
+
+    /// Returns the verdict to hand back for a decision, which shadow mode turns into an allow.
+    fn verdict(&self, is_allowed: bool) -> bool {
+        is_allowed || self.shadow_mode
+    }
+
+    /// Returns the config that applies to `user_id`: their override if any, else the request
+    /// type's, else the default config.
+    fn config<'a>(&'a self, table: &'a ConfigTable, user_id: &str, request_type: &str) -> Result<&'a Config, RateLimiterError> {
+        self.config_at(table, user_id, request_type, self.clock.now_millis())
+    }
+
+    /// Returns the config that applies to `user_id` at `now_millis`, which picks the slot of a schedule.
+    fn config_at<'a>(&'a self, table: &'a ConfigTable, user_id: &str, request_type: &str, now_millis: u64) -> Result<&'a Config, RateLimiterError> {
+        self.overrides
+            .get(request_type)
+            .and_then(|overrides| overrides.get(user_id))
+            .or_else(|| self.scheduled_config(request_type, now_millis))
+            .or_else(|| table.configs.get(request_type))
+            .or(self.default_config.as_ref())
+            .ok_or_else(|| RateLimiterError::UnknownRequestType(request_type.to_string()))
+    }
+
+    /// Returns whether a request type has a config or schedule of its own.
+    fn is_configured_explicitly(&self, request_type: &str) -> bool {
+        self.table.load().configs.contains_key(request_type) || self.schedules.contains_key(request_type)
+    }
+
+    /// Returns the config of the schedule slot of a request type that covers `now_millis`.
+    fn scheduled_config(&self, request_type: &str, now_millis: u64) -> Option<&Config> {
+        let (schedule, configs) = self.schedules.get(request_type)?;
+        let time_of_day = schedule::time_of_day(now_millis);
+        schedule.position(time_of_day).map(|i| &configs[i])
+    }
+
+}
+
+/// Shows the configured request types and the kind of storage, but nothing that names a user:
+/// neither their state nor who has an override.
+impl fmt::Debug for RateLimiter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RateLimiter")
+            .field("request_types", &self.configured_types())
+            .field("default_config", &self.default_config.is_some())
+            .field("overrides", &self.overrides.values().map(HashMap::len).sum::<usize>())
+            .field("storage", &self.storage.kind())
+            .field("key_prefix", &self.key_prefix)
+            .field("enabled", &self.enabled)
+            .field("shadow_mode", &self.shadow_mode)
+            .field("failure_mode", &self.failure_mode)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Reads e.g. `rate limiter for search, uploads on in_memory storage`.
+impl fmt::Display for RateLimiter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let request_types = self.configured_types();
+        if request_types.is_empty() {
+            write!(f, "rate limiter without configs on {} storage", self.storage.kind())
+        } else {
+            write!(f, "rate limiter for {} on {} storage", request_types.join(", "), self.storage.kind())
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use crate::clock::MockClock;
+    use super::*;
+
+    #[test]
+    fn test_with_in_memory(){
+        let clock = MockClock::new(1_000_000);
+        let mut limiter = RateLimiter::with_in_memory_clock(clock.clone());
+        limiter.add_config("type1", 2, 5000).add_config("type2", 10, 30000);
+
+        let user_id = "user12345";
+        for _ in 0..2 {
+            let is_allowed = limiter.allowed(user_id, "type1").unwrap();
+            clock.advance(Duration::from_millis(1000));
+            assert!(is_allowed);
+        }
+
+        // Previous request was two and maximum capacity is 2 within 2s with 5s window. Below we ensure that request 
+        // are no longer allowed because 5s overlaps and there is no enough capacity to handle new requests.
+        for _ in 0..2 {
+            let is_allowed = limiter.allowed(user_id, "type1").unwrap();
+            assert!(!is_allowed);
+            clock.advance(Duration::from_millis(1000));
+        }
+
+        // the first request falls out of the window after 5s.
+        clock.advance(Duration::from_millis(1001));
+        assert!(limiter.allowed(user_id, "type1").unwrap());
+    }
+
+    #[test]
+    fn test_with_custom_storage() {
+        struct DenyAll;
+
+        impl Storage for DenyAll {
+            fn check_and_record(&self, _key: &str, _capacity: u32, _cost: u32, _window: Duration, _now: u64) -> Result<bool, Box<dyn Error>> {
+                Ok(false)
+            }
+
+            fn count(&self, _key: &str, _window: Duration, _now: u64) -> Result<u32, Box<dyn Error>> {
+                Ok(0)
+            }
+
+            fn oldest(&self, _key: &str, _window: Duration, _now: u64) -> Result<Option<u64>, Box<dyn Error>> {
+                Ok(None)
+            }
+
+            fn remove(&self, _key: &str) -> Result<(), Box<dyn Error>> {
+                Ok(())
+            }
+        }
+
+        let mut limiter = RateLimiter::with_storage(DenyAll);
+        limiter.add_config("type1", 2, 5000);
+
+        assert!(!limiter.allowed("user12345", "type1").unwrap());
+    }
+
+    #[test]
+    fn test_window_larger_than_now_does_not_underflow() {
+        let mut limiter = RateLimiter::with_in_memory();
+        limiter.add_config("type1", 1, u64::MAX);
+
+        assert!(limiter.allowed("user12345", "type1").unwrap());
+        assert!(!limiter.allowed("user12345", "type1").unwrap());
+    }
+
+    #[test]
+    fn test_unknown_request_type() {
+        let limiter = RateLimiter::with_in_memory();
+
+        let err = limiter.allowed("user12345", "type1").unwrap_err();
+        assert!(matches!(err, RateLimiterError::UnknownRequestType(request_type) if request_type == "type1"));
+        assert!(limiter.allowed_or_default("user12345", "type1", true).unwrap());
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_allowed_async_with_in_memory() {
+        let mut limiter = RateLimiter::with_in_memory();
+        limiter.add_config("type1", 2, 5000);
+
+        fn assert_send<T: Send>(value: T) -> T {
+            value
+        }
+
+        let user_id = "user12345";
+        assert!(assert_send(limiter.allowed_async(user_id, "type1")).await.unwrap());
+        assert!(limiter.allowed_async(user_id, "type1").await.unwrap());
+        assert!(!limiter.allowed_async(user_id, "type1").await.unwrap());
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_allowed_wait_async() {
+        let mut limiter = RateLimiter::with_in_memory();
+        limiter.add_config("type1", 1, 100);
+
+        fn assert_send<T: Send>(value: T) -> T {
+            value
+        }
+
+        assert!(assert_send(limiter.allowed_wait_async("user1", "type1", Duration::ZERO)).await.unwrap());
+        assert!(!limiter.allowed_wait_async("user1", "type1", Duration::ZERO).await.unwrap());
+
+        // a waiter dropped while asleep leaves the request that freed up to the next one.
+        let started = Instant::now();
+        let cancelled = tokio::time::timeout(Duration::from_millis(20), limiter.allowed_wait_async("user1", "type1", Duration::from_secs(5))).await;
+        assert!(cancelled.is_err());
+        assert!(limiter.allowed_wait_async("user1", "type1", Duration::from_secs(5)).await.unwrap());
+        assert!(started.elapsed() >= Duration::from_millis(90));
+        assert_eq!(limiter.remaining("user1", "type1").unwrap(), 0);
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_with_async_storage() {
+        let mut limiter = RateLimiter::with_async_storage(InMemoryStorage::new());
+        limiter.add_config("type1", 2, 5000);
+
+        let user_id = "user12345";
+        assert!(limiter.allowed_async(user_id, "type1").await.unwrap());
+        assert!(limiter.allowed_async(user_id, "type1").await.unwrap());
+        assert!(!limiter.allowed_async(user_id, "type1").await.unwrap());
+        assert!(limiter.allowed(user_id, "type1").is_err());
+
+        // a penalty is decided through the synchronous API, which this storage lacks.
+        let mut limiter = RateLimiter::with_async_storage(InMemoryStorage::new()).with_penalty("type1", 1, Duration::from_secs(10));
+        limiter.add_config("type1", 2, 5000);
+        assert!(limiter.allowed_async(user_id, "type1").await.is_err());
+    }
+
+    #[test]
+    fn test_remaining_does_not_consume_quota() {
+        let mut limiter = RateLimiter::with_in_memory();
+        limiter.add_config("type1", 2, 5000);
+
+        let user_id = "user12345";
+        assert_eq!(limiter.remaining(user_id, "type1").unwrap(), 2);
+        assert_eq!(limiter.remaining(user_id, "type1").unwrap(), 2);
+
+        assert!(limiter.allowed(user_id, "type1").unwrap());
+        assert_eq!(limiter.remaining(user_id, "type1").unwrap(), 1);
+
+        assert!(limiter.allowed(user_id, "type1").unwrap());
+        assert_eq!(limiter.remaining(user_id, "type1").unwrap(), 0);
+    }
+
+    #[test]
+    fn test_retry_after() {
+        let clock = MockClock::new(1_000_000);
+        let mut limiter = RateLimiter::with_in_memory_clock(clock.clone());
+        limiter.add_config("type1", 1, 5000);
+
+        let user_id = "user12345";
+        assert_eq!(limiter.retry_after(user_id, "type1").unwrap(), None);
+
+        assert!(limiter.allowed(user_id, "type1").unwrap());
+        assert_eq!(limiter.retry_after(user_id, "type1").unwrap(), Some(Duration::from_millis(5000)));
+
+        clock.advance(Duration::from_millis(2000));
+        assert_eq!(limiter.retry_after(user_id, "type1").unwrap(), Some(Duration::from_millis(3000)));
+    }
+
+    #[cfg(feature = "redis")]
+    #[test]
+    fn test_with_redis_invalid_url() {
+        assert!(RateLimiter::with_redis("not a redis url").is_err());
+        assert!(RateLimiter::with_redis_server_time("not a redis url").is_err());
+    }
+
+    #[cfg(feature = "redis")]
+    #[test]
+    fn test_with_redis_tls_url() {
+        // TLS URLs only parse when the crate is built with TLS support.
+        assert_eq!(RateLimiter::with_redis("rediss://127.0.0.1/").is_ok(), cfg!(feature = "tls"));
+    }
+
+    #[cfg(feature = "redis")]
+    #[test]
+    fn test_with_redis_unreachable() {
+        // nothing listens on port 1, so the pool gives up with the refused connection.
+        let client = redis::Client::open("redis://127.0.0.1:1/").unwrap();
+        let mut limiter = RateLimiter::with_storage(RedisStorage::new(client).with_timeout(Duration::from_millis(200)));
+        limiter.add_config("type1", 2, 1000);
+        match limiter.allowed("user1", "type1") {
+            Err(RateLimiterError::Storage(e)) => assert!(e.to_string().contains("refused"), "{}", e),
+            other => panic!("expected a storage error, got {:?}", other),
+        }
+    }
+
+    #[cfg(feature = "cluster")]
+    #[test]
+    fn test_with_redis_cluster_invalid_nodes() {
+        assert!(RateLimiter::with_redis_cluster(&[]).is_err());
+        assert!(RateLimiter::with_redis_cluster(&["not a redis url"]).is_err());
+    }
+
+    #[cfg(feature = "cluster")]
+    #[test]
+    fn test_with_redis_cluster_cross_slot() {
+        // keys in different hash slots are rejected before any connection is made.
+        let mut limiter = RateLimiter::with_redis_cluster(&["redis://127.0.0.1:1/"]).unwrap();
+        limiter.add_config("type1", 2, 1000).add_config("type2", 2, 1000);
+        let err = limiter.allowed_all("user1", &["type1", "type2"]).unwrap_err();
+        assert!(err.to_string().contains("hash slots"), "{}", err);
+    }
+
+    #[test]
+    fn test_token_bucket() {
+        let clock = MockClock::new(1_000_000);
+        let mut limiter = RateLimiter::with_in_memory_clock(clock.clone());
+        limiter.add_config_token_bucket("type1", 2, 1.0);
+
+        let user_id = "user12345";
+        assert!(limiter.allowed(user_id, "type1").unwrap());
+        assert!(limiter.allowed(user_id, "type1").unwrap());
+        assert!(!limiter.allowed(user_id, "type1").unwrap());
+
+        // one token is refilled per second.
+        clock.advance(Duration::from_millis(999));
+        assert!(!limiter.allowed(user_id, "type1").unwrap());
+        clock.advance(Duration::from_millis(1));
+        assert!(limiter.allowed(user_id, "type1").unwrap());
+        assert!(!limiter.allowed(user_id, "type1").unwrap());
+
+        assert!(limiter.remaining(user_id, "type1").is_err());
+    }
+
+    #[test]
+    fn test_gcra() {
+        let clock = MockClock::new(1_000_000);
+        let mut limiter = RateLimiter::with_in_memory_clock(clock.clone());
+        limiter.add_config_gcra("type1", 4.0, 2);
+
+        let user_id = "user12345";
+        assert!(limiter.allowed(user_id, "type1").unwrap());
+        assert!(limiter.allowed(user_id, "type1").unwrap());
+        assert!(!limiter.allowed(user_id, "type1").unwrap());
+
+        // one request is let through every 250ms.
+        clock.advance(Duration::from_millis(249));
+        assert!(!limiter.allowed(user_id, "type1").unwrap());
+        clock.advance(Duration::from_millis(1));
+        assert!(limiter.allowed(user_id, "type1").unwrap());
+        assert!(!limiter.allowed(user_id, "type1").unwrap());
+
+        // a long pause restores the burst, but no more.
+        clock.advance(Duration::from_millis(60000));
+        assert!(!limiter.allowed_n(user_id, "type1", 3).unwrap());
+        assert!(limiter.allowed_n(user_id, "type1", 2).unwrap());
+        assert!(!limiter.allowed(user_id, "type1").unwrap());
+    }
+
+    #[test]
+    fn test_leaky_bucket() {
+        let clock = MockClock::new(1_000_000);
+        let mut limiter = RateLimiter::with_in_memory_clock(clock.clone());
+        limiter.add_config_leaky_bucket("type1", 2.0, 3);
+
+        let user_id = "user12345";
+        for _ in 0..3 {
+            assert!(limiter.allowed(user_id, "type1").unwrap());
+        }
+        assert!(!limiter.allowed(user_id, "type1").unwrap());
+
+        // the bucket drains one request every 500ms.
+        clock.advance(Duration::from_millis(499));
+        assert!(!limiter.allowed(user_id, "type1").unwrap());
+        clock.advance(Duration::from_millis(1));
+        assert!(limiter.allowed(user_id, "type1").unwrap());
+        assert!(!limiter.allowed(user_id, "type1").unwrap());
+
+        // a long pause drains the bucket, but never below empty.
+        clock.advance(Duration::from_millis(60000));
+        assert!(!limiter.allowed_n(user_id, "type1", 4).unwrap());
+        assert!(limiter.allowed_n(user_id, "type1", 3).unwrap());
+        assert!(!limiter.allowed(user_id, "type1").unwrap());
+    }
+
+    #[test]
+    fn test_fixed_window() {
+        let clock = MockClock::new(1000);
+        let mut limiter = RateLimiter::with_in_memory_clock(clock.clone());
+        limiter.add_config_fixed_window("type1", 2, 1000);
+
+        // all requests between 1000 and 1999 fall into the same window.
+        let user_id = "user12345";
+        assert!(limiter.allowed(user_id, "type1").unwrap());
+        clock.set(1500);
+        assert!(limiter.allowed(user_id, "type1").unwrap());
+        clock.set(1999);
+        assert!(!limiter.allowed(user_id, "type1").unwrap());
+
+        // the counter resets as soon as the next window starts.
+        clock.set(2000);
+        assert!(limiter.allowed(user_id, "type1").unwrap());
+    }
+
+    #[test]
+    fn test_add_config_with_kind() {
+        let clock = MockClock::new(1500);
+        let mut limiter = RateLimiter::with_in_memory_clock(clock.clone());
+        limiter
+            .add_config_with_kind("type1", 1, 1000, WindowKind::Rolling)
+            .add_config_with_kind("type2", 1, 1000, WindowKind::Aligned);
+
+        assert!(limiter.allowed("user1", "type1").unwrap());
+        assert!(limiter.allowed("user1", "type2").unwrap());
+
+        // the aligned window started at 1000 and resets at 2000, the rolling one only at 2500.
+        clock.set(2000);
+        assert!(!limiter.allowed("user1", "type1").unwrap());
+        assert!(limiter.allowed("user1", "type2").unwrap());
+        clock.set(2500);
+        assert!(limiter.allowed("user1", "type1").unwrap());
+        assert!(!limiter.allowed("user1", "type2").unwrap());
+    }
+
+    #[test]
+    fn test_sliding_counter() {
+        let clock = MockClock::new(1000);
+        let mut limiter = RateLimiter::with_in_memory_clock(clock.clone());
+        limiter.add_config_sliding_counter("type1", 4, 1000);
+
+        let user_id = "user12345";
+        for _ in 0..4 {
+            assert!(limiter.allowed(user_id, "type1").unwrap());
+        }
+        assert!(!limiter.allowed(user_id, "type1").unwrap());
+
+        // a quarter into the next window, the previous four still weigh in as three.
+        clock.set(2250);
+        assert!(limiter.allowed(user_id, "type1").unwrap());
+        assert!(!limiter.allowed(user_id, "type1").unwrap());
+
+        // halfway, they weigh in as two, next to the one already counted in this window.
+        clock.set(2500);
+        assert!(limiter.allowed(user_id, "type1").unwrap());
+        assert!(!limiter.allowed(user_id, "type1").unwrap());
+
+        // once the sliding window no longer overlaps the first window, only the last two count.
+        clock.set(3000);
+        assert!(limiter.allowed(user_id, "type1").unwrap());
+        assert!(limiter.allowed(user_id, "type1").unwrap());
+        assert!(!limiter.allowed(user_id, "type1").unwrap());
+
+        // after two quiet windows, the user starts over.
+        clock.set(5000);
+        for _ in 0..4 {
+            assert!(limiter.allowed(user_id, "type1").unwrap());
+        }
+    }
+
+    #[test]
+    fn test_user_override() {
+        let clock = MockClock::new(1_000_000);
+        let mut limiter = RateLimiter::with_in_memory_clock(clock.clone());
+        limiter.add_config("type1", 1, 5000).add_user_override("premium1", "type1", 3, 5000);
+
+        for _ in 0..3 {
+            assert!(limiter.allowed("premium1", "type1").unwrap());
+        }
+        assert!(!limiter.allowed("premium1", "type1").unwrap());
+
+        assert!(limiter.allowed("user12345", "type1").unwrap());
+        assert!(!limiter.allowed("user12345", "type1").unwrap());
+
+        // once the override is gone, the recorded requests count against the global capacity.
+        assert!(limiter.remove_user_override("premium1", "type1"));
+        assert!(!limiter.remove_user_override("premium1", "type1"));
+        assert_eq!(limiter.remaining("premium1", "type1").unwrap(), 0);
+
+        clock.advance(Duration::from_millis(5001));
+        assert!(limiter.allowed("premium1", "type1").unwrap());
+        assert!(!limiter.allowed("premium1", "type1").unwrap());
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut limiter = RateLimiter::with_in_memory();
+        limiter.add_config("type1", 1, 5000).add_config("type2", 1, 5000);
+
+        let user_id = "user12345";
+        assert!(limiter.allowed(user_id, "type1").unwrap());
+        assert!(limiter.allowed(user_id, "type2").unwrap());
+        assert!(limiter.allowed("user67890", "type1").unwrap());
+
+        limiter.reset(user_id, "type1").unwrap();
+        assert!(limiter.allowed(user_id, "type1").unwrap());
+        assert!(!limiter.allowed(user_id, "type2").unwrap());
+
+        limiter.reset_all(user_id).unwrap();
+        assert_eq!(limiter.remaining(user_id, "type1").unwrap(), 1);
+        assert_eq!(limiter.remaining(user_id, "type2").unwrap(), 1);
+        assert_eq!(limiter.remaining("user67890", "type1").unwrap(), 0);
+    }
+
+    #[test]
+    fn test_key_prefix() {
+        let storage = InMemoryStorage::new();
+        let mut limiter = RateLimiter::with_storage(storage.clone());
+        limiter.set_key_prefix("myapp").add_config("type1", 1, 5000);
+
+        assert_eq!(limiter.key("user12345", "type1"), "myapp:user12345:type1");
+        assert!(limiter.allowed("user12345", "type1").unwrap());
+        let now = SystemClock.now_millis();
+        assert_eq!(storage.count("myapp:user12345:type1", Duration::from_millis(5000), now).unwrap(), 1);
+        assert_eq!(storage.count("user12345:type1", Duration::from_millis(5000), now).unwrap(), 0);
+
+        limiter.reset("user12345", "type1").unwrap();
+        assert!(limiter.allowed("user12345", "type1").unwrap());
+    }
+
+    #[test]
+    fn test_key_builder() {
+        let storage = InMemoryStorage::new();
+        let mut limiter = RateLimiter::with_storage(storage.clone())
+            .with_key_builder(|user_id: &str, request_type: &str| format!("{}/{}", request_type, user_id));
+        limiter.set_key_prefix("myapp").add_config("type1", 1, 5000);
+
+        assert_eq!(limiter.key("user12345", "type1"), "myapp:type1/user12345");
+        assert!(limiter.allowed("user12345", "type1").unwrap());
+        let now = SystemClock.now_millis();
+        assert_eq!(storage.count("myapp:type1/user12345", Duration::from_millis(5000), now).unwrap(), 1);
+
+        assert_eq!(limiter.remaining("user12345", "type1").unwrap(), 0);
+        limiter.reset("user12345", "type1").unwrap();
+        assert!(limiter.allowed("user12345", "type1").unwrap());
+    }
+
+    #[test]
+    fn test_concurrent_users() {
+        let mut limiter = RateLimiter::with_in_memory();
+        limiter.add_config("type1", 5, 60000);
+        let limiter = std::sync::Arc::new(limiter);
+
+        let handles: Vec<_> = (0..8)
+            .map(|thread_id| {
+                let limiter = std::sync::Arc::clone(&limiter);
+                std::thread::spawn(move || {
+                    let user_id = format!("user{}", thread_id % 4);
+                    (0..10).filter(|_| limiter.allowed(&user_id, "type1").unwrap()).count()
+                })
+            })
+            .collect();
+
+        // two threads share each user, and together they get exactly the user's capacity.
+        let allowed: usize = handles.into_iter().map(|handle| handle.join().unwrap()).sum();
+        assert_eq!(allowed, 4 * 5);
+    }
+
+    #[test]
+    fn test_allowed_n() {
+        let mut limiter = RateLimiter::with_in_memory();
+        limiter
+            .add_config("type1", 5, 60000)
+            .add_config_token_bucket("type2", 5, 1.0)
+            .add_config_fixed_window("type3", 5, 60000);
+
+        let user_id = "user12345";
+        for request_type in ["type1", "type2", "type3"] {
+            // a cost above capacity is rejected without consuming anything.
+            assert!(!limiter.allowed_n(user_id, request_type, 6).unwrap());
+            assert!(limiter.allowed_n(user_id, request_type, 3).unwrap());
+            assert!(!limiter.allowed_n(user_id, request_type, 3).unwrap());
+            assert!(limiter.allowed_n(user_id, request_type, 2).unwrap());
+            assert!(!limiter.allowed(user_id, request_type).unwrap());
+        }
+        assert_eq!(limiter.remaining(user_id, "type1").unwrap(), 0);
+    }
+
+    #[test]
+    fn test_remove_config() {
+        let mut limiter = RateLimiter::with_in_memory();
+        limiter.add_config("type1", 1, 60000).add_config("type2", 1, 60000);
+
+        assert!(limiter.allowed("user12345", "type1").unwrap());
+        assert!(limiter.allowed("user67890", "type1").unwrap());
+        assert!(limiter.allowed("user12345", "type2").unwrap());
+
+        // without purging, re-adding the type picks the stored state up again.
+        assert!(limiter.remove_config("type1"));
+        assert!(!limiter.remove_config("type1"));
+        assert!(limiter.allowed("user12345", "type1").is_err());
+        limiter.add_config("type1", 1, 60000);
+        assert!(!limiter.allowed("user12345", "type1").unwrap());
+
+        assert_eq!(limiter.purge_request_type("type1").unwrap(), 2);
+        assert!(limiter.allowed("user12345", "type1").unwrap());
+        assert!(!limiter.allowed("user12345", "type2").unwrap());
+
+        // overwriting a config replaces its capacity.
+        limiter.add_config("type2", 2, 60000);
+        assert!(limiter.allowed("user12345", "type2").unwrap());
+    }
+
+    #[test]
+    fn test_allowed_all() {
+        let clock = MockClock::new(1_000_000);
+        let mut limiter = RateLimiter::with_in_memory_clock(clock.clone());
+        limiter.add_config("per_user", 3, 5000).add_config("per_endpoint", 1, 1000);
+
+        let user_id = "user12345";
+        assert!(limiter.allowed_all(user_id, &["per_user", "per_endpoint", "per_user"]).unwrap());
+        assert_eq!(limiter.remaining(user_id, "per_user").unwrap(), 2);
+
+        // per_endpoint is exhausted, so per_user must not be consumed either.
+        assert!(!limiter.allowed_all(user_id, &["per_user", "per_endpoint"]).unwrap());
+        assert_eq!(limiter.remaining(user_id, "per_user").unwrap(), 2);
+
+        clock.advance(Duration::from_millis(1001));
+        assert!(limiter.allowed_all(user_id, &["per_user", "per_endpoint"]).unwrap());
+        assert_eq!(limiter.remaining(user_id, "per_user").unwrap(), 1);
+
+        assert!(limiter.allowed_all(user_id, &[]).unwrap());
+        assert!(limiter.allowed_all(user_id, &["per_user", "type3"]).is_err());
+        assert_eq!(limiter.remaining(user_id, "per_user").unwrap(), 1);
+
+        limiter.add_config_token_bucket("bucket", 5, 1.0);
+        assert!(limiter.allowed_all(user_id, &["per_user", "bucket"]).is_err());
+    }
+
+    #[test]
+    fn test_peek() {
+        let clock = MockClock::new(1_000_000);
+        let mut limiter = RateLimiter::with_in_memory_clock(clock.clone());
+        limiter.add_config("type1", 2, 5000);
+
+        let user_id = "user12345";
+        for _ in 0..5 {
+            assert!(limiter.peek(user_id, "type1").unwrap());
+        }
+        assert!(limiter.allowed(user_id, "type1").unwrap());
+        assert!(limiter.peek(user_id, "type1").unwrap());
+        assert!(limiter.allowed(user_id, "type1").unwrap());
+        assert!(!limiter.peek(user_id, "type1").unwrap());
+
+        // once the window has moved past the recorded requests, peek sees the room again.
+        clock.advance(Duration::from_millis(5001));
+        assert!(limiter.peek(user_id, "type1").unwrap());
+        assert!(limiter.peek(user_id, "type2").is_err());
+    }
+
+    #[test]
+    fn test_default_config() {
+        let mut limiter = RateLimiter::with_in_memory();
+        limiter.add_config("type1", 2, 60000);
+        assert!(limiter.allowed("user12345", "type2").is_err());
+
+        limiter.set_default_config(1, 60000);
+        // the explicit config wins over the default.
+        assert!(limiter.allowed("user12345", "type1").unwrap());
+        assert!(limiter.allowed("user12345", "type1").unwrap());
+        assert!(!limiter.allowed("user12345", "type1").unwrap());
+
+        // every defaulted type is counted separately.
+        assert!(limiter.allowed("user12345", "type2").unwrap());
+        assert!(!limiter.allowed("user12345", "type2").unwrap());
+        assert!(limiter.allowed("user12345", "type3").unwrap());
+
+        assert!(limiter.clear_default_config());
+        assert!(!limiter.clear_default_config());
+        assert!(limiter.allowed("user12345", "type2").is_err());
+    }
+
+    #[test]
+    fn test_get_config() {
+        let mut limiter = RateLimiter::with_in_memory();
+        limiter
+            .add_config("type1", 5, 60000)
+            .add_config_fixed_window("type2", 10, 1000)
+            .add_config_leaky_bucket("type3", 4.0, 2)
+            .add_config_token_bucket("type4", 3, 0.0)
+            .add_user_override("user12345", "type1", 50, 60000);
+
+        assert_eq!(limiter.get_config("type1"), Some((5, Duration::from_secs(60))));
+        assert_eq!(limiter.get_config("type2"), Some((10, Duration::from_secs(1))));
+        assert_eq!(limiter.get_config("type3"), Some((2, Duration::from_millis(500))));
+        assert_eq!(limiter.get_config("type4"), Some((3, Duration::MAX)));
+        assert_eq!(limiter.get_config("type5"), None);
+
+        limiter.set_default_config(1, 1000);
+        assert_eq!(limiter.get_config("type5"), Some((1, Duration::from_secs(1))));
+    }
+
+    #[test]
+    fn test_non_str_keys() {
+        struct AccountId(String);
+
+        impl AsRef<str> for AccountId {
+            fn as_ref(&self) -> &str {
+                &self.0
+            }
+        }
+
+        let mut limiter = RateLimiter::with_in_memory();
+        limiter.add_config("type1", 2, 60000);
+
+        let account = AccountId(42.to_string());
+        assert!(limiter.allowed(&account, "type1").unwrap());
+        assert!(limiter.allowed("42", String::from("type1")).unwrap());
+        assert!(!limiter.allowed(&account, "type1").unwrap());
+        assert_eq!(limiter.remaining(String::from("42"), "type1").unwrap(), 0);
+
+        limiter.reset(&account, "type1").unwrap();
+        assert!(limiter.peek(account, "type1").unwrap());
+    }
+
+    #[test]
+    fn test_typed_request_types() {
+        #[derive(Clone, Copy)]
+        enum RequestType {
+            Login,
+            Search,
+        }
+
+        impl AsRef<str> for RequestType {
+            fn as_ref(&self) -> &str {
+                match self {
+                    RequestType::Login => "login",
+                    RequestType::Search => "search",
+                }
+            }
+        }
+
+        let mut limiter = RateLimiter::with_in_memory();
+        limiter.add_config(RequestType::Login, 1, 60000).add_user_override("user2", RequestType::Login, 2, 60000);
+        limiter.add_config_token_bucket(RequestType::Search, 1, 1.0);
+        assert!(limiter.is_configured(RequestType::Search));
+
+        assert!(limiter.allowed("user1", RequestType::Login).unwrap());
+        assert!(!limiter.allowed("user1", "login").unwrap());
+        assert!(limiter.allowed("user2", RequestType::Login).unwrap());
+        assert!(limiter.allowed("user2", RequestType::Login).unwrap());
+        assert!(limiter.allowed("user1", RequestType::Search).unwrap());
+
+        assert!(limiter.remove_config(RequestType::Search));
+        assert!(!limiter.is_configured("search"));
+    }
+
+    #[test]
+    fn test_allowed_keyed() {
+        let mut limiter = RateLimiter::with_in_memory();
+        limiter.add_config("type1", 1, 60000).add_user_override("user1:10.0.0.9", "type1", 2, 60000);
+
+        assert!(limiter.allowed_keyed(&["user1", "10.0.0.1"], "type1").unwrap());
+        assert!(!limiter.allowed("user1:10.0.0.1", "type1").unwrap());
+        assert!(limiter.allowed_keyed(&["user1"], "type1").unwrap());
+
+        assert!(limiter.allowed_keyed(&["user1", "10.0.0.9"], "type1").unwrap());
+        assert!(limiter.allowed_keyed(&["user1", "10.0.0.9"], "type1").unwrap());
+        assert!(!limiter.allowed_keyed(&["user1", "10.0.0.9"], "type1").unwrap());
+
+        assert!(limiter.allowed_keyed(&[], "type1").is_err());
+
+        // parts holding the separator join into different ids.
+        assert!(limiter.allowed_keyed(&["a:b", "c"], "type1").unwrap());
+        assert!(limiter.allowed_keyed(&["a", "b:c"], "type1").unwrap());
+        assert!(!limiter.allowed_keyed(&["a", "b:c"], "type1").unwrap());
+    }
+
+    #[test]
+    fn test_allowed_many() {
+        let mut limiter = RateLimiter::with_in_memory();
+        limiter
+            .add_config("type1", 1, 60000)
+            .add_config_token_bucket("type2", 1, 0.0)
+            .add_user_override("user3", "type1", 2, 60000);
+
+        assert_eq!(limiter.allowed_many(&["user1", "user2", "user1", "user3"], "type1").unwrap(), vec![true, true, false, true]);
+        assert_eq!(limiter.allowed_many(&["user1", "user3", "user4"], "type1").unwrap(), vec![false, true, true]);
+        assert_eq!(limiter.allowed_many(&["user1", "user1"], "type2").unwrap(), vec![true, false]);
+        assert!(limiter.allowed_many(&[], "type1").unwrap().is_empty());
+        assert!(limiter.allowed_many(&["user1"], "type3").is_err());
+    }
+
+    #[test]
+    fn test_eviction_jitter() {
+        let clock = MockClock::new(1_000_000);
+        let mut limiter = RateLimiter::with_in_memory_clock(clock.clone()).with_eviction_jitter(1000);
+        limiter.add_config("type1", 1, 10000);
+
+        let user_ids: Vec<String> = (0..50).map(|i| format!("user{}", i)).collect();
+        for user_id in &user_ids {
+            assert!(limiter.allowed(user_id, "type1").unwrap());
+        }
+
+        // no window is shortened, and the offsets spread expirations over the jitter.
+        let mut retry_afters: Vec<Duration> = user_ids.iter().map(|user_id| limiter.retry_after(user_id, "type1").unwrap().unwrap()).collect();
+        assert!(retry_afters.iter().all(|retry_after| (Duration::from_secs(10)..=Duration::from_secs(11)).contains(retry_after)));
+        retry_afters.dedup();
+        assert!(retry_afters.len() > 1);
+
+        // the offset of a key is stable, so it is denied until its own retry_after passes.
+        let retry_after = limiter.retry_after("user0", "type1").unwrap().unwrap();
+        clock.advance(retry_after - Duration::from_millis(1));
+        assert!(!limiter.allowed("user0", "type1").unwrap());
+        clock.advance(Duration::from_millis(2));
+        assert!(limiter.allowed("user0", "type1").unwrap());
+    }
+
+    #[test]
+    fn test_clear() {
+        let mut limiter = RateLimiter::with_in_memory();
+        limiter.add_config("type1", 1, 60000).add_config_fixed_window("type2", 1, 60000).add_user_override("user1", "type3", 1, 60000);
+
+        for request_type in ["type1", "type2", "type3"] {
+            assert!(limiter.allowed("user1", request_type).unwrap());
+            assert!(!limiter.allowed("user1", request_type).unwrap());
+        }
+        limiter.clear().unwrap();
+        for request_type in ["type1", "type2", "type3"] {
+            assert!(limiter.allowed("user1", request_type).unwrap());
+        }
+
+        // with a prefix, types governed by the default config are cleared as well.
+        limiter.set_key_prefix("myapp").set_default_config(1, 60000);
+        assert!(limiter.allowed("user1", "type4").unwrap());
+        assert!(!limiter.allowed("user1", "type4").unwrap());
+        limiter.clear().unwrap();
+        assert!(limiter.allowed("user1", "type4").unwrap());
+    }
+
+    #[test]
+    fn test_allowed_with_info() {
+        let mut limiter = RateLimiter::with_in_memory();
+        limiter
+            .add_config("type1", 1, 60000)
+            .add_config_token_bucket("type2", 2, 1.0)
+            .add_user_override("user2", "type1", 3, 1000);
+
+        assert_eq!(limiter.allowed_with_info("user1", "type1").unwrap(), (true, 1, 60000));
+        assert_eq!(limiter.allowed_with_info("user1", "type1").unwrap(), (false, 1, 60000));
+        assert_eq!(limiter.allowed_with_info("user2", "type1").unwrap(), (true, 3, 1000));
+        assert_eq!(limiter.allowed_with_info("user1", "type2").unwrap(), (true, 2, 2000));
+        assert!(limiter.allowed_with_info("user1", "type3").is_err());
+    }
+
+    #[test]
+    fn test_tiered_config() {
+        let clock = MockClock::new(1_000_000);
+        let mut limiter = RateLimiter::with_in_memory_clock(clock.clone());
+        limiter.add_config_tiers("type1", &[(2, 1000), (3, 60000)]);
+
+        let user_id = "user12345";
+        assert!(limiter.allowed(user_id, "type1").unwrap());
+        assert!(limiter.allowed(user_id, "type1").unwrap());
+        // the per-second tier is full, so nothing is recorded in the per-minute one.
+        assert!(!limiter.allowed(user_id, "type1").unwrap());
+        assert_eq!(limiter.remaining(user_id, "type1").unwrap(), 0);
+        assert_eq!(limiter.retry_after(user_id, "type1").unwrap(), Some(Duration::from_millis(1000)));
+
+        clock.advance(Duration::from_millis(1001));
+        assert!(limiter.peek(user_id, "type1").unwrap());
+        assert_eq!(limiter.remaining(user_id, "type1").unwrap(), 1);
+        assert!(limiter.allowed(user_id, "type1").unwrap());
+        // now the per-minute tier is full.
+        clock.advance(Duration::from_millis(1001));
+        assert!(!limiter.allowed(user_id, "type1").unwrap());
+        assert_eq!(limiter.retry_after(user_id, "type1").unwrap(), Some(Duration::from_millis(60000 - 2002)));
+
+        assert!(limiter.allowed_n(user_id, "type1", 2).is_err());
+        assert!(limiter.check(user_id, "type1").is_err());
+        assert!(limiter.update_config("type1", 5, 1000).is_err());
+
+        limiter.reset(user_id, "type1").unwrap();
+        assert!(limiter.allowed(user_id, "type1").unwrap());
+        assert_eq!(limiter.purge_request_type("type1").unwrap(), 2);
+    }
+
+    #[test]
+    fn test_add_configs() {
+        let mut limiter = RateLimiter::with_in_memory();
+        limiter.add_configs(vec![
+            ConfigSpec::SlidingWindow { request_type: "type1".to_string(), capacity: 1, window_time_millis: 60000 },
+            ConfigSpec::FixedWindow { request_type: "type2".to_string(), capacity: 2, window_time_millis: 60000 },
+            ConfigSpec::SlidingWindow { request_type: "type1".to_string(), capacity: 2, window_time_millis: 60000 },
+        ]);
+
+        assert_eq!(limiter.configured_types(), vec!["type1", "type2"]);
+        // the later spec for type1 wins.
+        assert_eq!(limiter.get_config("type1"), Some((2, Duration::from_millis(60000))));
+        assert!(limiter.allowed("user1", "type2").unwrap());
+        assert!(limiter.allowed("user1", "type2").unwrap());
+        assert!(!limiter.allowed("user1", "type2").unwrap());
+    }
+
+    #[test]
+    fn test_reset_at() {
+        let clock = MockClock::new(1_000_000);
+        let mut limiter = RateLimiter::with_in_memory_clock(clock.clone());
+        limiter.add_config("type1", 2, 60000).add_config_micros("type2", 1, 2500);
+
+        let user_id = "user12345";
+        assert!(limiter.allowed(user_id, "type1").unwrap());
+        clock.advance(Duration::from_millis(1000));
+        assert!(limiter.allowed(user_id, "type1").unwrap());
+        assert_eq!(limiter.reset_at(user_id, "type1").unwrap(), Some(1_060_000));
+        assert_eq!(limiter.retry_after(user_id, "type1").unwrap(), Some(Duration::from_millis(59000)));
+
+        // microsecond configs report milliseconds too.
+        assert!(limiter.allowed(user_id, "type2").unwrap());
+        assert_eq!(limiter.reset_at(user_id, "type2").unwrap(), Some(1_001_002));
+
+        clock.advance(Duration::from_millis(59001));
+        assert_eq!(limiter.reset_at(user_id, "type1").unwrap(), None);
+    }
+
+    #[test]
+    fn test_warmup() {
+        let clock = MockClock::new(1_000_000);
+        let mut limiter = RateLimiter::with_in_memory_clock(clock.clone())
+            .with_warmup("type1", Duration::from_millis(10000))
+            .with_warmup("type2", Duration::from_millis(10000));
+        limiter.add_config("type1", 20, 60000).add_config_token_bucket("type2", 20, 0.0).add_config("type3", 20, 60000);
+
+        let user_id = "user12345";
+        // at startup, a tenth of the capacity.
+        assert_eq!(limiter.remaining(user_id, "type1").unwrap(), 2);
+        assert!(limiter.allowed_n(user_id, "type2", 2).unwrap());
+        assert!(!limiter.allowed(user_id, "type2").unwrap());
+        assert_eq!(limiter.remaining(user_id, "type3").unwrap(), 20);
+
+        // halfway through, halfway between the floor and the capacity.
+        clock.advance(Duration::from_millis(5000));
+        assert_eq!(limiter.remaining(user_id, "type1").unwrap(), 11);
+        assert_eq!(limiter.check(user_id, "type1").unwrap().limit, 11);
+
+        clock.advance(Duration::from_millis(5000));
+        assert_eq!(limiter.remaining(user_id, "type1").unwrap(), 19);
+
+        // the ramp starts when the limiter is created, not when the warm-up is set.
+        let limiter = RateLimiter::with_in_memory_clock(clock.clone());
+        clock.advance(Duration::from_millis(5000));
+        let mut limiter = limiter.with_warmup("type1", Duration::from_millis(10000));
+        limiter.add_config("type1", 20, 60000);
+        assert_eq!(limiter.remaining(user_id, "type1").unwrap(), 11);
+    }
+
+    #[test]
+    fn test_consume() {
+        let clock = MockClock::new(1_000_000);
+        let mut limiter = RateLimiter::with_in_memory_clock(clock.clone());
+        limiter.add_config("type1", 1, 1000).add_config_token_bucket("type2", 1, 1.0);
+
+        assert!(limiter.consume("user1", "type1").is_ok());
+        clock.advance(Duration::from_millis(400));
+        let e = limiter.consume("user1", "type1").unwrap_err();
+        assert!(matches!(e, RateLimiterError::LimitExceeded { retry_after: Some(retry_after) } if retry_after == Duration::from_millis(600)));
+        assert_eq!(e.to_string(), "rate limit exceeded, retry after 600ms");
+
+        assert!(limiter.consume("user1", "type2").is_ok());
+        assert!(matches!(limiter.consume("user1", "type2"), Err(RateLimiterError::LimitExceeded { retry_after: None })));
+        assert!(matches!(limiter.consume("user1", "type3"), Err(RateLimiterError::UnknownRequestType(_))));
+    }
+
+    #[test]
+    fn test_allowed_blocking() {
+        let mut limiter = RateLimiter::with_in_memory();
+        limiter.add_config("type1", 1, 100).add_config_token_bucket("type2", 1, 20.0);
+        let limiter = Arc::new(limiter);
+
+        // two waiters share one request per window, so one of them waits a window longer. The
+        // limiter reads whole milliseconds, so the wait is measured in them too.
+        let started = SystemClock.now_millis();
+        let waiters: Vec<_> = (0..2)
+            .map(|_| {
+                let limiter = Arc::clone(&limiter);
+                std::thread::spawn(move || limiter.allowed_blocking("user1", "type1", Duration::from_secs(5)).unwrap())
+            })
+            .collect();
+        for waiter in waiters {
+            assert!(waiter.join().unwrap());
+        }
+        assert!(SystemClock.now_millis() - started >= 100);
+
+        // buckets are polled, as the limiter cannot tell when they refill.
+        assert!(limiter.allowed("user1", "type2").unwrap());
+        assert!(!limiter.allowed_blocking("user1", "type2", Duration::from_millis(1)).unwrap());
+        assert!(limiter.allowed_blocking("user1", "type2", Duration::from_secs(5)).unwrap());
+    }
+
+    #[test]
+    fn test_effective_capacity() {
+        let hour = Duration::from_secs(3600);
+        let clock = MockClock::new(0);
+        let mut limiter = RateLimiter::with_in_memory_clock(clock.clone()).with_warmup("type1", hour * 18);
+        limiter.add_config("type1", 100, 60000).add_user_override("user2", "type1", 1000, 60000);
+        limiter.add_scheduled_config("type1", Schedule::new().slot(hour * 9, hour * 18, 1000, 60000));
+
+        // warming up from a tenth of the overnight capacity.
+        assert_eq!(limiter.effective_capacity("user1", "type1").unwrap(), 10);
+        assert_eq!(limiter.effective_capacity("user2", "type1").unwrap(), 100);
+
+        // halfway through, the business hours slot applies.
+        clock.advance(hour * 9);
+        assert_eq!(limiter.effective_capacity("user1", "type1").unwrap(), 550);
+        clock.advance(hour * 9);
+        assert_eq!(limiter.effective_capacity("user1", "type1").unwrap(), 100);
+        assert_eq!(limiter.effective_capacity("user2", "type1").unwrap(), 1000);
+        assert!(limiter.effective_capacity("user1", "type2").is_err());
+    }
+
+    /// Runs the same sequence of requests around the window boundary against `storage`.
+    fn boundary_decisions(storage: impl Storage + 'static) -> Vec<bool> {
+        let clock = MockClock::new(1_000_000);
+        let mut limiter = RateLimiter { clock: Arc::new(clock.clone()), ..RateLimiter::with_storage(storage) };
+        limiter.add_config("type1", 2, 1000);
+
+        let user_id = "user12345";
+        let mut decisions = Vec::new();
+        for advance_millis in [0, 0, 0, 999, 1, 0, 0, 500, 499, 1] {
+            clock.advance(Duration::from_millis(advance_millis));
+            decisions.push(limiter.allowed(user_id, "type1").unwrap());
+        }
+        decisions
+    }
+
+    // both requests made at the start expire exactly one window later.
+    const BOUNDARY_DECISIONS: [bool; 10] = [true, true, false, false, true, true, false, false, false, true];
+
+    #[test]
+    fn test_window_boundary() {
+        assert_eq!(boundary_decisions(InMemoryStorage::new()), BOUNDARY_DECISIONS);
+
+        // retry_after points exactly at the boundary.
+        let clock = MockClock::new(1_000_000);
+        let mut limiter = RateLimiter::with_in_memory_clock(clock.clone());
+        limiter.add_config("type1", 1, 1000);
+        assert!(limiter.allowed("user1", "type1").unwrap());
+        let retry_after = limiter.retry_after("user1", "type1").unwrap().unwrap();
+        clock.advance(retry_after - Duration::from_millis(1));
+        assert!(!limiter.allowed("user1", "type1").unwrap());
+        clock.advance(Duration::from_millis(1));
+        assert!(limiter.allowed("user1", "type1").unwrap());
+    }
+
+    #[cfg(feature = "sled")]
+    #[test]
+    fn test_window_boundary_sled() {
+        let path = std::env::temp_dir().join(format!("ratelimiter-boundary-{}", std::process::id()));
+        let storage = crate::storage::SledStorage::open(&path).unwrap();
+        assert_eq!(boundary_decisions(storage), BOUNDARY_DECISIONS);
+        let _ = std::fs::remove_dir_all(&path);
+    }
+
+    /// Starts a Redis container, returning it with its URL; Redis stops when it is dropped.
+    #[cfg(feature = "redis")]
+    fn redis_container() -> (testcontainers::Container<testcontainers::GenericImage>, String) {
+        use testcontainers::core::{IntoContainerPort, WaitFor};
+        use testcontainers::runners::SyncRunner;
+        use testcontainers::GenericImage;
+
+        let container = GenericImage::new("redis", "7")
+            .with_exposed_port(6379.tcp())
+            .with_wait_for(WaitFor::message_on_stdout("Ready to accept connections"))
+            .start()
+            .unwrap();
+        let redis_url = format!("redis://127.0.0.1:{}/", container.get_host_port_ipv4(6379).unwrap());
+        (container, redis_url)
+    }
+
+    #[cfg(feature = "redis")]
+    #[test]
+    #[ignore = "needs docker to start a Redis container"]
+    fn test_window_boundary_redis() {
+        let (_container, redis_url) = redis_container();
+        let storage = RedisStorage::new(redis::Client::open(redis_url).unwrap());
+        assert_eq!(boundary_decisions(storage), BOUNDARY_DECISIONS);
+    }
+
+    #[cfg(feature = "redis")]
+    #[test]
+    fn test_redis_timeout() {
+        // the kernel completes the handshake, but nothing ever answers.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let redis_url = format!("redis://{}/", listener.local_addr().unwrap());
+        let mut limiter = RateLimiter::with_redis_timeout(&redis_url, Duration::from_millis(100)).unwrap();
+        limiter.add_config("type1", 5, 60000);
+
+        let started_at = std::time::Instant::now();
+        assert!(matches!(limiter.allowed("user1", "type1"), Err(RateLimiterError::Timeout)));
+        assert!(started_at.elapsed() < Duration::from_secs(5));
+
+        let limiter = limiter.with_failure_mode(FailureMode::Open);
+        assert!(limiter.allowed("user1", "type1").unwrap());
+    }
+
+    #[cfg(feature = "redis")]
+    #[test]
+    #[ignore = "needs docker to start a Redis container"]
+    fn test_redis_bucketed() {
+        let (_container, redis_url) = redis_container();
+        let storage = RedisStorage::new(redis::Client::open(redis_url).unwrap()).with_buckets(100);
+        let window = Duration::from_millis(1000);
+
+        for now in [1000, 1050, 1150] {
+            assert!(storage.check_and_record("user1:type1", 3, 1, window, now).unwrap());
+        }
+        assert!(!storage.check_and_record("user1:type1", 3, 1, window, 1199).unwrap());
+
+        // the first two requests count as made at 1099, the end of their bucket.
+        assert!(!storage.check_and_record("user1:type1", 3, 1, window, 2098).unwrap());
+        assert_eq!(storage.oldest("user1:type1", window, 2098).unwrap(), Some(1099));
+        assert!(storage.check_and_record("user1:type1", 3, 1, window, 2099).unwrap());
+        assert_eq!(storage.count("user1:type1", window, 2099).unwrap(), 2);
+        assert!(storage.check_and_record_all(&[("user1:type1", 3, window), ("user1:type2", 1, window)], 2100).unwrap());
+        assert!(!storage.check_and_record_all(&[("user1:type1", 3, window), ("user1:type2", 1, window)], 2101).unwrap());
+    }
+
+    #[cfg(feature = "redis")]
+    #[test]
+    #[ignore = "needs docker to start a Redis container"]
+    fn test_redis_keys_expire() {
+        let (_container, redis_url) = redis_container();
+        let mut limiter = RateLimiter::with_redis(&redis_url).unwrap();
+        limiter.add_config("type1", 1, 5000).add_config_tiers("type2", &[(1, 1000), (1, 60000)]);
+
+        assert!(limiter.allowed("user1", "type1").unwrap());
+        assert!(limiter.allowed("user1", "type2").unwrap());
+        let mut con = redis::Client::open(redis_url).unwrap().get_connection().unwrap();
+        let ttl = |con: &mut redis::Connection, key: &str| -> i64 { redis::cmd("PTTL").arg(key).query(con).unwrap() };
+        assert!((1..=5000).contains(&ttl(&mut con, "user1:type1")));
+        assert!((1..=1000).contains(&ttl(&mut con, "user1:type2")));
+        assert!((1000..=60000).contains(&ttl(&mut con, "user1:60000ms:type2")));
+    }
+
+    #[test]
+    fn test_add_config_quota() {
+        let clock = MockClock::new(1_000_000);
+        let mut limiter = RateLimiter::with_in_memory_clock(clock.clone());
+        limiter.add_config_quota("type1", Quota::try_from((2, Duration::from_millis(1500))).unwrap());
+        assert_eq!(limiter.get_config("type1"), Some((2, Duration::from_millis(1500))));
+
+        assert!(limiter.allowed("user1", "type1").unwrap());
+        assert!(limiter.allowed("user1", "type1").unwrap());
+        assert!(!limiter.allowed("user1", "type1").unwrap());
+        clock.advance(Duration::from_millis(1500));
+        assert!(limiter.allowed("user1", "type1").unwrap());
+    }
+
+    #[test]
+    fn test_add_config_per() {
+        let clock = MockClock::new(1_000_000);
+        let mut limiter = RateLimiter::with_in_memory_clock(clock.clone());
+        limiter.add_config_per("type1", 2, Duration::from_secs(300)).add_config_per("type2", 0, Duration::from_secs(1));
+        assert_eq!(limiter.get_config("type1"), Some((2, Duration::from_secs(300))));
+
+        assert!(limiter.allowed("user1", "type1").unwrap());
+        assert!(limiter.allowed("user1", "type1").unwrap());
+        assert!(!limiter.allowed("user1", "type1").unwrap());
+        clock.advance(Duration::from_secs(299));
+        assert!(!limiter.allowed("user1", "type1").unwrap());
+        clock.advance(Duration::from_secs(1));
+        assert!(limiter.allowed("user1", "type1").unwrap());
+        assert!(!limiter.allowed("user1", "type2").unwrap());
+
+        // windows under a millisecond still limit, over a whole one.
+        limiter.add_config_per("type3", 1, Duration::from_micros(500)).add_config_per("type4", 1, Duration::from_micros(1500));
+        assert_eq!(limiter.get_config("type3"), Some((1, Duration::from_millis(1))));
+        assert_eq!(limiter.get_config("type4"), Some((1, Duration::from_millis(2))));
+        assert!(limiter.allowed("user1", "type3").unwrap());
+        assert!(!limiter.allowed("user1", "type3").unwrap());
+        clock.advance(Duration::from_millis(1));
+        assert!(limiter.allowed("user1", "type3").unwrap());
+    }
+
+    #[test]
+    fn test_allowed_hierarchical() {
+        let clock = MockClock::new(1_000_000);
+        let mut limiter = RateLimiter::with_in_memory_clock(clock.clone());
+        limiter.add_config("type1", 2, 1000).add_tenant_config("type1", 3, 60000);
+        assert!(limiter.allowed_hierarchical("tenant1", "user1", "type2").is_err());
+        limiter.add_config("type2", 1, 1000);
+        assert!(limiter.allowed_hierarchical("tenant1", "user1", "type2").is_err());
+
+        assert!(limiter.allowed_hierarchical("tenant1", "user1", "type1").unwrap());
+        assert!(limiter.allowed_hierarchical("tenant1", "user1", "type1").unwrap());
+        // the user limit denies, and nothing is recorded against the tenant.
+        assert!(!limiter.allowed_hierarchical("tenant1", "user1", "type1").unwrap());
+        assert!(limiter.allowed_hierarchical("tenant1", "user2", "type1").unwrap());
+
+        // the tenant limit denies, and nothing is recorded against the user.
+        assert!(!limiter.allowed_hierarchical("tenant1", "user3", "type1").unwrap());
+        assert_eq!(limiter.remaining("user3", "type1").unwrap(), 2);
+        assert!(limiter.allowed_hierarchical("tenant2", "user3", "type1").unwrap());
+
+        // the user window expires long before the tenant one.
+        clock.advance(Duration::from_millis(1000));
+        assert!(!limiter.allowed_hierarchical("tenant1", "user1", "type1").unwrap());
+        assert_eq!(limiter.purge_request_type("type1").unwrap(), 5);
+    }
+
+    #[test]
+    fn test_prune_config() {
+        let mut limiter = RateLimiter::with_in_memory();
+        limiter.add_config("type1", 1, 60000).add_config("type2", 1, 60000).add_user_override("user2", "type1", 5, 60000);
+        limiter.allowed("user1", "type1").unwrap();
+        limiter.allowed("user2", "type1").unwrap();
+        limiter.allowed("user1", "type2").unwrap();
+
+        assert_eq!(limiter.prune_config("type1").unwrap(), 2);
+        assert!(limiter.allowed("user2", "type1").is_err());
+        assert_eq!(limiter.configured_types(), vec!["type2"]);
+        // other request types keep their state.
+        assert!(!limiter.allowed("user1", "type2").unwrap());
+
+        // re-adding the type starts from a clean slate.
+        limiter.add_config("type1", 1, 60000);
+        assert!(limiter.allowed("user1", "type1").unwrap());
+        assert_eq!(limiter.prune_config("type3").unwrap(), 0);
+    }
+
+    #[test]
+    fn test_allowed_with_window() {
+        let clock = MockClock::new(1_000_000);
+        let mut limiter = RateLimiter::with_in_memory_clock(clock.clone());
+        limiter.add_config("type1", 2, 1000).add_config_token_bucket("type2", 1, 1.0);
+
+        let user_id = "user12345";
+        assert!(limiter.allowed(user_id, "type1").unwrap());
+        clock.advance(Duration::from_millis(1000));
+        // under a longer window, the first request still counts.
+        assert!(limiter.allowed_with_window(user_id, "type1", 10000).unwrap());
+        assert!(!limiter.allowed_with_window(user_id, "type1", 10000).unwrap());
+
+        // the configured window still applies to other calls.
+        clock.advance(Duration::from_millis(1000));
+        assert!(limiter.allowed(user_id, "type1").unwrap());
+        assert_eq!(limiter.get_config("type1"), Some((2, Duration::from_millis(1000))));
+
+        clock.advance(Duration::from_millis(100));
+        assert!(limiter.allowed(user_id, "type1").unwrap());
+        assert!(!limiter.allowed(user_id, "type1").unwrap());
+        // under a shorter window, only the last request counts.
+        assert!(limiter.allowed_with_window(user_id, "type1", 50).unwrap());
+        assert!(limiter.allowed_with_window(user_id, "type2", 1000).is_err());
+    }
+
+    #[test]
+    fn test_disabled() {
+        let mut limiter = RateLimiter::disabled();
+        limiter.add_config("type1", 1, 60000);
+
+        let user_id = "user12345";
+        for _ in 0..3 {
+            assert!(limiter.allowed(user_id, "type1").unwrap());
+            assert!(limiter.allowed_n(user_id, "type1", 5).unwrap());
+            assert!(limiter.allowed_all(user_id, &["type1", "type2"]).unwrap());
+        }
+        // nothing was recorded while disabled.
+        assert_eq!(limiter.remaining(user_id, "type1").unwrap(), 1);
+
+        limiter.set_enabled(true);
+        assert!(limiter.allowed(user_id, "type1").unwrap());
+        assert!(!limiter.allowed(user_id, "type1").unwrap());
+        assert!(limiter.allowed(user_id, "type2").is_err());
+    }
+
+    #[test]
+    fn test_update_config() {
+        let clock = MockClock::new(1_000_000);
+        let mut limiter = RateLimiter::with_in_memory_clock(clock.clone());
+        limiter.add_config("type1", 5, 10000).add_config_token_bucket("type2", 5, 1.0);
+
+        // the user is one request short of their limit.
+        let user_id = "user12345";
+        for _ in 0..4 {
+            assert!(limiter.allowed(user_id, "type1").unwrap());
+            clock.advance(Duration::from_millis(1000));
+        }
+
+        // their four requests already exceed the shrunk capacity.
+        limiter.update_config("type1", 3, 10000).unwrap();
+        assert!(!limiter.allowed(user_id, "type1").unwrap());
+        assert_eq!(limiter.get_config("type1"), Some((3, Duration::from_secs(10))));
+
+        // under a shorter window, the requests older than 2 seconds no longer count.
+        limiter.update_config("type1", 3, 2500).unwrap();
+        assert!(limiter.allowed(user_id, "type1").unwrap());
+        assert!(!limiter.allowed(user_id, "type1").unwrap());
+
+        assert!(limiter.update_config("type2", 3, 10000).is_err());
+        assert_eq!(limiter.get_config("type2"), Some((5, Duration::from_secs(5))));
+        assert!(matches!(
+            limiter.update_config("type3", 3, 10000),
+            Err(RateLimiterError::UnknownRequestType(request_type)) if request_type == "type3"
+        ));
+    }
+
+    #[test]
+    fn test_is_configured() {
+        let mut limiter = RateLimiter::with_in_memory();
+        assert!(!limiter.is_configured("type1"));
+        assert!(limiter.configured_types().is_empty());
+
+        limiter.add_config("type2", 5, 60000).add_config_gcra("type1", 10.0, 5);
+        assert!(limiter.is_configured("type1"));
+        assert!(!limiter.is_configured("type3"));
+        assert_eq!(limiter.configured_types(), vec!["type1", "type2"]);
+
+        limiter.set_default_config(1, 1000);
+        assert!(limiter.is_configured("type3"));
+        assert_eq!(limiter.configured_types(), vec!["type1", "type2"]);
+    }
+
+    #[test]
+    fn test_evict_idle() {
+        let clock = MockClock::new(1_000_000);
+        let mut limiter = RateLimiter::with_in_memory_clock(clock.clone());
+        assert_eq!(limiter.evict_idle().unwrap(), 0);
+
+        limiter
+            .add_config("type1", 2, 1000)
+            .add_config_fixed_window("type2", 2, 1000)
+            .add_config_token_bucket("type3", 2, 1.0);
+
+        limiter.allowed("user12345", "type1").unwrap();
+        limiter.allowed("user12345", "type2").unwrap();
+        limiter.allowed("user12345", "type3").unwrap();
+        clock.advance(Duration::from_millis(1500));
+        limiter.allowed("user67890", "type1").unwrap();
+
+        // the token bucket takes two seconds to refill, so nothing is idle for long enough yet.
+        assert_eq!(limiter.evict_idle().unwrap(), 0);
+
+        clock.advance(Duration::from_millis(501));
+        assert_eq!(limiter.evict_idle().unwrap(), 3);
+        assert_eq!(limiter.remaining("user67890", "type1").unwrap(), 1);
+    }
+
+    #[test]
+    fn test_keys_do_not_collide() {
+        let mut limiter = RateLimiter::with_in_memory();
+        limiter.add_config("c", 1, 60000).add_config("b:c", 1, 60000).add_config_tiers("type1", &[(1, 1000), (5, 60000)]);
+
+        assert!(limiter.allowed("a:b", "c").unwrap());
+        assert!(limiter.allowed("a", "b:c").unwrap());
+        assert!(limiter.allowed("a\\", "c").unwrap());
+        assert!(limiter.allowed("a\\:", "c").unwrap());
+        assert!(limiter.allowed("a%3Ab", "c").unwrap());
+
+        // a user id that looks like a tier of another user is a user of its own.
+        assert!(limiter.allowed("user1", "type1").unwrap());
+        assert!(limiter.allowed("user1:60000ms", "type1").unwrap());
+
+        // purging a request type leaves the types it is a suffix of alone.
+        assert_eq!(limiter.purge_request_type("c").unwrap(), 4);
+        assert!(!limiter.allowed("a", "b:c").unwrap());
+        assert!(limiter.allowed("a:b", "c").unwrap());
+
+        assert_eq!(limiter.prune_config("c").unwrap(), 1);
+        assert!(!limiter.allowed("a", "b:c").unwrap());
+        assert_eq!(limiter.prune_config("b:c").unwrap(), 1);
+    }
+
+    #[test]
+    fn test_on_threshold() {
+        let clock = MockClock::new(0);
+        let mut limiter = RateLimiter::with_in_memory_clock(clock.clone());
+        let crossings = Arc::new(std::sync::Mutex::new(Vec::new()));
+        for fraction in [0.5, 1.0] {
+            let crossings = Arc::clone(&crossings);
+            limiter.on_threshold("type1", fraction, move |user_id, utilization| crossings.lock().unwrap().push((user_id.to_string(), utilization)));
+        }
+        limiter.add_config("type1", 4, 1000);
+
+        for _ in 0..6 {
+            limiter.allowed("user1", "type1").unwrap();
+        }
+        assert!(limiter.allowed_n("user2", "type1", 3).unwrap());
+        assert_eq!(*crossings.lock().unwrap(), vec![("user1".to_string(), 0.5), ("user1".to_string(), 1.0), ("user2".to_string(), 0.75)]);
+
+        // a new window fires again.
+        clock.advance(Duration::from_millis(1000));
+        crossings.lock().unwrap().clear();
+        for _ in 0..2 {
+            limiter.allowed("user1", "type1").unwrap();
+        }
+        assert_eq!(*crossings.lock().unwrap(), vec![("user1".to_string(), 0.5)]);
+    }
+
+    #[test]
+    fn test_penalty() {
+        let clock = MockClock::new(1_000_000);
+        let mut limiter = RateLimiter::with_in_memory_clock(clock.clone()).with_penalty("type1", 2, Duration::from_secs(10));
+        limiter.add_config("type1", 1, 1000).add_config("type2", 1, 1000);
+
+        assert!(limiter.allowed("user1", "type1").unwrap());
+        assert!(!limiter.allowed("user1", "type1").unwrap());
+
+        // a single violation per window never triggers the block.
+        clock.advance(Duration::from_millis(1000));
+        assert!(limiter.allowed("user1", "type1").unwrap());
+        clock.advance(Duration::from_millis(1000));
+        assert!(limiter.allowed("user1", "type1").unwrap());
+        assert!(!limiter.allowed("user1", "type1").unwrap());
+        assert!(!limiter.allowed("user1", "type1").unwrap());
+
+        // the second violation blocks the user past the window, but not other users or types.
+        clock.advance(Duration::from_millis(1000));
+        assert!(!limiter.allowed("user1", "type1").unwrap());
+        assert!(limiter.allowed("user2", "type1").unwrap());
+        assert!(limiter.allowed("user1", "type2").unwrap());
+
+        clock.advance(Duration::from_millis(9000));
+        assert!(limiter.allowed("user1", "type1").unwrap());
+
+        // reset lifts a block.
+        assert!(!limiter.allowed("user1", "type1").unwrap());
+        assert!(!limiter.allowed("user1", "type1").unwrap());
+        limiter.reset("user1", "type1").unwrap();
+        assert!(limiter.allowed("user1", "type1").unwrap());
+    }
+
+    /// A limiter that blocks a user from type1 for 10 seconds on their first denial.
+    fn penalized_limiter() -> (MockClock, RateLimiter) {
+        let clock = MockClock::new(1_000_000);
+        let mut limiter = RateLimiter::with_in_memory_clock(clock.clone()).with_penalty("type1", 1, Duration::from_secs(10));
+        limiter.add_config("type1", 1, 1000).add_config("type2", 10, 1000).add_tenant_config("type1", 10, 1000);
+        (clock, limiter)
+    }
+
+    #[test]
+    fn test_penalty_allowed_all() {
+        let (clock, limiter) = penalized_limiter();
+        assert!(limiter.allowed_all("user1", &["type1", "type2"]).unwrap());
+        assert!(!limiter.allowed_all("user1", &["type1", "type2"]).unwrap());
+
+        // the block denies the batch without recording it against the other type.
+        clock.advance(Duration::from_millis(1000));
+        assert!(!limiter.allowed_all("user1", &["type1", "type2"]).unwrap());
+        assert!(!limiter.allowed("user1", "type1").unwrap());
+        assert_eq!(limiter.remaining("user1", "type2").unwrap(), 10);
+        assert!(limiter.allowed_all("user2", &["type1", "type2"]).unwrap());
+    }
+
+    #[test]
+    fn test_penalty_allowed_many() {
+        let (clock, limiter) = penalized_limiter();
+        assert_eq!(limiter.allowed_many(&["user1", "user1", "user2"], "type1").unwrap(), vec![true, false, true]);
+
+        clock.advance(Duration::from_millis(1000));
+        assert_eq!(limiter.allowed_many(&["user1", "user2"], "type1").unwrap(), vec![false, true]);
+        assert!(!limiter.allowed("user1", "type1").unwrap());
+    }
+
+    #[test]
+    fn test_penalty_allowed_hierarchical() {
+        let (clock, limiter) = penalized_limiter();
+        assert!(limiter.allowed_hierarchical("acme", "user1", "type1").unwrap());
+        assert!(!limiter.allowed_hierarchical("acme", "user1", "type1").unwrap());
+
+        clock.advance(Duration::from_millis(1000));
+        assert!(!limiter.allowed_hierarchical("acme", "user1", "type1").unwrap());
+        assert!(!limiter.allowed("user1", "type1").unwrap());
+        assert!(limiter.allowed_hierarchical("acme", "user2", "type1").unwrap());
+    }
+
+    #[test]
+    fn test_penalty_allowed_with_window() {
+        let (clock, limiter) = penalized_limiter();
+        assert!(limiter.allowed_with_window("user1", "type1", 500).unwrap());
+        assert!(!limiter.allowed_with_window("user1", "type1", 500).unwrap());
+
+        clock.advance(Duration::from_millis(500));
+        assert!(!limiter.allowed_with_window("user1", "type1", 500).unwrap());
+        assert!(!limiter.allowed("user1", "type1").unwrap());
+        assert!(limiter.allowed_with_window("user2", "type1", 500).unwrap());
+    }
+
+    #[test]
+    fn test_allowed_first() {
+        let clock = MockClock::new(1_000_000);
+        let mut limiter = RateLimiter::with_in_memory_clock(clock.clone());
+        limiter.add_config("type1", 2, 1000).add_config("type2", 0, 1000).add_config_token_bucket("type3", 1, 1.0);
+
+        assert_eq!(limiter.allowed_first("user1", "type1").unwrap(), (true, true));
+        assert_eq!(limiter.allowed_first("user1", "type1").unwrap(), (true, false));
+        assert_eq!(limiter.allowed_first("user1", "type1").unwrap(), (false, false));
+        assert_eq!(limiter.allowed_first("user2", "type1").unwrap(), (true, true));
+
+        // once the window has emptied, the next request is the first again.
+        clock.advance(Duration::from_millis(1000));
+        assert_eq!(limiter.allowed_first("user1", "type1").unwrap(), (true, true));
+
+        // a denied request on an empty window is still the first one.
+        assert_eq!(limiter.allowed_first("user1", "type2").unwrap(), (false, true));
+        assert!(limiter.allowed_first("user1", "type3").is_err());
+    }
+
+    #[test]
+    fn test_stats() {
+        let clock = MockClock::new(1_000_000);
+        let mut limiter = RateLimiter::with_in_memory_clock(clock.clone());
+        limiter.add_config("type1", 5, 1000).add_config_token_bucket("type2", 5, 1.0);
+        assert_eq!(limiter.stats().unwrap(), LimiterStats { tracked_users: 0, total_entries: 0, configured_types: 2 });
+
+        for _ in 0..3 {
+            limiter.allowed("user1", "type1").unwrap();
+        }
+        limiter.allowed("user2", "type1").unwrap();
+        limiter.allowed("user1", "type2").unwrap();
+        assert_eq!(limiter.stats().unwrap(), LimiterStats { tracked_users: 3, total_entries: 5, configured_types: 2 });
+
+        // expired state still counts until it is evicted, once the bucket has refilled too.
+        clock.advance(Duration::from_millis(5001));
+        assert_eq!(limiter.stats().unwrap().total_entries, 5);
+        assert_eq!(limiter.evict_idle().unwrap(), 3);
+        assert_eq!(limiter.stats().unwrap(), LimiterStats { tracked_users: 0, total_entries: 0, configured_types: 2 });
+    }
+
+    #[test]
+    fn test_start_gc_stops_with_limiter() {
+        let limiter = Arc::new(RateLimiter::with_in_memory());
+        let gc = limiter.start_gc(Duration::from_millis(1));
+        drop(limiter);
+        let started = std::time::Instant::now();
+        while !gc.is_finished() {
+            assert!(started.elapsed() < Duration::from_secs(5));
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn test_gc_handle_stops_on_drop() {
+        let limiter = Arc::new(RateLimiter::with_in_memory());
+        let gc = limiter.start_gc(Duration::from_secs(3600));
+
+        // the thread wakes up right away instead of sleeping through its interval.
+        let started = std::time::Instant::now();
+        drop(gc);
+        assert!(started.elapsed() < Duration::from_millis(900));
+
+        // a detached thread keeps running until the limiter is gone.
+        limiter.start_gc(Duration::from_millis(1)).detach();
+        drop(limiter);
+    }
+
+    #[test]
+    fn test_micros_config() {
+        let clock = MockClock::new(1_000_000);
+        let mut limiter = RateLimiter::with_in_memory_clock(clock.clone());
+        limiter.add_config_micros("type1", 2, 2500).add_config("type2", 1, 60000);
+
+        let user_id = "user12345";
+        assert!(limiter.allowed(user_id, "type1").unwrap());
+        clock.advance(Duration::from_micros(400));
+        assert!(limiter.allowed(user_id, "type1").unwrap());
+        assert!(!limiter.allowed(user_id, "type1").unwrap());
+        assert_eq!(limiter.retry_after(user_id, "type1").unwrap(), Some(Duration::from_micros(2100)));
+
+        // the first request expires 2.5 milliseconds after it was made.
+        clock.advance(Duration::from_micros(2099));
+        assert!(!limiter.allowed(user_id, "type1").unwrap());
+        clock.advance(Duration::from_micros(2));
+        assert!(limiter.allowed(user_id, "type1").unwrap());
+
+        assert!(limiter.allowed_all(user_id, &["type1", "type2"]).is_err());
+    }
+
+    #[test]
+    fn test_clone_shares_storage() {
+        let mut limiter = RateLimiter::with_in_memory();
+        limiter.add_config("type1", 2, 60000);
+
+        let mut clone = limiter.clone();
+        assert!(limiter.allowed("user1", "type1").unwrap());
+        assert!(clone.allowed("user1", "type1").unwrap());
+        assert!(!limiter.allowed("user1", "type1").unwrap());
+        assert!(!clone.allowed("user1", "type1").unwrap());
+
+        // configs are copied, not shared.
+        clone.add_config("type2", 1, 60000);
+        assert!(clone.allowed("user1", "type2").unwrap());
+        assert!(limiter.allowed("user1", "type2").is_err());
+    }
+
+    #[test]
+    fn test_check() {
+        let clock = MockClock::new(1_000_000);
+        let mut limiter = RateLimiter::with_in_memory_clock(clock.clone());
+        limiter.add_config("type1", 2, 5000).add_config_token_bucket("type2", 2, 1.0);
+
+        let user_id = "user12345";
+        let decision = limiter.check(user_id, "type1").unwrap();
+        assert_eq!(decision, Decision { allowed: true, limit: 2, remaining: 1, reset_after: Duration::from_millis(5000) });
+
+        clock.advance(Duration::from_millis(1000));
+        let decision = limiter.check(user_id, "type1").unwrap();
+        assert_eq!(decision, Decision { allowed: true, limit: 2, remaining: 0, reset_after: Duration::from_millis(4000) });
+
+        let decision = limiter.check(user_id, "type1").unwrap();
+        assert_eq!(decision, Decision { allowed: false, limit: 2, remaining: 0, reset_after: Duration::from_millis(4000) });
+
+        assert!(limiter.check(user_id, "type2").is_err());
+        assert!(limiter.check(user_id, "type3").is_err());
+
+        // a disabled limiter allows unknown types, as `allowed` does.
+        limiter.set_enabled(false);
+        assert!(limiter.check(user_id, "type3").unwrap().allowed);
+        assert!(limiter.allowed(user_id, "type3").unwrap());
+    }
+
+    #[test]
+    fn test_check_penalty() {
+        let clock = MockClock::new(1_000_000);
+        let mut limiter = RateLimiter::with_in_memory_clock(clock.clone()).with_penalty("type1", 1, Duration::from_secs(10));
+        limiter.add_config("type1", 1, 1000);
+
+        assert!(limiter.check("user1", "type1").unwrap().allowed);
+        assert!(!limiter.check("user1", "type1").unwrap().allowed);
+
+        // denials by `check` count as violations, and the block outlives the window.
+        clock.advance(Duration::from_millis(1000));
+        assert_eq!(limiter.check("user1", "type1").unwrap(), Decision { allowed: false, limit: 1, remaining: 0, reset_after: Duration::ZERO });
+        assert!(!limiter.allowed("user1", "type1").unwrap());
+        assert!(limiter.check("user2", "type1").unwrap().allowed);
+    }
+
+    #[test]
+    fn test_builder() {
+        let limiter = RateLimiter::builder()
+            .in_memory()
+            .key_prefix("myapp")
+            .config("type1", 1, 60000)
+            .config("type2", 2, 60000)
+            .build()
+            .unwrap();
+
+        assert!(limiter.allowed("user12345", "type1").unwrap());
+        assert!(!limiter.allowed("user12345", "type1").unwrap());
+        assert!(limiter.allowed("user12345", "type2").unwrap());
+        assert!(limiter.allowed("user12345", "type2").unwrap());
+        assert!(!limiter.allowed("user12345", "type2").unwrap());
+
+        assert!(RateLimiter::builder().build().is_err());
+        assert!(RateLimiter::builder().config("type1", 1, 60000).build().is_err());
+        #[cfg(feature = "redis")]
+        assert!(RateLimiter::builder().redis("not a redis url").build().is_err());
+        assert!(RateLimiter::builder().storage(InMemoryStorage::new()).build().is_ok());
+    }
+
+    #[test]
+    fn test_failure_mode() {
+        use std::sync::{Arc, Mutex};
+
+        struct FailingStorage;
+
+        impl Storage for FailingStorage {
+            fn check_and_record(&self, _key: &str, _capacity: u32, _cost: u32, _window: Duration, _now: u64) -> Result<bool, Box<dyn Error>> {
+                Err(Box::new(RateLimiterError::Message("connection refused".to_string())))
+            }
+
+            fn count(&self, _key: &str, _window: Duration, _now: u64) -> Result<u32, Box<dyn Error>> {
+                Err(Box::new(RateLimiterError::Message("connection refused".to_string())))
+            }
+
+            fn oldest(&self, _key: &str, _window: Duration, _now: u64) -> Result<Option<u64>, Box<dyn Error>> {
+                Err(Box::new(RateLimiterError::Message("connection refused".to_string())))
+            }
+
+            fn remove(&self, _key: &str) -> Result<(), Box<dyn Error>> {
+                Err(Box::new(RateLimiterError::Message("connection refused".to_string())))
+            }
+        }
+
+        #[derive(Default, Clone)]
+        struct ErrorRecorder(Arc<Mutex<Vec<String>>>);
+
+        impl MetricsSink for ErrorRecorder {
+            fn on_allowed(&self, _request_type: &str) {}
+
+            fn on_denied(&self, _request_type: &str) {}
+
+            fn on_storage_error(&self, request_type: &str, error: &dyn Error) {
+                self.0.lock().unwrap().push(format!("{}: {}", request_type, error));
+            }
+        }
+
+        let mut limiter = RateLimiter::with_storage(FailingStorage);
+        limiter.add_config("type1", 5, 60000);
+        assert!(limiter.allowed("user1", "type1").is_err());
+
+        let recorder = ErrorRecorder::default();
+        let mut limiter = RateLimiter::with_storage(FailingStorage).with_failure_mode(FailureMode::Open).with_metrics(recorder.clone());
+        limiter.add_config("type1", 5, 60000).add_config("type2", 5, 60000);
+        assert!(limiter.allowed("user1", "type1").unwrap());
+        assert_eq!(limiter.allowed_many(&["user1", "user2"], "type1").unwrap(), vec![true, true]);
+        // the default batched check is not supported by this storage, which counts as a failure too.
+        assert!(limiter.allowed_all("user1", &["type1", "type2"]).unwrap());
+        assert!(limiter.allowed("user1", "type3").is_err());
+        assert!(limiter.remaining("user1", "type1").is_err());
+        assert_eq!(recorder.0.lock().unwrap().len(), 4);
+        assert_eq!(recorder.0.lock().unwrap()[0], "type1: connection refused");
+
+        let mut limiter = RateLimiter::with_storage(FailingStorage).with_failure_mode(FailureMode::Closed);
+        limiter.add_config("type1", 5, 60000);
+        assert!(!limiter.allowed("user1", "type1").unwrap());
+        assert_eq!(limiter.allowed_many(&["user1"], "type1").unwrap(), vec![false]);
+    }
+
+    #[test]
+    fn test_reserve() {
+        let mut limiter = RateLimiter::with_in_memory();
+        limiter.add_config("type1", 2, 60000).add_config_token_bucket("type2", 1, 1.0);
+
+        let first = limiter.reserve("user1", "type1").unwrap().unwrap();
+        let second = limiter.reserve("user1", "type1").unwrap().unwrap();
+        assert!(limiter.reserve("user1", "type1").unwrap().is_none());
+
+        // dropping or cancelling gives the request back, committing keeps it.
+        drop(first);
+        assert_eq!(limiter.remaining("user1", "type1").unwrap(), 1);
+        second.cancel().unwrap();
+        assert_eq!(limiter.remaining("user1", "type1").unwrap(), 2);
+        limiter.reserve("user1", "type1").unwrap().unwrap().commit();
+        assert_eq!(limiter.remaining("user1", "type1").unwrap(), 1);
+        assert!(limiter.reserve("user1", "type2").is_err());
+
+        // a reservation outlives the limiter.
+        let reservation = limiter.reserve("user1", "type1").unwrap().unwrap();
+        let storage = Arc::clone(&limiter.storage);
+        drop(limiter);
+        drop(reservation);
+        assert_eq!(storage.count("user1:type1", Duration::from_secs(60), SystemClock.now_millis()).unwrap(), 1);
+    }
+
+    #[cfg(feature = "prometheus")]
+    #[test]
+    fn test_register_metrics() {
+        let registry = prometheus::Registry::new();
+        let mut limiter = RateLimiter::with_in_memory();
+        limiter.register_metrics(&registry).unwrap();
+        limiter.add_config("type1", 1, 60000);
+        limiter.allowed("user1", "type1").unwrap();
+        limiter.allowed("user1", "type1").unwrap();
+        limiter.allowed("user2", "type1").unwrap();
+
+        let families = registry.gather();
+        let requests: Vec<_> = families[0]
+            .get_metric()
+            .iter()
+            .map(|metric| (metric.get_label()[0].get_value(), metric.get_label()[1].get_value(), metric.get_counter().get_value()))
+            .collect();
+        assert_eq!(requests, vec![("allowed", "type1", 2.0), ("denied", "type1", 1.0)]);
+        assert_eq!(families[1].get_metric()[0].get_gauge().get_value(), 2.0);
+
+        // a second limiter cannot claim the same names.
+        assert!(RateLimiter::with_in_memory().register_metrics(&registry).is_err());
+    }
+
+    #[test]
+    fn test_replace_configs() {
+        let mut limiter = RateLimiter::with_in_memory();
+        limiter.add_config("type1", 1, 60000).add_config("type2", 1, 60000);
+        assert!(limiter.allowed("user1", "type2").unwrap());
+
+        let specs = HashMap::from([
+            ("type2".to_string(), ConfigSpec::SlidingWindow { request_type: "type2".to_string(), capacity: 2, window_time_millis: 60000 }),
+            ("type3".to_string(), ConfigSpec::TokenBucket { request_type: "type3".to_string(), capacity: 1, refill_per_sec: 1.0 }),
+        ]);
+        limiter.replace_configs(specs).unwrap();
+        assert_eq!(limiter.configured_types(), vec!["type2", "type3"]);
+        assert!(matches!(limiter.allowed("user1", "type1"), Err(RateLimiterError::UnknownRequestType(_))));
+        // state survives the reload.
+        assert!(limiter.allowed("user1", "type2").unwrap());
+        assert!(!limiter.allowed("user1", "type2").unwrap());
+
+        // a mismatched key rejects the whole reload.
+        let specs = HashMap::from([
+            ("type4".to_string(), ConfigSpec::SlidingWindow { request_type: "type4".to_string(), capacity: 1, window_time_millis: 60000 }),
+            ("type5".to_string(), ConfigSpec::SlidingWindow { request_type: "type6".to_string(), capacity: 1, window_time_millis: 60000 }),
+        ]);
+        assert!(limiter.replace_configs(specs).is_err());
+        assert_eq!(limiter.configured_types(), vec!["type2", "type3"]);
+    }
+
+    #[test]
+    fn test_replace_configs_shared() {
+        let mut limiter = RateLimiter::with_in_memory();
+        limiter.add_shared_budget("api", 2, 60000);
+        limiter.add_config_shared("search", "api", 1.0).add_config_shared("export", "api", 1.0);
+        let limiter = Arc::new(limiter);
+        assert!(limiter.allowed("user1", "export").unwrap());
+        assert!(limiter.allowed("user1", "export").unwrap());
+        assert!(!limiter.allowed("user1", "search").unwrap());
+
+        // a reload through the shared limiter keeps search in the exhausted budget...
+        let search = ConfigSpec::SlidingWindow { request_type: "search".to_string(), capacity: 5, window_time_millis: 60000 };
+        limiter.replace_configs(HashMap::from([("search".to_string(), search.clone())])).unwrap();
+        assert!(!limiter.allowed("user1", "search").unwrap());
+        assert_eq!(limiter.allowed_many(&["user1", "user2"], "search").unwrap(), vec![false, true]);
+        assert!(limiter.allowed("user1", "export").is_err());
+
+        // ...but export, which left it, comes back with a limit of its own.
+        let export = ConfigSpec::SlidingWindow { request_type: "export".to_string(), capacity: 5, window_time_millis: 60000 };
+        limiter.replace_configs(HashMap::from([("search".to_string(), search), ("export".to_string(), export)])).unwrap();
+        assert!(limiter.allowed("user1", "export").unwrap());
+        assert!(!limiter.allowed("user1", "search").unwrap());
+    }
+
+    #[test]
+    fn test_merge_configs() {
+        let mut limiter = RateLimiter::with_in_memory();
+        limiter.add_config("type1", 1, 60000);
+
+        let mut other = RateLimiter::with_in_memory();
+        other.add_shared_budget("budget1", 2, 60000);
+        other.add_config_shared("type2", "budget1", 1.0).add_config_shared("type3", "budget1", 1.0);
+        limiter.merge_configs(&other).unwrap();
+        assert_eq!(limiter.configured_types(), vec!["type1", "type2", "type3"]);
+
+        // the budget comes along with its members.
+        assert!(limiter.allowed("user1", "type2").unwrap());
+        assert!(limiter.allowed("user1", "type3").unwrap());
+        assert!(!limiter.allowed("user1", "type2").unwrap());
+
+        // a request type configured in both rejects the whole merge.
+        let mut conflicting = RateLimiter::with_in_memory();
+        conflicting.add_config("type4", 1, 60000).add_config("type1", 5, 60000);
+        assert!(limiter.merge_configs(&conflicting).is_err());
+        assert_eq!(limiter.configured_types(), vec!["type1", "type2", "type3"]);
+        assert!(limiter.allowed("user1", "type1").unwrap());
+        assert!(!limiter.allowed("user1", "type1").unwrap());
+    }
+
+    #[test]
+    fn test_debug_hides_users() {
+        let mut limiter = RateLimiter::with_in_memory();
+        assert_eq!(limiter.to_string(), "rate limiter without configs on in_memory storage");
+
+        limiter.add_config("uploads", 5, 60000).add_config("search", 5, 60000);
+        limiter.add_user_override("alice@example.com", "search", 10, 60000);
+        limiter.allowed("alice@example.com", "search").unwrap();
+        assert_eq!(limiter.to_string(), "rate limiter for search, uploads on in_memory storage");
+
+        let debug = format!("{:?}", limiter);
+        assert!(debug.contains(r#"request_types: ["search", "uploads"]"#) && debug.contains("overrides: 1"));
+        assert!(!debug.contains("alice"));
+    }
+
+    #[test]
+    fn test_shared_budget() {
+        let mut limiter = RateLimiter::with_in_memory();
+        limiter.add_shared_budget("api", 4, 60000);
+        limiter.add_config_shared("search", "api", 0.5).add_config_shared("export", "api", 1.0);
+        assert_eq!(limiter.get_config("search"), Some((2, Duration::from_secs(60))));
+
+        // search is capped at its share, export may use whatever is left.
+        assert!(limiter.allowed("user1", "search").unwrap());
+        assert!(limiter.allowed("user1", "search").unwrap());
+        assert!(!limiter.allowed("user1", "search").unwrap());
+        assert!(limiter.allowed("user1", "export").unwrap());
+        assert!(limiter.allowed("user1", "export").unwrap());
+        assert!(!limiter.allowed("user1", "export").unwrap());
+        // every user has a budget of their own.
+        assert!(limiter.allowed("user2", "export").unwrap());
+        assert!(limiter.allowed_n("user2", "export", 2).is_err());
+
+        // a type whose share is free is still denied once the budget is exhausted.
+        let mut limiter = RateLimiter::with_in_memory();
+        limiter.add_shared_budget("api", 2, 60000);
+        limiter.add_config_shared("search", "api", 1.0).add_config_shared("export", "api", 0.5);
+        assert!(limiter.allowed("user1", "search").unwrap());
+        assert!(limiter.allowed("user1", "search").unwrap());
+        assert!(!limiter.allowed("user1", "export").unwrap());
+        assert_eq!(limiter.remaining("user1", "export").unwrap(), 0);
+
+        assert!(limiter.remove_config("search"));
+        assert!(limiter.allowed("user1", "search").is_err());
+    }
+
+    #[test]
+    fn test_shared_budget_entry_points() {
+        let mut limiter = RateLimiter::with_in_memory();
+        limiter.add_shared_budget("api", 2, 60000);
+        limiter.add_config_shared("search", "api", 1.0).add_config_shared("export", "api", 1.0).add_config("other", 5, 60000);
+        limiter.add_tenant_config("export", 10, 60000);
+        assert!(limiter.peek("user1", "export").unwrap());
+        assert!(limiter.retry_after("user1", "export").unwrap().is_none());
+        assert!(limiter.allowed("user1", "search").unwrap());
+        assert!(limiter.allowed("user1", "search").unwrap());
+
+        // export has its own share left, but every entry point sees the exhausted budget.
+        assert!(!limiter.peek("user1", "export").unwrap());
+        assert_eq!(limiter.remaining("user1", "export").unwrap(), 0);
+        assert!(limiter.retry_after("user1", "export").unwrap().is_some());
+        assert!(limiter.reset_at("user1", "export").unwrap().is_some());
+        assert!(!limiter.allowed_all("user1", &["other", "export"]).unwrap());
+        assert_eq!(limiter.remaining("user1", "other").unwrap(), 5);
+        assert!(!limiter.allowed_hierarchical("tenant1", "user1", "export").unwrap());
+
+        // the APIs that record against a single key refuse budget members.
+        assert!(limiter.reserve("user1", "export").is_err());
+        assert!(limiter.try_take("user1", "export", 1).is_err());
+        assert!(limiter.allowed_with_window("user1", "export", 1000).is_err());
+        assert!(limiter.allowed_first("user1", "export").is_err());
+
+        assert!(limiter.peek("user2", "export").unwrap());
+        assert!(limiter.allowed_all("user2", &["other", "export"]).unwrap());
+        assert!(limiter.allowed_hierarchical("tenant1", "user2", "export").unwrap());
+        assert!(!limiter.allowed("user2", "search").unwrap());
+    }
+
+    #[test]
+    fn test_allowed_at() {
+        let mut limiter = RateLimiter::with_in_memory().with_penalty("type2", 1, Duration::from_secs(10));
+        limiter.add_config("type1", 2, 1000).add_config("type2", 1, 1000);
+
+        // replayed events are placed by their own timestamps, not the system clock.
+        assert!(limiter.allowed_at("user1", "type1", 1000).unwrap());
+        assert!(limiter.allowed_at("user1", "type1", 1500).unwrap());
+        assert!(!limiter.allowed_at("user1", "type1", 1999).unwrap());
+        assert!(limiter.allowed_at("user1", "type1", 2000).unwrap());
+
+        // an event out of order still sees the later requests, so it cannot exceed the limit.
+        assert!(!limiter.allowed_at("user1", "type1", 1200).unwrap());
+
+        // penalties are decided by event time too.
+        assert!(limiter.allowed_at("user1", "type2", 0).unwrap());
+        assert!(!limiter.allowed_at("user1", "type2", 1).unwrap());
+        assert!(!limiter.allowed_at("user1", "type2", 5000).unwrap());
+        assert!(limiter.allowed_at("user1", "type2", 20000).unwrap());
+
+        let hour = Duration::from_secs(3600).as_millis() as u64;
+        limiter.add_scheduled_config("type3", Schedule::new().slot(Duration::from_secs(9 * 3600), Duration::from_secs(18 * 3600), 1, 1000));
+        assert!(limiter.allowed_at("user1", "type3", 9 * hour).unwrap());
+        assert!(limiter.allowed_at("user1", "type3", 8 * hour).is_err());
+    }
+
+    #[test]
+    fn test_scheduled_config() {
+        let hour = Duration::from_secs(3600);
+        // one millisecond before 9:00 on the second day since the epoch.
+        let clock = MockClock::new((hour * 33).as_millis() as u64 - 1);
+        let mut limiter = RateLimiter::with_in_memory_clock(clock.clone());
+        limiter.add_scheduled_config("type1", Schedule::new().slot(hour * 9, hour * 18, 3, 1000).slot(hour * 18, hour * 9, 1, 1000));
+        assert!(limiter.is_configured("type1"));
+        assert_eq!(limiter.configured_types(), vec!["type1"]);
+        assert_eq!(limiter.get_config("type1"), Some((1, Duration::from_secs(1))));
+
+        assert!(limiter.allowed("user1", "type1").unwrap());
+        assert!(!limiter.allowed("user1", "type1").unwrap());
+
+        // at the exact switch time the business hours slot applies, and the earlier request still counts.
+        clock.advance(Duration::from_millis(1));
+        assert_eq!(limiter.get_config("type1"), Some((3, Duration::from_secs(1))));
+        assert!(limiter.allowed("user1", "type1").unwrap());
+        assert!(limiter.allowed("user1", "type1").unwrap());
+        assert!(!limiter.allowed("user1", "type1").unwrap());
+
+        // a schedule that leaves gaps falls back to the plain config.
+        limiter.add_config("type2", 2, 1000).add_scheduled_config("type2", Schedule::new().slot(hour * 18, hour * 24, 1, 1000));
+        assert!(limiter.allowed("user1", "type2").unwrap());
+        assert!(limiter.allowed("user1", "type2").unwrap());
+        clock.set(hour.as_millis() as u64 * 42);
+        assert!(limiter.allowed("user1", "type2").unwrap());
+        assert!(!limiter.allowed("user1", "type2").unwrap());
+
+        assert!(limiter.remove_config("type1"));
+        assert!(limiter.allowed("user1", "type1").is_err());
+    }
+
+    #[test]
+    fn test_allowed_once() {
+        let clock = MockClock::new(0);
+        let mut limiter = RateLimiter::with_in_memory_clock(clock.clone());
+        limiter.add_config("type1", 2, 1000);
+
+        assert!(limiter.allowed_once("user1", "type1", "request-1").unwrap());
+        assert!(limiter.allowed_once("user1", "type1", "request-1").unwrap());
+        assert!(limiter.allowed_once("user1", "type1", "request-2").unwrap());
+        assert!(!limiter.allowed_once("user1", "type1", "request-3").unwrap());
+        // tokens are per user.
+        assert!(limiter.allowed_once("user2", "type1", "request-1").unwrap());
+        assert_eq!(limiter.remaining("user2", "type1").unwrap(), 1);
+
+        // a denied token is checked again, and a token expires with the window.
+        clock.set(1000);
+        assert!(limiter.allowed_once("user1", "type1", "request-3").unwrap());
+        assert!(limiter.allowed_once("user1", "type1", "request-1").unwrap());
+        assert!(!limiter.allowed("user1", "type1").unwrap());
+    }
+
+    #[test]
+    fn test_try_take() {
+        let clock = MockClock::new(1000);
+        let mut limiter = RateLimiter::with_in_memory_clock(clock.clone());
+        limiter.add_config("type1", 5, 1000).add_config_token_bucket("type2", 5, 1.0);
+
+        assert_eq!(limiter.try_take("user1", "type1", 3).unwrap(), 3);
+        clock.set(1500);
+        assert_eq!(limiter.try_take("user1", "type1", 3).unwrap(), 2);
+        assert_eq!(limiter.try_take("user1", "type1", 3).unwrap(), 0);
+        assert_eq!(limiter.inspect("user1", "type1").unwrap(), vec![1000, 1000, 1000, 1500, 1500]);
+
+        // the first three units expire together, and only they are given back.
+        clock.set(2000);
+        assert_eq!(limiter.try_take("user1", "type1", 10).unwrap(), 3);
+        assert_eq!(limiter.remaining("user1", "type1").unwrap(), 0);
+        assert_eq!(limiter.try_take("user1", "type1", 0).unwrap(), 0);
+        assert!(limiter.try_take("user1", "type2", 1).is_err());
+    }
+
+    #[test]
+    fn test_zero_capacity_and_window() {
+        let mut limiter = RateLimiter::with_in_memory();
+        limiter
+            .add_config("blocked", 0, 60000)
+            .add_config("unlimited", 5, 0)
+            .add_config_fixed_window("unlimited_fixed", 5, 0)
+            .add_config_sliding_counter("unlimited_counter", 5, 0)
+            .add_config("both", 0, 0);
+
+        for _ in 0..10 {
+            assert!(!limiter.allowed("user1", "blocked").unwrap());
+            assert!(limiter.allowed("user1", "unlimited").unwrap());
+            assert!(limiter.allowed("user1", "unlimited_fixed").unwrap());
+            assert!(limiter.allowed("user1", "unlimited_counter").unwrap());
+            assert!(!limiter.allowed("user1", "both").unwrap());
+        }
+        assert!(!limiter.allowed_n("user1", "blocked", 0).unwrap());
+        assert!(limiter.allowed_n("user1", "unlimited", 5).unwrap());
+        // nothing was recorded for the unlimited types.
+        assert_eq!(limiter.stats().unwrap().total_entries, 0);
+    }
+
+    #[test]
+    fn test_inspect() {
+        let clock = MockClock::new(1000);
+        let mut limiter = RateLimiter::with_in_memory_clock(clock.clone());
+        limiter.add_config("type1", 3, 1000).add_config_token_bucket("type2", 1, 1.0);
+
+        assert!(limiter.inspect("user1", "type1").unwrap().is_empty());
+        for now in [1000, 1400, 1400, 1500] {
+            clock.set(now);
+            limiter.allowed("user1", "type1").unwrap();
+        }
+        assert_eq!(limiter.inspect("user1", "type1").unwrap(), vec![1000, 1400, 1400]);
+
+        // the request at 1000 expires a window later.
+        clock.set(2000);
+        assert_eq!(limiter.inspect("user1", "type1").unwrap(), vec![1400, 1400]);
+        assert!(limiter.inspect("user1", "type2").is_err());
+    }
+
+    #[test]
+    fn test_shadow_mode() {
+        use std::sync::{Arc, Mutex};
+
+        #[derive(Default, Clone)]
+        struct Recorder(Arc<Mutex<Vec<bool>>>);
+
+        impl MetricsSink for Recorder {
+            fn on_allowed(&self, _request_type: &str) {
+                self.0.lock().unwrap().push(true);
+            }
+
+            fn on_denied(&self, _request_type: &str) {
+                self.0.lock().unwrap().push(false);
+            }
+        }
+
+        let recorder = Recorder::default();
+        let mut limiter = RateLimiter::with_in_memory().with_metrics(recorder.clone()).with_shadow_mode(true);
+        limiter.add_config("type1", 2, 60000).add_config_token_bucket("type2", 1, 0.001);
+
+        for _ in 0..4 {
+            assert!(limiter.allowed("user1", "type1").unwrap());
+        }
+        assert!(limiter.allowed("user1", "type2").unwrap());
+        assert!(limiter.allowed("user1", "type2").unwrap());
+        assert!(limiter.check("user1", "type1").unwrap().allowed);
+        assert_eq!(limiter.allowed_many(&["user1", "user2"], "type1").unwrap(), vec![true, true]);
+
+        // the decisions still went through the limits: only the first two requests were recorded.
+        assert_eq!(limiter.remaining("user1", "type1").unwrap(), 0);
+        assert_eq!(*recorder.0.lock().unwrap(), vec![true, true, false, false, true, false, false, false, true]);
+    }
+
+    #[test]
+    fn test_metrics() {
+        use std::sync::{Arc, Mutex};
+
+        #[derive(Default, Clone)]
+        struct Recorder(Arc<Mutex<Vec<(String, bool)>>>);
+
+        impl MetricsSink for Recorder {
+            fn on_allowed(&self, request_type: &str) {
+                self.0.lock().unwrap().push((request_type.to_string(), true));
+            }
+
+            fn on_denied(&self, request_type: &str) {
+                self.0.lock().unwrap().push((request_type.to_string(), false));
+            }
+        }
+
+        let recorder = Recorder::default();
+        let mut limiter = RateLimiter::with_in_memory().with_metrics(recorder.clone());
+        limiter.add_config("type1", 1, 60000).add_config_token_bucket("type2", 1, 1.0);
+
+        assert!(limiter.allowed("user12345", "type1").unwrap());
+        assert!(!limiter.allowed("user12345", "type1").unwrap());
+        assert!(!limiter.allowed_n("user12345", "type2", 2).unwrap());
+        // errors are not decisions, so they are not reported.
+        assert!(limiter.allowed("user12345", "type3").is_err());
+
+        let expected = vec![("type1".to_string(), true), ("type1".to_string(), false), ("type2".to_string(), false)];
+        assert_eq!(*recorder.0.lock().unwrap(), expected);
     }
 
 }
\ No newline at end of file
@@ -0,0 +1,61 @@
+use std::fmt;
+use std::sync::Arc;
+
+use crate::error::RateLimiterError;
+use crate::storage::Storage;
+
+/// A request counted by [`RateLimiter::reserve`](crate::RateLimiter::reserve) that is given
+/// back unless it is committed.
+///
+/// Dropping the reservation releases the request, ignoring storage errors; call
+/// [`cancel`](Reservation::cancel) to see them. A reservation shares the limiter's storage, so
+/// it can outlive the limiter and move across threads.
+#[must_use = "dropping a reservation releases it, so commit it once the work is done"]
+pub struct Reservation {
+    // the storage, key and timestamp of the recorded request, or `None` if nothing was recorded
+    recorded: Option<(Arc<dyn Storage>, String, u64)>,
+}
+
+impl Reservation {
+    /// Creates a reservation for a request recorded at `timestamp` under `key`.
+    pub(crate) fn new(storage: Arc<dyn Storage>, key: String, timestamp: u64) -> Self {
+        Self { recorded: Some((storage, key, timestamp)) }
+    }
+
+    /// Creates a reservation that has nothing to release, for requests allowed without
+    /// being recorded.
+    pub(crate) fn unrecorded() -> Self {
+        Self { recorded: None }
+    }
+
+    /// Keeps the request counted.
+    pub fn commit(mut self) {
+        self.recorded = None;
+    }
+
+    /// Gives the request back right away, returning any storage error that dropping the
+    /// reservation would ignore.
+    pub fn cancel(mut self) -> Result<(), RateLimiterError> {
+        match self.recorded.take() {
+            Some((storage, key, timestamp)) => Ok(storage.release(&key, timestamp)?),
+            None => Ok(()),
+        }
+    }
+}
+
+impl fmt::Debug for Reservation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Reservation")
+            .field("key", &self.recorded.as_ref().map(|(_, key, _)| key))
+            .field("timestamp", &self.recorded.as_ref().map(|&(_, _, timestamp)| timestamp))
+            .finish()
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        if let Some((storage, key, timestamp)) = self.recorded.take() {
+            let _ = storage.release(&key, timestamp);
+        }
+    }
+}
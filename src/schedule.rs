@@ -0,0 +1,102 @@
+use std::time::Duration;
+
+const DAY_MILLIS: u64 = 24 * 60 * 60 * 1000;
+
+/// Sliding window limits that take turns by time of day, for
+/// [`RateLimiter::add_scheduled_config`](crate::RateLimiter::add_scheduled_config).
+///
+/// Slots are given as offsets since midnight UTC and cover `start..end`, so a request at the
+/// exact switch time is decided by the slot that starts then. A slot whose end is before its
+/// start wraps past midnight. Where slots overlap, the one added first wins.
+///
+/// # Examples
+///
+/// ```
+/// use ratelimiter_rs::Schedule;
+/// use std::time::Duration;
+///
+/// let hour = Duration::from_secs(3600);
+/// // 1000 requests per minute from 9:00 to 18:00, 100 per minute overnight.
+/// let schedule = Schedule::new().slot(hour * 9, hour * 18, 1000, 60000).slot(hour * 18, hour * 9, 100, 60000);
+/// assert_eq!(schedule.limit_at(hour * 18), Some((100, Duration::from_secs(60))));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Schedule {
+    // (start, end) in milliseconds since midnight, capacity, window in milliseconds
+    slots: Vec<(u64, u64, u32, u64)>,
+}
+
+impl Schedule {
+    /// Creates a schedule without any slots.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a slot that allows `capacity` requests per `window_time_millis` from `start` until
+    /// `end`, both measured from midnight UTC.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `start` or `end` is longer than a day, or if they are equal.
+    pub fn slot(mut self, start: Duration, end: Duration, capacity: u32, window_time_millis: u64) -> Self {
+        let start = u64::try_from(start.as_millis()).unwrap_or(u64::MAX);
+        let end = u64::try_from(end.as_millis()).unwrap_or(u64::MAX);
+        assert!(start <= DAY_MILLIS && end <= DAY_MILLIS, "schedule slots must lie within a day");
+        assert!(start != end, "schedule slots must not be empty");
+        self.slots.push((start % DAY_MILLIS, end % DAY_MILLIS, capacity, window_time_millis));
+        self
+    }
+
+    /// Returns the capacity and window in effect `time_of_day` after midnight UTC, or `None`
+    /// if no slot covers it.
+    pub fn limit_at(&self, time_of_day: Duration) -> Option<(u32, Duration)> {
+        let millis = u64::try_from(time_of_day.as_millis()).unwrap_or(u64::MAX) % DAY_MILLIS;
+        self.position(millis).map(|i| {
+            let (_, _, capacity, window_time_millis) = self.slots[i];
+            (capacity, Duration::from_millis(window_time_millis))
+        })
+    }
+
+    /// Returns the index of the first slot covering `millis` since midnight.
+    pub(crate) fn position(&self, millis: u64) -> Option<usize> {
+        self.slots.iter().position(|&(start, end, _, _)| {
+            if start < end {
+                (start..end).contains(&millis)
+            } else {
+                millis >= start || millis < end
+            }
+        })
+    }
+
+    /// Returns the capacity and window in milliseconds of every slot, in the order they were added.
+    pub(crate) fn limits(&self) -> impl Iterator<Item = (u32, u64)> + '_ {
+        self.slots.iter().map(|&(_, _, capacity, window_time_millis)| (capacity, window_time_millis))
+    }
+}
+
+/// Returns how many milliseconds of the current UTC day have passed at `now_millis`.
+pub(crate) fn time_of_day(now_millis: u64) -> u64 {
+    now_millis % DAY_MILLIS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_boundaries() {
+        let hour = Duration::from_secs(3600);
+        let schedule = Schedule::new().slot(hour * 9, hour * 18, 10, 1000).slot(hour * 22, hour * 6, 1, 1000);
+
+        assert_eq!(schedule.limit_at(hour * 9 - Duration::from_millis(1)), None);
+        assert_eq!(schedule.limit_at(hour * 9), Some((10, Duration::from_secs(1))));
+        assert_eq!(schedule.limit_at(hour * 18 - Duration::from_millis(1)), Some((10, Duration::from_secs(1))));
+        assert_eq!(schedule.limit_at(hour * 18), None);
+
+        // the overnight slot wraps past midnight.
+        assert_eq!(schedule.limit_at(hour * 22), Some((1, Duration::from_secs(1))));
+        assert_eq!(schedule.limit_at(Duration::ZERO), Some((1, Duration::from_secs(1))));
+        assert_eq!(schedule.limit_at(hour * 24), Some((1, Duration::from_secs(1))));
+        assert_eq!(schedule.limit_at(hour * 6), None);
+    }
+}
@@ -0,0 +1,91 @@
+//! The sliding window log algorithm on its own, for logs kept by the caller.
+//!
+//! Everything here only needs `core` and `alloc`, so it builds without the `std` feature, e.g.
+//! for embedded or WASM targets that keep their logs in a static or a single-threaded map.
+//! [`InMemoryStorage`](crate::InMemoryStorage) decides its sliding windows with it.
+//!
+//! # Examples
+//!
+//! ```
+//! use ratelimiter_rs::sliding_window;
+//! use std::time::Duration;
+//!
+//! let mut request_logs = Vec::new();
+//! let window = Duration::from_secs(1);
+//! assert!(sliding_window::check_and_record(&mut request_logs, 2, 1, window, 0));
+//! assert!(sliding_window::check_and_record(&mut request_logs, 2, 1, window, 500));
+//! assert!(!sliding_window::check_and_record(&mut request_logs, 2, 1, window, 999));
+//!
+//! // the first request expires a full window after it was made.
+//! assert!(sliding_window::check_and_record(&mut request_logs, 2, 1, window, 1000));
+//! ```
+
+use alloc::vec::Vec;
+use core::time::Duration;
+
+/// Whether a request logged at `timestamp` still counts in the sliding window ending at `now`.
+///
+/// Requests expire exactly one window after they were made, so the window covers
+/// `(now - window, now]`. Every backend uses this boundary, so they all decide alike.
+pub fn in_window(timestamp: u64, window: Duration, now: u64) -> bool {
+    timestamp.saturating_add(window.as_millis() as u64) > now
+}
+
+/// Drops every timestamp of a log made a full window or more before `now`.
+pub fn evict(request_logs: &mut Vec<u64>, window: Duration, now: u64) {
+    request_logs.retain(|&timestamp| in_window(timestamp, window, now));
+}
+
+/// Records `cost` requests at `now` in a log that holds only the timestamps that still count,
+/// if they fit in `capacity`, and returns whether they did.
+///
+/// Timestamps after `now`, left behind when the clock jumped backwards, still count, so a
+/// jump never frees up capacity. The log never keeps more than `capacity` timestamps.
+pub fn record(request_logs: &mut Vec<u64>, capacity: u32, cost: u32, now: u64) -> bool {
+    let is_allowed = request_logs.len() + cost as usize <= capacity as usize;
+    if is_allowed {
+        request_logs.resize(request_logs.len() + cost as usize, now);
+    }
+    // logs can only outgrow the capacity when it was lowered since they were recorded.
+    cap(request_logs, capacity);
+    is_allowed
+}
+
+/// Evicts the timestamps of a log that left the window ending at `now`, then records `cost`
+/// requests at `now` if they fit in `capacity`, and returns whether they did.
+pub fn check_and_record(request_logs: &mut Vec<u64>, capacity: u32, cost: u32, window: Duration, now: u64) -> bool {
+    evict(request_logs, window, now);
+    record(request_logs, capacity, cost, now)
+}
+
+/// Drops the oldest timestamps of a log beyond its newest `capacity`.
+///
+/// A log at capacity denies every request no matter how many older timestamps it holds, and
+/// the newest `capacity` are the ones that decide when the next request is allowed, so the
+/// rest carry no information. Timestamps are appended in time order, so the oldest come first,
+/// unless the clock jumped backwards; a monotonic clock rules that out.
+pub(crate) fn cap(request_logs: &mut Vec<u64>, capacity: u32) {
+    let excess = request_logs.len().saturating_sub(capacity as usize);
+    request_logs.drain(..excess);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn test_record() {
+        let mut request_logs = vec![0, 10];
+        assert!(record(&mut request_logs, 3, 1, 20));
+        assert!(!record(&mut request_logs, 3, 1, 30));
+        assert_eq!(request_logs, vec![0, 10, 20]);
+
+        // a lowered capacity keeps only the newest timestamps.
+        assert!(!record(&mut request_logs, 2, 1, 30));
+        assert_eq!(request_logs, vec![10, 20]);
+        assert!(!record(&mut request_logs, 5, 4, 30));
+        assert!(record(&mut request_logs, 5, 3, 30));
+        assert_eq!(request_logs, vec![10, 20, 30, 30, 30]);
+    }
+}
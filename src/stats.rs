@@ -0,0 +1,13 @@
+/// How much state a [`RateLimiter`](crate::RateLimiter) holds, as returned by
+/// [`RateLimiter::stats`](crate::RateLimiter::stats), e.g. to tell whether idle eviction keeps up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LimiterStats {
+    /// How many keys the storage holds state for, i.e. pairs of a user and a request type,
+    /// including further tiers and tenants.
+    pub tracked_users: usize,
+    /// How many entries those keys hold together: one per timestamp of a request log, and one
+    /// per bucket or counter.
+    pub total_entries: usize,
+    /// How many request types have a config of their own.
+    pub configured_types: usize,
+}
@@ -1,8 +1,312 @@
-use std::collections::HashMap;
-use std::sync::{Mutex, Arc};
+use std::error::Error;
+#[cfg(feature = "async")]
+use std::future::Future;
+#[cfg(feature = "async")]
+use std::pin::Pin;
+use std::time::Duration;
 
-#[derive(Clone)]
-pub(crate) enum Storage {
-    InMemory(Arc<Mutex<HashMap<String, Vec<u64>>>>),
-    Redis(redis::Client),
+use crate::error::RateLimiterError;
+
+#[cfg(feature = "async")]
+mod async_storage;
+mod cached;
+mod eviction;
+mod in_memory;
+#[cfg(feature = "redis")]
+mod redis;
+#[cfg(feature = "sled")]
+mod sled;
+
+#[cfg(feature = "async")]
+pub use async_storage::AsyncStorage;
+#[cfg(feature = "async")]
+pub(crate) use async_storage::AsyncOnlyStorage;
+pub use cached::CachedStorage;
+pub use eviction::{EvictionPolicy, WindowEviction};
+pub use in_memory::InMemoryStorage;
+#[cfg(feature = "redis")]
+pub use self::redis::RedisStorage;
+#[cfg(feature = "sled")]
+pub use self::sled::SledStorage;
+
+/// The sliding window counter check shared by every backend.
+///
+/// Rolls the `(window_id, previous, current)` counts stored for a key forward to the window
+/// `now` falls into, then adds `cost` to the current count if the estimate stays within
+/// `capacity`. The estimate weighs the previous window by how much of it still overlaps the
+/// sliding window ending at `now`, assuming its requests were spread evenly. Returns the new
+/// counts and the decision.
+fn slide_counter(counts: Option<(u64, u32, u32)>, capacity: u32, cost: u32, window: Duration, now: u64) -> ((u64, u32, u32), bool) {
+    let window_millis = (window.as_millis() as u64).max(1);
+    let window_id = now / window_millis;
+    let (previous, current) = match counts {
+        Some((stored_id, previous, current)) if stored_id == window_id => (previous, current),
+        Some((stored_id, _, current)) if stored_id + 1 == window_id => (current, 0),
+        _ => (0, 0),
+    };
+
+    let elapsed = now % window_millis;
+    let estimate = previous as f64 * (window_millis - elapsed) as f64 / window_millis as f64 + current as f64;
+    if estimate + cost as f64 <= capacity as f64 {
+        ((window_id, previous, current + cost), true)
+    } else {
+        ((window_id, previous, current), false)
+    }
+}
+
+/// The boxed future returned by the async storage methods.
+#[cfg(feature = "async")]
+pub type StorageFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, Box<dyn Error + Send + Sync>>> + Send + 'a>>;
+
+/// A backend that keeps the per-key request logs used by the sliding window.
+///
+/// Implement this trait to plug a custom backend (e.g. Memcached) into
+/// [`RateLimiter::with_storage`](crate::RateLimiter::with_storage).
+pub trait Storage: Send + Sync {
+    /// Evicts entries that fell out of `window`, i.e. were made at or before `now - window`,
+    /// then records `cost` requests at `now` if the window still has room for all of them.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The storage key identifying the user and request type.
+    /// * `capacity` - The maximum number of requests allowed in the window.
+    /// * `cost` - The number of requests to record at once.
+    /// * `window` - The length of the sliding window.
+    /// * `now` - The current time in milliseconds since the unix epoch.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(true)` if the requests were recorded.
+    /// * `Ok(false)` if the window has no room for `cost` more requests; nothing is recorded.
+    /// * `Err` if the backend fails.
+    fn check_and_record(&self, key: &str, capacity: u32, cost: u32, window: Duration, now: u64) -> Result<bool, Box<dyn Error>>;
+
+    /// Like [`check_and_record`](Storage::check_and_record), but also returns how many requests
+    /// are in the window after the decision, for reporting the remaining quota.
+    ///
+    /// The default implementation follows up with [`count`](Storage::count); network backends
+    /// should override it to answer in a single round trip.
+    fn check_and_record_with_count(&self, key: &str, capacity: u32, cost: u32, window: Duration, now: u64) -> Result<(bool, u32), Box<dyn Error>> {
+        let is_allowed = self.check_and_record(key, capacity, cost, window, now)?;
+        Ok((is_allowed, self.count(key, window, now)?))
+    }
+
+    /// Evicts entries that fell out of `window` and returns how many requests remain in it,
+    /// without recording a new one.
+    fn count(&self, key: &str, window: Duration, now: u64) -> Result<u32, Box<dyn Error>>;
+
+    /// Evicts entries that fell out of `window` and returns the oldest timestamp left in it,
+    /// or `None` if the window is empty.
+    fn oldest(&self, key: &str, window: Duration, now: u64) -> Result<Option<u64>, Box<dyn Error>>;
+
+    /// Deletes all state kept for `key`.
+    fn remove(&self, key: &str) -> Result<(), Box<dyn Error>>;
+
+    /// Removes one request recorded at `timestamp` from the sliding window log for `key`, if
+    /// there is one, giving its capacity back.
+    ///
+    /// The default implementation returns an error, so backends only need to implement it to
+    /// support [`RateLimiter::reserve`](crate::RateLimiter::reserve).
+    fn release(&self, key: &str, timestamp: u64) -> Result<(), Box<dyn Error>> {
+        let _ = (key, timestamp);
+        Err(RateLimiterError::Message("releasing requests is not supported by this storage".to_string()).into())
+    }
+
+    /// Evicts entries that fell out of `window` and returns the timestamps left in it, oldest
+    /// first, for diagnostics.
+    ///
+    /// The default implementation returns an error, so backends only need to implement it to
+    /// support [`RateLimiter::inspect`](crate::RateLimiter::inspect).
+    fn timestamps(&self, key: &str, window: Duration, now: u64) -> Result<Vec<u64>, Box<dyn Error>> {
+        let _ = (key, window, now);
+        Err(RateLimiterError::Message("listing timestamps is not supported by this storage".to_string()).into())
+    }
+
+    /// Deletes the state of every key that starts with `prefix` and ends with `suffix`,
+    /// returning how many keys were removed.
+    ///
+    /// The default implementation returns an error, since not every backend can enumerate
+    /// its keys.
+    fn remove_matching(&self, prefix: &str, suffix: &str) -> Result<u64, Box<dyn Error>> {
+        let _ = (prefix, suffix);
+        Err(RateLimiterError::Message("removing keys by pattern is not supported by this storage".to_string()).into())
+    }
+
+    /// Deletes the state of every key that has not seen a request for longer than `max_idle`,
+    /// returning how many keys were removed.
+    ///
+    /// Callers pass the longest window in use, so the removed state would not have affected
+    /// any decision. The default implementation removes nothing, which suits backends that
+    /// expire keys on their own.
+    fn remove_idle(&self, max_idle: Duration, now: u64) -> Result<u64, Box<dyn Error>> {
+        let _ = (max_idle, now);
+        Ok(0)
+    }
+
+    /// Returns how many keys the storage holds and how many entries they hold together: one per
+    /// timestamp of a request log, and one per bucket or counter.
+    ///
+    /// Expired state that has not been evicted yet is included. The default implementation
+    /// returns an error, since counting may mean scanning a whole remote keyspace.
+    fn usage(&self) -> Result<(usize, usize), Box<dyn Error>> {
+        Err(RateLimiterError::Message("usage stats are not supported by this storage".to_string()).into())
+    }
+
+    /// Names the backend, e.g. for the `Debug` output of a [`RateLimiter`](crate::RateLimiter).
+    ///
+    /// The default implementation returns `"custom"`.
+    fn kind(&self) -> &'static str {
+        "custom"
+    }
+
+    /// Atomically records one request in every sliding window log in `checks`, but only if
+    /// each of them has capacity left; if any would be denied, none is recorded.
+    ///
+    /// Each check is a `(key, capacity, window)` triple, and keys must be distinct. The default
+    /// implementation returns an error, since the checks must happen under a single lock or
+    /// transaction.
+    fn check_and_record_all(&self, checks: &[(&str, u32, Duration)], now: u64) -> Result<bool, Box<dyn Error>> {
+        let _ = (checks, now);
+        Err(RateLimiterError::Message("batched checks are not supported by this storage".to_string()).into())
+    }
+
+    /// Records one request in each sliding window log in `checks` that has capacity left,
+    /// returning the decision for every check in order.
+    ///
+    /// Unlike [`check_and_record_all`](Storage::check_and_record_all), the checks are independent,
+    /// and the same key may appear more than once. The default implementation calls
+    /// [`check_and_record`](Storage::check_and_record) for each check; backends should override
+    /// it to amortize locking or round trips over the batch.
+    fn check_and_record_each(&self, checks: &[(&str, u32, Duration)], now: u64) -> Result<Vec<bool>, Box<dyn Error>> {
+        checks.iter().map(|&(key, capacity, window)| self.check_and_record(key, capacity, 1, window, now)).collect()
+    }
+
+    /// Records up to `desired` requests in the sliding window log for `key`, as many as fit in
+    /// `capacity`, returning how many were recorded.
+    ///
+    /// The default implementation counts the window first, then records what fit; if a
+    /// concurrent request took the room in between, it counts again. Backends should override
+    /// it to do both at once.
+    fn take_available(&self, key: &str, capacity: u32, desired: u32, window: Duration, now: u64) -> Result<u32, Box<dyn Error>> {
+        loop {
+            let granted = desired.min(capacity.saturating_sub(self.count(key, window, now)?));
+            if granted == 0 || self.check_and_record(key, capacity, granted, window, now)? {
+                return Ok(granted);
+            }
+        }
+    }
+
+    /// Refills the token bucket for `key` at `refill_per_sec` up to `capacity`, then takes
+    /// `cost` tokens from it if that many are available.
+    ///
+    /// The default implementation returns an error, so backends only need to implement it
+    /// if they are used with token bucket configs.
+    fn take_tokens(&self, key: &str, capacity: u32, refill_per_sec: f64, cost: u32, now: u64) -> Result<bool, Box<dyn Error>> {
+        let _ = (key, capacity, refill_per_sec, cost, now);
+        Err(RateLimiterError::Message("token bucket is not supported by this storage".to_string()).into())
+    }
+
+    /// Adds `cost` to the counter of the fixed window `now` falls into, resetting it when a new
+    /// window starts, if the count stays within `capacity`.
+    ///
+    /// Windows are aligned to the unix epoch, so the window id is `now / window`. Like
+    /// [`take_tokens`](Storage::take_tokens), the default implementation returns an error.
+    fn increment_window(&self, key: &str, capacity: u32, cost: u32, window: Duration, now: u64) -> Result<bool, Box<dyn Error>> {
+        let _ = (key, capacity, cost, window, now);
+        Err(RateLimiterError::Message("fixed window is not supported by this storage".to_string()).into())
+    }
+
+    /// Adds `cost` to the sliding window counter for `key` if the weighted count of the current
+    /// and previous fixed windows stays within `capacity`.
+    ///
+    /// Only the counts of two epoch-aligned windows are stored, so the estimate is approximate
+    /// but uses constant memory. Like [`take_tokens`](Storage::take_tokens), the default
+    /// implementation returns an error.
+    fn increment_sliding_counter(&self, key: &str, capacity: u32, cost: u32, window: Duration, now: u64) -> Result<bool, Box<dyn Error>> {
+        let _ = (key, capacity, cost, window, now);
+        Err(RateLimiterError::Message("sliding window counter is not supported by this storage".to_string()).into())
+    }
+
+    /// Drains the leaky bucket for `key` at `leak_per_sec` since its last request, then adds
+    /// `cost` to it if the level stays within `bucket_size`.
+    ///
+    /// A bucket starts empty. Like [`take_tokens`](Storage::take_tokens), the default
+    /// implementation returns an error.
+    fn leak_and_fill(&self, key: &str, bucket_size: u32, leak_per_sec: f64, cost: u32, now: u64) -> Result<bool, Box<dyn Error>> {
+        let _ = (key, bucket_size, leak_per_sec, cost, now);
+        Err(RateLimiterError::Message("leaky bucket is not supported by this storage".to_string()).into())
+    }
+
+    /// Applies the generic cell rate algorithm to `key`: `cost` requests are allowed if the
+    /// theoretical arrival time they push the key to stays within `burst` emission intervals
+    /// of `now`, where one interval is `1000 / rate_per_sec` milliseconds.
+    ///
+    /// Only the theoretical arrival time is stored. Like [`take_tokens`](Storage::take_tokens),
+    /// the default implementation returns an error.
+    fn advance_tat(&self, key: &str, burst: u32, rate_per_sec: f64, cost: u32, now: u64) -> Result<bool, Box<dyn Error>> {
+        let _ = (key, burst, rate_per_sec, cost, now);
+        Err(RateLimiterError::Message("GCRA is not supported by this storage".to_string()).into())
+    }
+
+    /// Async counterpart of [`check_and_record`](Storage::check_and_record).
+    ///
+    /// The default implementation runs the synchronous method in place, which is fine
+    /// for backends that never block. Network backends should override it.
+    #[cfg(feature = "async")]
+    fn check_and_record_async<'a>(&'a self, key: &'a str, capacity: u32, cost: u32, window: Duration, now: u64) -> StorageFuture<'a, bool> {
+        let result = self
+            .check_and_record(key, capacity, cost, window, now)
+            .map_err(|e| RateLimiterError::from(e).into());
+        Box::pin(async move { result })
+    }
+
+    /// Async counterpart of [`take_tokens`](Storage::take_tokens), with the same default behavior
+    /// as [`check_and_record_async`](Storage::check_and_record_async).
+    #[cfg(feature = "async")]
+    fn take_tokens_async<'a>(&'a self, key: &'a str, capacity: u32, refill_per_sec: f64, cost: u32, now: u64) -> StorageFuture<'a, bool> {
+        let result = self
+            .take_tokens(key, capacity, refill_per_sec, cost, now)
+            .map_err(|e| RateLimiterError::from(e).into());
+        Box::pin(async move { result })
+    }
+
+    /// Async counterpart of [`increment_window`](Storage::increment_window), with the same default
+    /// behavior as [`check_and_record_async`](Storage::check_and_record_async).
+    #[cfg(feature = "async")]
+    fn increment_window_async<'a>(&'a self, key: &'a str, capacity: u32, cost: u32, window: Duration, now: u64) -> StorageFuture<'a, bool> {
+        let result = self
+            .increment_window(key, capacity, cost, window, now)
+            .map_err(|e| RateLimiterError::from(e).into());
+        Box::pin(async move { result })
+    }
+
+    /// Async counterpart of [`increment_sliding_counter`](Storage::increment_sliding_counter), with
+    /// the same default behavior as [`check_and_record_async`](Storage::check_and_record_async).
+    #[cfg(feature = "async")]
+    fn increment_sliding_counter_async<'a>(&'a self, key: &'a str, capacity: u32, cost: u32, window: Duration, now: u64) -> StorageFuture<'a, bool> {
+        let result = self
+            .increment_sliding_counter(key, capacity, cost, window, now)
+            .map_err(|e| RateLimiterError::from(e).into());
+        Box::pin(async move { result })
+    }
+
+    /// Async counterpart of [`advance_tat`](Storage::advance_tat), with the same default
+    /// behavior as [`check_and_record_async`](Storage::check_and_record_async).
+    #[cfg(feature = "async")]
+    fn advance_tat_async<'a>(&'a self, key: &'a str, burst: u32, rate_per_sec: f64, cost: u32, now: u64) -> StorageFuture<'a, bool> {
+        let result = self
+            .advance_tat(key, burst, rate_per_sec, cost, now)
+            .map_err(|e| RateLimiterError::from(e).into());
+        Box::pin(async move { result })
+    }
+
+    /// Async counterpart of [`leak_and_fill`](Storage::leak_and_fill), with the same default
+    /// behavior as [`check_and_record_async`](Storage::check_and_record_async).
+    #[cfg(feature = "async")]
+    fn leak_and_fill_async<'a>(&'a self, key: &'a str, bucket_size: u32, leak_per_sec: f64, cost: u32, now: u64) -> StorageFuture<'a, bool> {
+        let result = self
+            .leak_and_fill(key, bucket_size, leak_per_sec, cost, now)
+            .map_err(|e| RateLimiterError::from(e).into());
+        Box::pin(async move { result })
+    }
 }
@@ -0,0 +1,56 @@
+use std::error::Error;
+use std::time::Duration;
+
+use crate::error::RateLimiterError;
+use super::{Storage, StorageFuture};
+
+/// A backend whose client is async-only, e.g. ScyllaDB, DynamoDB or an async Redis client,
+/// keeping the per-key request logs used by the sliding window.
+///
+/// This is the async counterpart of [`Storage`], with the same semantics for every method.
+/// Implementations return boxed futures, typically `Box::pin(async move { ... })`. Limiters
+/// created with [`RateLimiter::with_async_storage`](crate::RateLimiter::with_async_storage)
+/// only support `allowed_async`.
+pub trait AsyncStorage: Send + Sync {
+    /// Async counterpart of [`Storage::check_and_record`].
+    fn check_and_record<'a>(&'a self, key: &'a str, capacity: u32, cost: u32, window: Duration, now: u64) -> StorageFuture<'a, bool>;
+
+    /// Async counterpart of [`Storage::count`].
+    fn count<'a>(&'a self, key: &'a str, window: Duration, now: u64) -> StorageFuture<'a, u32>;
+
+    /// Async counterpart of [`Storage::oldest`].
+    fn oldest<'a>(&'a self, key: &'a str, window: Duration, now: u64) -> StorageFuture<'a, Option<u64>>;
+
+    /// Async counterpart of [`Storage::remove`].
+    fn remove<'a>(&'a self, key: &'a str) -> StorageFuture<'a, ()>;
+}
+
+/// Lets a limiter run on an [`AsyncStorage`], failing every synchronous operation.
+pub(crate) struct AsyncOnlyStorage<S>(pub(crate) S);
+
+/// The error returned by the synchronous operations of an [`AsyncOnlyStorage`].
+fn async_only() -> Box<dyn Error> {
+    RateLimiterError::Message("this storage is async-only, use allowed_async".to_string()).into()
+}
+
+impl<S: AsyncStorage> Storage for AsyncOnlyStorage<S> {
+    fn check_and_record(&self, _key: &str, _capacity: u32, _cost: u32, _window: Duration, _now: u64) -> Result<bool, Box<dyn Error>> {
+        Err(async_only())
+    }
+
+    fn count(&self, _key: &str, _window: Duration, _now: u64) -> Result<u32, Box<dyn Error>> {
+        Err(async_only())
+    }
+
+    fn oldest(&self, _key: &str, _window: Duration, _now: u64) -> Result<Option<u64>, Box<dyn Error>> {
+        Err(async_only())
+    }
+
+    fn remove(&self, _key: &str) -> Result<(), Box<dyn Error>> {
+        Err(async_only())
+    }
+
+    fn check_and_record_async<'a>(&'a self, key: &'a str, capacity: u32, cost: u32, window: Duration, now: u64) -> StorageFuture<'a, bool> {
+        self.0.check_and_record(key, capacity, cost, window, now)
+    }
+}
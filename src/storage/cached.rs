@@ -0,0 +1,261 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+use super::Storage;
+#[cfg(feature = "async")]
+use super::StorageFuture;
+
+/// What the cache last learned about a sliding window log from the inner storage.
+struct CachedLog {
+    capacity: u32,
+    window: Duration,
+    // requests in the window as of the last sync
+    count: u32,
+    // requests allowed locally since then, not yet recorded in the inner storage
+    pending: u32,
+    synced_at: u64,
+}
+
+/// Wraps a storage, typically Redis, with a local pre-check that allows requests of users far
+/// from their limit without a round trip.
+///
+/// After a sliding window check reaches the inner storage, the count it returns is cached for
+/// `ttl`. Until then, requests that keep the cached count plus the locally allowed ones within
+/// half the capacity are allowed locally, and recorded in the inner storage in one go on the
+/// next sync. Users closer to their limit are always checked against the inner storage.
+///
+/// This trades exactness for throughput: every app server may allow up to half the capacity
+/// locally before its requests are visible to the others, and those requests are recorded with
+/// the time of the sync. All other operations go straight to the inner storage, and so does
+/// `check_and_record_async`.
+pub struct CachedStorage<S> {
+    inner: S,
+    ttl: Duration,
+    logs: Mutex<HashMap<String, CachedLog>>,
+}
+
+impl<S: Storage> CachedStorage<S> {
+    /// Creates a cache in front of `inner` that trusts a synced count for `ttl`.
+    pub fn new(inner: S, ttl: Duration) -> Self {
+        Self { inner, ttl, logs: Mutex::new(HashMap::new()) }
+    }
+
+    /// Locks the cached logs, recovering them if a thread panicked while holding the lock.
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, CachedLog>> {
+        self.logs.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Drops the cached log of `key`, first recording its pending requests in the inner storage.
+    fn flush(&self, key: &str, now: u64) -> Result<(), Box<dyn Error>> {
+        let cached_log = self.lock().remove(key);
+        match cached_log {
+            Some(log) if log.pending > 0 => {
+                self.inner.check_and_record(key, log.capacity, log.pending, log.window, now)?;
+                Ok(())
+            },
+            _ => Ok(()),
+        }
+    }
+
+    /// Flushes every key of a batch of checks.
+    fn flush_all(&self, checks: &[(&str, u32, Duration)], now: u64) -> Result<(), Box<dyn Error>> {
+        checks.iter().try_for_each(|&(key, _, _)| self.flush(key, now))
+    }
+}
+
+/// Summarizes the cache by its number of cached keys, leaving out the keys themselves, which name users.
+impl<S: Storage + fmt::Debug> fmt::Debug for CachedStorage<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CachedStorage")
+            .field("inner", &self.inner)
+            .field("ttl", &self.ttl)
+            .field("cached_keys", &self.lock().len())
+            .finish()
+    }
+}
+
+impl<S: Storage> Storage for CachedStorage<S> {
+    fn check_and_record(&self, key: &str, capacity: u32, cost: u32, window: Duration, now: u64) -> Result<bool, Box<dyn Error>> {
+        let (is_allowed, _) = self.check_and_record_with_count(key, capacity, cost, window, now)?;
+        Ok(is_allowed)
+    }
+
+    fn check_and_record_with_count(&self, key: &str, capacity: u32, cost: u32, window: Duration, now: u64) -> Result<(bool, u32), Box<dyn Error>> {
+        let ttl_millis = u64::try_from(self.ttl.as_millis()).unwrap_or(u64::MAX);
+        if let Some(log) = self.lock().get_mut(key) {
+            let is_fresh = now.saturating_sub(log.synced_at) < ttl_millis && log.capacity == capacity && log.window == window;
+            let request_count = log.count + log.pending + cost;
+            if is_fresh && request_count <= capacity / 2 {
+                log.pending += cost;
+                return Ok((true, request_count));
+            }
+        }
+
+        // the lock is not held across round trips, so other keys are never blocked on them.
+        self.flush(key, now)?;
+        let (is_allowed, request_count) = self.inner.check_and_record_with_count(key, capacity, cost, window, now)?;
+        let log = CachedLog { capacity, window, count: request_count, pending: 0, synced_at: now };
+        self.lock().insert(key.to_string(), log);
+        Ok((is_allowed, request_count))
+    }
+
+    fn count(&self, key: &str, window: Duration, now: u64) -> Result<u32, Box<dyn Error>> {
+        self.flush(key, now)?;
+        self.inner.count(key, window, now)
+    }
+
+    fn oldest(&self, key: &str, window: Duration, now: u64) -> Result<Option<u64>, Box<dyn Error>> {
+        self.flush(key, now)?;
+        self.inner.oldest(key, window, now)
+    }
+
+    fn remove(&self, key: &str) -> Result<(), Box<dyn Error>> {
+        self.lock().remove(key);
+        self.inner.remove(key)
+    }
+
+    fn release(&self, key: &str, timestamp: u64) -> Result<(), Box<dyn Error>> {
+        self.flush(key, timestamp)?;
+        self.inner.release(key, timestamp)
+    }
+
+    fn timestamps(&self, key: &str, window: Duration, now: u64) -> Result<Vec<u64>, Box<dyn Error>> {
+        self.flush(key, now)?;
+        self.inner.timestamps(key, window, now)
+    }
+
+    fn remove_matching(&self, prefix: &str, suffix: &str) -> Result<u64, Box<dyn Error>> {
+        self.lock()
+            .retain(|key, _| !(key.len() >= prefix.len() + suffix.len() && key.starts_with(prefix) && key.ends_with(suffix)));
+        self.inner.remove_matching(prefix, suffix)
+    }
+
+    fn remove_idle(&self, max_idle: Duration, now: u64) -> Result<u64, Box<dyn Error>> {
+        let idle_since = now.saturating_sub(u64::try_from(max_idle.as_millis()).unwrap_or(u64::MAX));
+        self.lock().retain(|_, log| log.synced_at >= idle_since);
+        self.inner.remove_idle(max_idle, now)
+    }
+
+    fn usage(&self) -> Result<(usize, usize), Box<dyn Error>> {
+        self.inner.usage()
+    }
+
+    fn kind(&self) -> &'static str {
+        "cached"
+    }
+
+    fn check_and_record_all(&self, checks: &[(&str, u32, Duration)], now: u64) -> Result<bool, Box<dyn Error>> {
+        self.flush_all(checks, now)?;
+        self.inner.check_and_record_all(checks, now)
+    }
+
+    fn check_and_record_each(&self, checks: &[(&str, u32, Duration)], now: u64) -> Result<Vec<bool>, Box<dyn Error>> {
+        self.flush_all(checks, now)?;
+        self.inner.check_and_record_each(checks, now)
+    }
+
+    fn take_available(&self, key: &str, capacity: u32, desired: u32, window: Duration, now: u64) -> Result<u32, Box<dyn Error>> {
+        self.flush(key, now)?;
+        self.inner.take_available(key, capacity, desired, window, now)
+    }
+
+    fn take_tokens(&self, key: &str, capacity: u32, refill_per_sec: f64, cost: u32, now: u64) -> Result<bool, Box<dyn Error>> {
+        self.inner.take_tokens(key, capacity, refill_per_sec, cost, now)
+    }
+
+    fn increment_window(&self, key: &str, capacity: u32, cost: u32, window: Duration, now: u64) -> Result<bool, Box<dyn Error>> {
+        self.inner.increment_window(key, capacity, cost, window, now)
+    }
+
+    fn increment_sliding_counter(&self, key: &str, capacity: u32, cost: u32, window: Duration, now: u64) -> Result<bool, Box<dyn Error>> {
+        self.inner.increment_sliding_counter(key, capacity, cost, window, now)
+    }
+
+    fn leak_and_fill(&self, key: &str, bucket_size: u32, leak_per_sec: f64, cost: u32, now: u64) -> Result<bool, Box<dyn Error>> {
+        self.inner.leak_and_fill(key, bucket_size, leak_per_sec, cost, now)
+    }
+
+    fn advance_tat(&self, key: &str, burst: u32, rate_per_sec: f64, cost: u32, now: u64) -> Result<bool, Box<dyn Error>> {
+        self.inner.advance_tat(key, burst, rate_per_sec, cost, now)
+    }
+
+    #[cfg(feature = "async")]
+    fn check_and_record_async<'a>(&'a self, key: &'a str, capacity: u32, cost: u32, window: Duration, now: u64) -> StorageFuture<'a, bool> {
+        self.inner.check_and_record_async(key, capacity, cost, window, now)
+    }
+
+    #[cfg(feature = "async")]
+    fn take_tokens_async<'a>(&'a self, key: &'a str, capacity: u32, refill_per_sec: f64, cost: u32, now: u64) -> StorageFuture<'a, bool> {
+        self.inner.take_tokens_async(key, capacity, refill_per_sec, cost, now)
+    }
+
+    #[cfg(feature = "async")]
+    fn increment_window_async<'a>(&'a self, key: &'a str, capacity: u32, cost: u32, window: Duration, now: u64) -> StorageFuture<'a, bool> {
+        self.inner.increment_window_async(key, capacity, cost, window, now)
+    }
+
+    #[cfg(feature = "async")]
+    fn increment_sliding_counter_async<'a>(&'a self, key: &'a str, capacity: u32, cost: u32, window: Duration, now: u64) -> StorageFuture<'a, bool> {
+        self.inner.increment_sliding_counter_async(key, capacity, cost, window, now)
+    }
+
+    #[cfg(feature = "async")]
+    fn leak_and_fill_async<'a>(&'a self, key: &'a str, bucket_size: u32, leak_per_sec: f64, cost: u32, now: u64) -> StorageFuture<'a, bool> {
+        self.inner.leak_and_fill_async(key, bucket_size, leak_per_sec, cost, now)
+    }
+
+    #[cfg(feature = "async")]
+    fn advance_tat_async<'a>(&'a self, key: &'a str, burst: u32, rate_per_sec: f64, cost: u32, now: u64) -> StorageFuture<'a, bool> {
+        self.inner.advance_tat_async(key, burst, rate_per_sec, cost, now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::InMemoryStorage;
+
+    #[test]
+    fn test_local_pre_check() {
+        let storage = CachedStorage::new(InMemoryStorage::new(), Duration::from_secs(1));
+        let window = Duration::from_secs(60);
+
+        // the first request syncs, the next four fit in half the capacity and stay local.
+        for now in 0..5 {
+            assert!(storage.check_and_record("user1:type1", 10, 1, window, now).unwrap());
+        }
+        assert_eq!(storage.inner.count("user1:type1", window, 5).unwrap(), 1);
+
+        // the sixth would cross half the capacity, so the pending requests are synced first.
+        assert!(storage.check_and_record("user1:type1", 10, 1, window, 5).unwrap());
+        assert_eq!(storage.inner.count("user1:type1", window, 5).unwrap(), 6);
+
+        // from there on, every request is checked against the inner storage.
+        for now in 6..10 {
+            assert!(storage.check_and_record("user1:type1", 10, 1, window, now).unwrap());
+        }
+        assert!(!storage.check_and_record("user1:type1", 10, 1, window, 10).unwrap());
+        assert_eq!(storage.inner.count("user1:type1", window, 10).unwrap(), 10);
+    }
+
+    #[test]
+    fn test_cached_count_expires() {
+        let storage = CachedStorage::new(InMemoryStorage::new(), Duration::from_millis(100));
+        let window = Duration::from_secs(60);
+
+        assert!(storage.check_and_record("user1:type1", 10, 1, window, 0).unwrap());
+        assert!(storage.check_and_record("user1:type1", 10, 1, window, 50).unwrap());
+        assert_eq!(storage.inner.count("user1:type1", window, 50).unwrap(), 1);
+
+        // once the cached count is stale, the next request syncs.
+        assert!(storage.check_and_record("user1:type1", 10, 1, window, 100).unwrap());
+        assert_eq!(storage.inner.count("user1:type1", window, 100).unwrap(), 3);
+
+        // queries see the pending requests too.
+        assert!(storage.check_and_record("user1:type1", 10, 1, window, 101).unwrap());
+        assert_eq!(storage.count("user1:type1", window, 101).unwrap(), 4);
+    }
+}
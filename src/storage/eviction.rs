@@ -0,0 +1,54 @@
+use std::time::Duration;
+
+use crate::sliding_window;
+
+/// Decides which timestamps of a sliding window log [`InMemoryStorage`](crate::InMemoryStorage)
+/// keeps, e.g. to bound the memory of logs with huge capacities at the cost of accuracy.
+///
+/// Set a policy with [`InMemoryStorage::with_eviction_policy`](crate::InMemoryStorage::with_eviction_policy).
+/// It runs under the lock of the log's shard before every count, so it should be cheap. Every
+/// timestamp it keeps counts against the capacity: keeping expired ones makes the limit
+/// stricter, and dropping live ones lets more requests through. Closures taking the log, the
+/// window and the current time implement it too.
+///
+/// # Examples
+///
+/// ```
+/// use ratelimiter_rs::{EvictionPolicy, InMemoryStorage, RateLimiter, WindowEviction};
+/// use std::time::Duration;
+///
+/// // keep at most the 100 newest timestamps, however high the capacity.
+/// let storage = InMemoryStorage::new().with_eviction_policy(|request_logs: &mut Vec<u64>, window: Duration, now: u64| {
+///     WindowEviction.evict(request_logs, window, now);
+///     let excess = request_logs.len().saturating_sub(100);
+///     request_logs.drain(..excess);
+/// });
+/// let mut limiter = RateLimiter::with_storage(storage);
+/// limiter.add_config("type1", 10_000, 60000);
+/// assert!(limiter.allowed("user1", "type1").unwrap());
+/// ```
+pub trait EvictionPolicy: Send + Sync {
+    /// Removes the timestamps that should no longer count from a log, oldest first, for a
+    /// sliding window of `window` ending at `now`.
+    fn evict(&self, request_logs: &mut Vec<u64>, window: Duration, now: u64);
+}
+
+/// The eviction used unless another [`EvictionPolicy`] is set: drops every timestamp made a
+/// full window or more ago.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WindowEviction;
+
+impl EvictionPolicy for WindowEviction {
+    fn evict(&self, request_logs: &mut Vec<u64>, window: Duration, now: u64) {
+        sliding_window::evict(request_logs, window, now);
+    }
+}
+
+impl<F> EvictionPolicy for F
+where
+    F: Fn(&mut Vec<u64>, Duration, u64) + Send + Sync,
+{
+    fn evict(&self, request_logs: &mut Vec<u64>, window: Duration, now: u64) {
+        self(request_logs, window, now)
+    }
+}
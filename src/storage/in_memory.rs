@@ -0,0 +1,882 @@
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::hash::BuildHasher;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+#[cfg(feature = "async")]
+use crate::error::RateLimiterError;
+use crate::sliding_window::{self, cap};
+use super::{slide_counter, EvictionPolicy, Storage, WindowEviction};
+#[cfg(feature = "async")]
+use super::StorageFuture;
+
+/// The state kept for a single key, depending on the algorithm configured for it.
+enum Entry {
+    Log(Vec<u64>),
+    Bucket { tokens: f64, last_refill: u64 },
+    Counter { window_id: u64, count: u32, last_request: u64 },
+    SlidingCounter { window_id: u64, previous: u32, current: u32, last_request: u64 },
+    Leaky { level: f64, last_leak: u64 },
+    Tat(f64),
+}
+
+impl Entry {
+    /// Returns the request log, replacing any other state with an empty log.
+    fn log(&mut self) -> &mut Vec<u64> {
+        if !matches!(self, Entry::Log(_)) {
+            *self = Entry::Log(Vec::new());
+        }
+        match self {
+            Entry::Log(request_logs) => request_logs,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Returns the token bucket, replacing any other state with a full bucket.
+    fn bucket(&mut self, capacity: u32, now: u64) -> (&mut f64, &mut u64) {
+        if !matches!(self, Entry::Bucket { .. }) {
+            *self = Entry::Bucket { tokens: capacity as f64, last_refill: now };
+        }
+        match self {
+            Entry::Bucket { tokens, last_refill } => (tokens, last_refill),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Returns the leaky bucket, replacing any other state with an empty bucket.
+    fn leaky(&mut self, now: u64) -> (&mut f64, &mut u64) {
+        if !matches!(self, Entry::Leaky { .. }) {
+            *self = Entry::Leaky { level: 0.0, last_leak: now };
+        }
+        match self {
+            Entry::Leaky { level, last_leak } => (level, last_leak),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Returns the theoretical arrival time, replacing any other state with `now`.
+    fn tat(&mut self, now: u64) -> &mut f64 {
+        if !matches!(self, Entry::Tat(_)) {
+            *self = Entry::Tat(now as f64);
+        }
+        match self {
+            Entry::Tat(tat) => tat,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Returns the fixed window counter, replacing any other state with an empty counter.
+    fn counter(&mut self, window_id: u64, now: u64) -> (&mut u64, &mut u32, &mut u64) {
+        if !matches!(self, Entry::Counter { .. }) {
+            *self = Entry::Counter { window_id, count: 0, last_request: now };
+        }
+        match self {
+            Entry::Counter { window_id, count, last_request } => (window_id, count, last_request),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Returns the sliding window counter, replacing any other state with empty counts.
+    fn sliding_counter(&mut self, now: u64) -> (&mut u64, &mut u32, &mut u32, &mut u64) {
+        if !matches!(self, Entry::SlidingCounter { .. }) {
+            *self = Entry::SlidingCounter { window_id: 0, previous: 0, current: 0, last_request: now };
+        }
+        match self {
+            Entry::SlidingCounter { window_id, previous, current, last_request } => (window_id, previous, current, last_request),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Returns how many entries the state counts as: one per timestamp of a log, and one otherwise.
+    fn len(&self) -> usize {
+        match self {
+            Entry::Log(request_logs) => request_logs.len(),
+            _ => 1,
+        }
+    }
+
+    /// Returns the time of the last request recorded in the entry.
+    fn last_request(&self) -> Option<u64> {
+        match self {
+            Entry::Log(request_logs) => request_logs.iter().copied().max(),
+            Entry::Bucket { last_refill, .. } => Some(*last_refill),
+            Entry::Counter { last_request, .. } | Entry::SlidingCounter { last_request, .. } => Some(*last_request),
+            Entry::Leaky { last_leak, .. } => Some(*last_leak),
+            // the theoretical arrival time is never before the last request.
+            Entry::Tat(tat) => Some(*tat as u64),
+        }
+    }
+}
+
+// New request logs reserve room for up to this many timestamps up front, so huge capacities
+// do not allocate their worst case for users who only make a few requests.
+const MAX_PREALLOCATED_LOG: u32 = 1024;
+
+/// The sliding window check shared by single and batched requests, on an already locked shard.
+fn check_and_record_log<S: BuildHasher>(entries: &mut HashMap<String, Entry, S>, eviction: &dyn EvictionPolicy, key: &str, capacity: u32, cost: u32, window: Duration, now: u64) -> bool {
+    let request_logs = entries
+        .entry(key.to_string())
+        .or_insert_with(|| Entry::Log(Vec::with_capacity(capacity.min(MAX_PREALLOCATED_LOG) as usize)))
+        .log();
+
+    // evict expired entries, by default those made a full window or more ago.
+    eviction.evict(request_logs, window, now);
+    sliding_window::record(request_logs, capacity, cost, now)
+}
+
+type Shard<S> = Mutex<HashMap<String, Entry, S>>;
+
+/// Locks a shard, recovering it if a thread panicked while holding it.
+fn lock<S>(shard: &Shard<S>) -> MutexGuard<'_, HashMap<String, Entry, S>> {
+    shard.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Keeps request logs in a process-local map.
+///
+/// The map is split into shards, each behind its own lock and selected by hashing the key,
+/// so requests from unrelated users rarely contend with each other. A request log never holds
+/// more than `capacity` timestamps, so the worst-case memory is `O(capacity)` per active user.
+///
+/// Keys are hashed with `S`, the standard library's DoS-resistant hasher unless another one is
+/// passed to [`with_hasher`](InMemoryStorage::with_hasher). Expired timestamps are dropped
+/// before every count, as decided by the [`EvictionPolicy`].
+#[derive(Clone)]
+pub struct InMemoryStorage<S = RandomState> {
+    shards: Arc<[Shard<S>]>,
+    hasher: S,
+    eviction: Arc<dyn EvictionPolicy>,
+    // the most keys a shard holds before it evicts the least recently used one
+    max_keys_per_shard: Option<usize>,
+}
+
+impl InMemoryStorage {
+    /// The number of shards used by [`InMemoryStorage::new`].
+    pub const DEFAULT_SHARDS: usize = 16;
+
+    /// Creates an empty in-memory storage with [`DEFAULT_SHARDS`](InMemoryStorage::DEFAULT_SHARDS) shards.
+    pub fn new() -> Self {
+        Self::with_shards(Self::DEFAULT_SHARDS)
+    }
+
+    /// Creates an empty in-memory storage split into `shards` independently locked maps.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `shards` is 0.
+    pub fn with_shards(shards: usize) -> Self {
+        assert!(shards > 0, "an in-memory storage needs at least one shard");
+        Self {
+            shards: (0..shards).map(|_| Shard::default()).collect(),
+            hasher: RandomState::new(),
+            eviction: Arc::new(WindowEviction),
+            max_keys_per_shard: None,
+        }
+    }
+
+    /// Creates an empty in-memory storage with room for about `keys` keys before it has to grow.
+    ///
+    /// # Arguments
+    ///
+    /// * `keys` - The expected number of keys, i.e. users times request types.
+    pub fn with_capacity(keys: usize) -> Self {
+        let keys_per_shard = keys.div_ceil(Self::DEFAULT_SHARDS);
+        Self {
+            shards: (0..Self::DEFAULT_SHARDS).map(|_| Mutex::new(HashMap::with_capacity(keys_per_shard))).collect(),
+            hasher: RandomState::new(),
+            eviction: Arc::new(WindowEviction),
+            max_keys_per_shard: None,
+        }
+    }
+}
+
+impl<S: BuildHasher + Clone> InMemoryStorage<S> {
+    /// Creates an empty in-memory storage with [`DEFAULT_SHARDS`](InMemoryStorage::DEFAULT_SHARDS)
+    /// shards that hashes keys with `hasher`.
+    ///
+    /// A faster hasher such as FxHash speeds up every request, but unlike the default one,
+    /// it lets clients who pick their own user ids force collisions. Only use one for
+    /// trusted ids.
+    ///
+    /// # Arguments
+    ///
+    /// * `hasher` - Builds the hasher for picking shards and for the maps in them.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::{InMemoryStorage, RateLimiter};
+    /// use std::hash::{BuildHasherDefault, DefaultHasher};
+    ///
+    /// let storage = InMemoryStorage::with_hasher(BuildHasherDefault::<DefaultHasher>::default());
+    /// let mut limiter = RateLimiter::with_storage(storage);
+    /// limiter.add_config("type1", 5, 60000);
+    /// assert!(limiter.allowed("user1", "type1").unwrap());
+    /// ```
+    pub fn with_hasher(hasher: S) -> Self {
+        Self {
+            shards: (0..InMemoryStorage::DEFAULT_SHARDS).map(|_| Mutex::new(HashMap::with_hasher(hasher.clone()))).collect(),
+            hasher,
+            eviction: Arc::new(WindowEviction),
+            max_keys_per_shard: None,
+        }
+    }
+
+    /// Evicts timestamps from sliding window logs with `policy` instead of [`WindowEviction`].
+    ///
+    /// # Arguments
+    ///
+    /// * `policy` - The eviction policy, or a closure taking a log, the window and the current time.
+    pub fn with_eviction_policy(mut self, policy: impl EvictionPolicy + 'static) -> Self {
+        self.eviction = Arc::new(policy);
+        self
+    }
+
+    /// Bounds the storage to about `max_keys` keys, evicting the state of the least recently
+    /// used key whenever a new one would exceed the bound.
+    ///
+    /// Keys are bounded per shard, to `max_keys` divided by the number of shards (rounded up),
+    /// so a shard that many keys happen to hash to may evict before the storage as a whole is
+    /// full. Recency is the time of a key's last recorded request. An evicted user simply
+    /// starts over with a fresh quota. Finding the key to evict scans its shard, so admitting
+    /// a new key into a full shard costs time linear in the keys per shard.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_keys` - The most keys to hold, i.e. users times request types.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_keys` is 0.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::{InMemoryStorage, Storage};
+    /// use std::time::Duration;
+    ///
+    /// let storage = InMemoryStorage::with_shards(1).with_max_keys(2);
+    /// let window = Duration::from_secs(60);
+    /// storage.check_and_record("user1:type1", 5, 1, window, 1000).unwrap();
+    /// storage.check_and_record("user2:type1", 5, 1, window, 2000).unwrap();
+    /// storage.check_and_record("user3:type1", 5, 1, window, 3000).unwrap();
+    ///
+    /// // user1 was the least recently used.
+    /// assert_eq!(storage.count("user1:type1", window, 3000).unwrap(), 0);
+    /// assert_eq!(storage.count("user2:type1", window, 3000).unwrap(), 1);
+    /// ```
+    pub fn with_max_keys(mut self, max_keys: usize) -> Self {
+        assert!(max_keys > 0, "an in-memory storage must be able to hold at least one key");
+        self.max_keys_per_shard = Some(max_keys.div_ceil(self.shards.len()));
+        self
+    }
+
+    /// Returns a copy of every sliding window log, keyed by storage key, e.g. to inspect the
+    /// state in tests or to hand it over to another process.
+    ///
+    /// This is a point-in-time copy, not a live view: each shard is copied under its own lock,
+    /// so requests made meanwhile may show up in some shards and not others. Logs may still
+    /// hold expired timestamps that have not been evicted yet. Other algorithms' state is left out.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ratelimiter_rs::{InMemoryStorage, RateLimiter};
+    ///
+    /// // clones share their state, so one can be kept to look into the limiter's storage.
+    /// let storage = InMemoryStorage::new();
+    /// let mut limiter = RateLimiter::with_storage(storage.clone());
+    /// limiter.add_config("type1", 5, 60000);
+    /// limiter.allowed("user1", "type1").unwrap();
+    ///
+    /// let snapshot = storage.snapshot();
+    /// assert_eq!(snapshot["user1:type1"].len(), 1);
+    ///
+    /// let restored = InMemoryStorage::new();
+    /// restored.load_snapshot(snapshot);
+    /// ```
+    pub fn snapshot(&self) -> HashMap<String, Vec<u64>> {
+        let mut snapshot = HashMap::new();
+        for index in 0..self.shards.len() {
+            for (key, entry) in self.lock(index).iter() {
+                if let Entry::Log(request_logs) = entry {
+                    snapshot.insert(key.clone(), request_logs.clone());
+                }
+            }
+        }
+        snapshot
+    }
+
+    /// Restores sliding window logs from a [`snapshot`](InMemoryStorage::snapshot), replacing
+    /// the state of every key in it and leaving other keys alone.
+    ///
+    /// # Arguments
+    ///
+    /// * `snapshot` - The logs to restore, keyed by storage key.
+    pub fn load_snapshot(&self, snapshot: HashMap<String, Vec<u64>>) {
+        for (key, mut request_logs) in snapshot {
+            // logs are kept in time order, which a hand-written snapshot may not be in.
+            request_logs.sort_unstable();
+            let mut entries = self.shard(&key);
+            self.make_room(&mut entries, &[&key]);
+            entries.insert(key, Entry::Log(request_logs));
+        }
+    }
+
+    /// Returns the index of the shard that owns `key`.
+    fn shard_index(&self, key: &str) -> usize {
+        self.hasher.hash_one(key) as usize % self.shards.len()
+    }
+
+    /// Locks the shard at `index`.
+    ///
+    /// A thread that panicked while holding the lock leaves the shard poisoned. Its state is
+    /// still usable, at worst with one request more or less recorded, so the guard is taken
+    /// over instead of failing every later request of the shard.
+    fn lock(&self, index: usize) -> MutexGuard<'_, HashMap<String, Entry, S>> {
+        lock(&self.shards[index])
+    }
+
+    /// Locks the shard that owns `key`.
+    fn shard(&self, key: &str) -> MutexGuard<'_, HashMap<String, Entry, S>> {
+        self.lock(self.shard_index(key))
+    }
+
+    /// Evicts the least recently used keys of a shard other than `keep`, until the keys of
+    /// `keep` it does not hold yet fit within [`with_max_keys`](InMemoryStorage::with_max_keys),
+    /// if the storage is bounded.
+    fn make_room(&self, entries: &mut HashMap<String, Entry, S>, keep: &[&str]) {
+        let Some(max_keys) = self.max_keys_per_shard else {
+            return;
+        };
+        let new_keys = keep.iter().filter(|key| !entries.contains_key(**key)).count();
+        while entries.len() + new_keys > max_keys {
+            let least_recently_used = entries
+                .iter()
+                .filter(|(key, _)| !keep.contains(&key.as_str()))
+                .min_by_key(|(_, entry)| entry.last_request())
+                .map(|(key, _)| key.clone());
+            match least_recently_used {
+                Some(key) => entries.remove(&key),
+                // a batch may touch more keys of a shard than it holds.
+                None => break,
+            };
+        }
+    }
+}
+
+impl Default for InMemoryStorage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Summarizes the storage by its number of keys and entries, leaving out the keys themselves,
+/// which name users.
+impl<S: BuildHasher + Clone + Send + Sync> fmt::Debug for InMemoryStorage<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (keys, entries) = self.usage().unwrap_or_default();
+        f.debug_struct("InMemoryStorage")
+            .field("shards", &self.shards.len())
+            .field("keys", &keys)
+            .field("entries", &entries)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S: BuildHasher + Clone + Send + Sync> Storage for InMemoryStorage<S> {
+    fn check_and_record(&self, key: &str, capacity: u32, cost: u32, window: Duration, now: u64) -> Result<bool, Box<dyn Error>> {
+        let mut entries = self.shard(key);
+        self.make_room(&mut entries, &[key]);
+        Ok(check_and_record_log(&mut entries, &*self.eviction, key, capacity, cost, window, now))
+    }
+
+    fn check_and_record_with_count(&self, key: &str, capacity: u32, cost: u32, window: Duration, now: u64) -> Result<(bool, u32), Box<dyn Error>> {
+        // the count is taken under the same lock, so no other request can slip in between.
+        let mut entries = self.shard(key);
+        self.make_room(&mut entries, &[key]);
+        let is_allowed = check_and_record_log(&mut entries, &*self.eviction, key, capacity, cost, window, now);
+        match entries.get(key) {
+            Some(Entry::Log(request_logs)) => Ok((is_allowed, request_logs.len() as u32)),
+            _ => Ok((is_allowed, 0)),
+        }
+    }
+
+    fn count(&self, key: &str, window: Duration, now: u64) -> Result<u32, Box<dyn Error>> {
+        let mut entries = self.shard(key);
+        let request_logs = match entries.get_mut(key) {
+            Some(Entry::Log(request_logs)) => request_logs,
+            _ => return Ok(0),
+        };
+
+        self.eviction.evict(request_logs, window, now);
+        Ok(request_logs.len() as u32)
+    }
+
+    fn oldest(&self, key: &str, window: Duration, now: u64) -> Result<Option<u64>, Box<dyn Error>> {
+        let mut entries = self.shard(key);
+        let request_logs = match entries.get_mut(key) {
+            Some(Entry::Log(request_logs)) => request_logs,
+            _ => return Ok(None),
+        };
+
+        self.eviction.evict(request_logs, window, now);
+        Ok(request_logs.iter().copied().min())
+    }
+
+    fn remove(&self, key: &str) -> Result<(), Box<dyn Error>> {
+        let mut entries = self.shard(key);
+        entries.remove(key);
+        Ok(())
+    }
+
+    fn release(&self, key: &str, timestamp: u64) -> Result<(), Box<dyn Error>> {
+        let mut entries = self.shard(key);
+        if let Some(Entry::Log(request_logs)) = entries.get_mut(key) {
+            if let Some(position) = request_logs.iter().rposition(|&logged| logged == timestamp) {
+                request_logs.remove(position);
+            }
+        }
+        Ok(())
+    }
+
+    fn timestamps(&self, key: &str, window: Duration, now: u64) -> Result<Vec<u64>, Box<dyn Error>> {
+        let mut entries = self.shard(key);
+        let request_logs = match entries.get_mut(key) {
+            Some(Entry::Log(request_logs)) => request_logs,
+            _ => return Ok(Vec::new()),
+        };
+
+        self.eviction.evict(request_logs, window, now);
+        let mut timestamps = request_logs.clone();
+        timestamps.sort_unstable();
+        Ok(timestamps)
+    }
+
+    fn remove_matching(&self, prefix: &str, suffix: &str) -> Result<u64, Box<dyn Error>> {
+        let mut removed = 0;
+        for shard in self.shards.iter() {
+            let mut entries = lock(shard);
+            let before = entries.len();
+            entries.retain(|key, _| !(key.len() >= prefix.len() + suffix.len() && key.starts_with(prefix) && key.ends_with(suffix)));
+            removed += (before - entries.len()) as u64;
+        }
+        Ok(removed)
+    }
+
+    fn remove_idle(&self, max_idle: Duration, now: u64) -> Result<u64, Box<dyn Error>> {
+        let idle_since = now.saturating_sub(u64::try_from(max_idle.as_millis()).unwrap_or(u64::MAX));
+
+        let mut removed = 0;
+        // shards are swept one at a time, so requests only wait for the shard being swept.
+        for index in 0..self.shards.len() {
+            let mut entries = self.lock(index);
+            let before = entries.len();
+            entries.retain(|_, entry| entry.last_request().is_some_and(|last_request| last_request >= idle_since));
+            removed += (before - entries.len()) as u64;
+        }
+        Ok(removed)
+    }
+
+    fn usage(&self) -> Result<(usize, usize), Box<dyn Error>> {
+        let (mut keys, mut entries) = (0, 0);
+        for index in 0..self.shards.len() {
+            let shard = self.lock(index);
+            keys += shard.len();
+            entries += shard.values().map(Entry::len).sum::<usize>();
+        }
+        Ok((keys, entries))
+    }
+
+    fn take_available(&self, key: &str, capacity: u32, desired: u32, window: Duration, now: u64) -> Result<u32, Box<dyn Error>> {
+        let mut entries = self.shard(key);
+        self.make_room(&mut entries, &[key]);
+        let available = match entries.get_mut(key) {
+            Some(Entry::Log(request_logs)) => {
+                self.eviction.evict(request_logs, window, now);
+                capacity.saturating_sub(request_logs.len() as u32)
+            },
+            _ => capacity,
+        };
+
+        let granted = desired.min(available);
+        if granted > 0 {
+            check_and_record_log(&mut entries, &*self.eviction, key, capacity, granted, window, now);
+        }
+        Ok(granted)
+    }
+
+    fn check_and_record_each(&self, checks: &[(&str, u32, Duration)], now: u64) -> Result<Vec<bool>, Box<dyn Error>> {
+        let mut checks_by_shard = vec![Vec::new(); self.shards.len()];
+        for (position, &(key, _, _)) in checks.iter().enumerate() {
+            checks_by_shard[self.shard_index(key)].push(position);
+        }
+
+        // each shard is locked once for all of its checks, and only one shard is held at a time.
+        let mut results = vec![false; checks.len()];
+        for (index, positions) in checks_by_shard.iter().enumerate().filter(|(_, positions)| !positions.is_empty()) {
+            let mut entries = self.lock(index);
+            let keys: Vec<&str> = positions.iter().map(|&position| checks[position].0).collect();
+            self.make_room(&mut entries, &keys);
+            for &position in positions {
+                let (key, capacity, window) = checks[position];
+                results[position] = check_and_record_log(&mut entries, &*self.eviction, key, capacity, 1, window, now);
+            }
+        }
+        Ok(results)
+    }
+
+    fn kind(&self) -> &'static str {
+        "in_memory"
+    }
+
+    fn check_and_record_all(&self, checks: &[(&str, u32, Duration)], now: u64) -> Result<bool, Box<dyn Error>> {
+        let mut indices: Vec<usize> = checks.iter().map(|&(key, _, _)| self.shard_index(key)).collect();
+        indices.sort_unstable();
+        indices.dedup();
+
+        // shards are always locked in index order, so concurrent batches cannot deadlock.
+        let mut shards = Vec::with_capacity(indices.len());
+        for &index in &indices {
+            shards.push(self.lock(index));
+        }
+
+        let positions: Vec<usize> = checks
+            .iter()
+            .map(|&(key, _, _)| indices.binary_search(&self.shard_index(key)).expect("every shard of the batch is locked"))
+            .collect();
+        for (position, entries) in shards.iter_mut().enumerate() {
+            let keys: Vec<&str> = checks.iter().zip(&positions).filter(|(_, &p)| p == position).map(|(&(key, _, _), _)| key).collect();
+            self.make_room(entries, &keys);
+        }
+
+        for (&(key, capacity, window), &position) in checks.iter().zip(&positions) {
+            let request_logs = shards[position].entry(key.to_string()).or_insert_with(|| Entry::Log(Vec::new())).log();
+            self.eviction.evict(request_logs, window, now);
+
+            if request_logs.len() + 1 > capacity as usize {
+                cap(request_logs, capacity);
+                return Ok(false);
+            }
+        }
+
+        // every log has room, so the request is recorded in all of them.
+        for (&(key, capacity, _), &position) in checks.iter().zip(&positions) {
+            if let Some(entry) = shards[position].get_mut(key) {
+                let request_logs = entry.log();
+                request_logs.push(now);
+                cap(request_logs, capacity);
+            }
+        }
+        Ok(true)
+    }
+
+    fn take_tokens(&self, key: &str, capacity: u32, refill_per_sec: f64, cost: u32, now: u64) -> Result<bool, Box<dyn Error>> {
+        let mut entries = self.shard(key);
+        self.make_room(&mut entries, &[key]);
+        let (tokens, last_refill) = entries
+            .entry(key.to_string())
+            .or_insert(Entry::Bucket { tokens: capacity as f64, last_refill: now })
+            .bucket(capacity, now);
+
+        // refill based on the time elapsed since the last request, never above capacity.
+        let elapsed_millis = now.saturating_sub(*last_refill);
+        *tokens = (*tokens + elapsed_millis as f64 * refill_per_sec / 1000.0).min(capacity as f64);
+        *last_refill = (*last_refill).max(now);
+
+        if *tokens >= cost as f64 {
+            *tokens -= cost as f64;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    fn leak_and_fill(&self, key: &str, bucket_size: u32, leak_per_sec: f64, cost: u32, now: u64) -> Result<bool, Box<dyn Error>> {
+        let mut entries = self.shard(key);
+        self.make_room(&mut entries, &[key]);
+        let (level, last_leak) = entries
+            .entry(key.to_string())
+            .or_insert(Entry::Leaky { level: 0.0, last_leak: now })
+            .leaky(now);
+
+        // drain based on the time elapsed since the last request, never below empty.
+        let elapsed_millis = now.saturating_sub(*last_leak);
+        *level = (*level - elapsed_millis as f64 * leak_per_sec / 1000.0).max(0.0);
+        *last_leak = (*last_leak).max(now);
+
+        if *level + cost as f64 <= bucket_size as f64 {
+            *level += cost as f64;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    fn advance_tat(&self, key: &str, burst: u32, rate_per_sec: f64, cost: u32, now: u64) -> Result<bool, Box<dyn Error>> {
+        let emission_interval = 1000.0 / rate_per_sec;
+
+        let mut entries = self.shard(key);
+        self.make_room(&mut entries, &[key]);
+        let tat = entries.entry(key.to_string()).or_insert(Entry::Tat(now as f64)).tat(now);
+
+        // a theoretical arrival time in the past means the key has fully recovered.
+        let new_tat = tat.max(now as f64) + cost as f64 * emission_interval;
+        if new_tat - burst as f64 * emission_interval <= now as f64 {
+            *tat = new_tat;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    fn increment_sliding_counter(&self, key: &str, capacity: u32, cost: u32, window: Duration, now: u64) -> Result<bool, Box<dyn Error>> {
+        let mut entries = self.shard(key);
+        self.make_room(&mut entries, &[key]);
+        let (window_id, previous, current, last_request) = entries
+            .entry(key.to_string())
+            .or_insert(Entry::SlidingCounter { window_id: 0, previous: 0, current: 0, last_request: now })
+            .sliding_counter(now);
+
+        let (counts, is_allowed) = slide_counter(Some((*window_id, *previous, *current)), capacity, cost, window, now);
+        (*window_id, *previous, *current) = counts;
+        *last_request = (*last_request).max(now);
+        Ok(is_allowed)
+    }
+
+    fn increment_window(&self, key: &str, capacity: u32, cost: u32, window: Duration, now: u64) -> Result<bool, Box<dyn Error>> {
+        let current_window_id = now / (window.as_millis() as u64).max(1);
+
+        let mut entries = self.shard(key);
+        self.make_room(&mut entries, &[key]);
+        let (window_id, count, last_request) = entries
+            .entry(key.to_string())
+            .or_insert(Entry::Counter { window_id: current_window_id, count: 0, last_request: now })
+            .counter(current_window_id, now);
+
+        // a new window started since the last request, so the count starts over.
+        if *window_id != current_window_id {
+            *window_id = current_window_id;
+            *count = 0;
+        }
+        *last_request = (*last_request).max(now);
+
+        if count.saturating_add(cost) <= capacity {
+            *count += cost;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+}
+
+/// Answers right away, for testing code written against async-only backends.
+#[cfg(feature = "async")]
+impl<S: BuildHasher + Clone + Send + Sync> super::AsyncStorage for InMemoryStorage<S> {
+    fn check_and_record<'a>(&'a self, key: &'a str, capacity: u32, cost: u32, window: Duration, now: u64) -> StorageFuture<'a, bool> {
+        let result = Storage::check_and_record(self, key, capacity, cost, window, now).map_err(|e| RateLimiterError::from(e).into());
+        Box::pin(async move { result })
+    }
+
+    fn count<'a>(&'a self, key: &'a str, window: Duration, now: u64) -> StorageFuture<'a, u32> {
+        let result = Storage::count(self, key, window, now).map_err(|e| RateLimiterError::from(e).into());
+        Box::pin(async move { result })
+    }
+
+    fn oldest<'a>(&'a self, key: &'a str, window: Duration, now: u64) -> StorageFuture<'a, Option<u64>> {
+        let result = Storage::oldest(self, key, window, now).map_err(|e| RateLimiterError::from(e).into());
+        Box::pin(async move { result })
+    }
+
+    fn remove<'a>(&'a self, key: &'a str) -> StorageFuture<'a, ()> {
+        let result = Storage::remove(self, key).map_err(|e| RateLimiterError::from(e).into());
+        Box::pin(async move { result })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn log_len(storage: &InMemoryStorage, key: &str) -> usize {
+        match storage.shard(key).get(key) {
+            Some(Entry::Log(request_logs)) => request_logs.len(),
+            _ => 0,
+        }
+    }
+
+    #[test]
+    fn test_log_is_capped_at_capacity() {
+        let storage = InMemoryStorage::new();
+        let window = Duration::from_secs(60);
+
+        for now in 0..100 {
+            storage.check_and_record("user1:type1", 100, 1, window, now).unwrap();
+        }
+        assert_eq!(log_len(&storage, "user1:type1"), 100);
+
+        // lowering the capacity drops the oldest timestamps, which no longer matter.
+        assert!(!storage.check_and_record("user1:type1", 10, 1, window, 100).unwrap());
+        assert_eq!(log_len(&storage, "user1:type1"), 10);
+        assert_eq!(storage.oldest("user1:type1", window, 100).unwrap(), Some(90));
+
+        assert!(!storage.check_and_record_all(&[("user1:type1", 5, window)], 100).unwrap());
+        assert_eq!(log_len(&storage, "user1:type1"), 5);
+    }
+
+    #[test]
+    fn test_backward_clock_jump() {
+        let storage = InMemoryStorage::new();
+        let window = Duration::from_secs(60);
+
+        assert!(storage.check_and_record("user1:type1", 2, 1, window, 10_000).unwrap());
+        assert!(storage.check_and_record("user1:type1", 2, 1, window, 10_001).unwrap());
+
+        // requests recorded before the clock jumped back still count against the window.
+        assert!(!storage.check_and_record("user1:type1", 2, 1, window, 5_000).unwrap());
+        assert_eq!(storage.count("user1:type1", window, 5_000).unwrap(), 2);
+        assert_eq!(storage.oldest("user1:type1", window, 5_000).unwrap(), Some(10_000));
+        assert!(!storage.check_and_record_all(&[("user1:type1", 2, window)], 5_000).unwrap());
+
+        // they expire a window after they were recorded.
+        assert_eq!(storage.count("user1:type1", window, 70_002).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_check_and_record_each() {
+        let storage = InMemoryStorage::with_shards(2);
+        let window = Duration::from_secs(60);
+        let checks = [("user1:type1", 1, window), ("user2:type1", 2, window), ("user1:type1", 1, window), ("user3:type1", 0, window)];
+
+        assert_eq!(storage.check_and_record_each(&checks, 0).unwrap(), vec![true, true, false, false]);
+        assert_eq!(storage.check_and_record_each(&checks, 1).unwrap(), vec![false, true, false, false]);
+        assert_eq!(storage.count("user2:type1", window, 1).unwrap(), 2);
+        assert!(storage.check_and_record_each(&[], 1).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let storage = InMemoryStorage::new();
+        let window = Duration::from_secs(60);
+        storage.check_and_record("user1:type1", 5, 2, window, 1000).unwrap();
+        storage.check_and_record("user2:type1", 5, 1, window, 2000).unwrap();
+        storage.take_tokens("user1:type2", 5, 1.0, 1, 2000).unwrap();
+
+        let snapshot = storage.snapshot();
+        assert_eq!(snapshot.len(), 2);
+        assert_eq!(snapshot["user1:type1"], vec![1000, 1000]);
+
+        // the snapshot does not follow later requests.
+        storage.check_and_record("user1:type1", 5, 1, window, 3000).unwrap();
+        assert_eq!(snapshot["user1:type1"].len(), 2);
+
+        let restored = InMemoryStorage::new();
+        restored.check_and_record("user3:type1", 5, 1, window, 2000).unwrap();
+        restored.load_snapshot(snapshot);
+        assert_eq!(restored.count("user1:type1", window, 3000).unwrap(), 2);
+        assert_eq!(restored.oldest("user2:type1", window, 3000).unwrap(), Some(2000));
+        assert_eq!(restored.count("user3:type1", window, 3000).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_recovers_from_poisoned_lock() {
+        let storage = InMemoryStorage::with_shards(1);
+        let window = Duration::from_secs(60);
+        assert!(storage.check_and_record("user1:type1", 2, 1, window, 1000).unwrap());
+
+        let shared = storage.clone();
+        let panicked = std::thread::spawn(move || {
+            let _entries = shared.shards[0].lock().unwrap();
+            panic!("poisons the shard");
+        })
+        .join();
+        assert!(panicked.is_err());
+        assert!(storage.shards[0].is_poisoned());
+
+        // the state recorded before the panic is kept.
+        assert!(storage.check_and_record("user1:type1", 2, 1, window, 2000).unwrap());
+        assert!(!storage.check_and_record("user1:type1", 2, 1, window, 3000).unwrap());
+        assert_eq!(storage.snapshot().len(), 1);
+    }
+
+    #[test]
+    fn test_with_capacity() {
+        let storage = InMemoryStorage::with_capacity(1000);
+        assert_eq!(storage.shards.len(), InMemoryStorage::DEFAULT_SHARDS);
+        assert!(storage.shards.iter().all(|shard| shard.lock().unwrap().capacity() >= 63));
+
+        let window = Duration::from_secs(60);
+        assert!(storage.check_and_record("user1:type1", 5, 1, window, 0).unwrap());
+        let entries = storage.shard("user1:type1");
+        match entries.get("user1:type1") {
+            Some(Entry::Log(request_logs)) => assert!(request_logs.capacity() >= 5),
+            _ => panic!("expected a request log"),
+        }
+    }
+
+    #[test]
+    fn test_with_hasher() {
+        use std::hash::{BuildHasherDefault, DefaultHasher};
+
+        let storage = InMemoryStorage::with_hasher(BuildHasherDefault::<DefaultHasher>::default());
+        assert_eq!(storage.shards.len(), InMemoryStorage::DEFAULT_SHARDS);
+
+        // a deterministic hasher puts a key in the same shard in every storage.
+        let other = InMemoryStorage::with_hasher(BuildHasherDefault::<DefaultHasher>::default());
+        assert_eq!(storage.shard_index("user1:type1"), other.shard_index("user1:type1"));
+
+        let window = Duration::from_secs(60);
+        assert!(storage.check_and_record("user1:type1", 1, 1, window, 0).unwrap());
+        assert!(!storage.check_and_record("user1:type1", 1, 1, window, 1).unwrap());
+        assert_eq!(storage.count("user1:type1", window, 1).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_debug_summarizes() {
+        let storage = InMemoryStorage::with_shards(4);
+        storage.check_and_record("user1:type1", 5, 2, Duration::from_secs(60), 0).unwrap();
+        assert_eq!(format!("{:?}", storage), "InMemoryStorage { shards: 4, keys: 1, entries: 2, .. }");
+    }
+
+    #[test]
+    fn test_max_keys() {
+        let storage = InMemoryStorage::with_shards(1).with_max_keys(2);
+        let window = Duration::from_secs(60);
+
+        assert!(storage.check_and_record("user1:type1", 2, 1, window, 0).unwrap());
+        assert!(storage.check_and_record("user2:type1", 2, 1, window, 1).unwrap());
+        // user1 is used again, which makes user2 the least recently used.
+        assert!(storage.check_and_record("user1:type1", 2, 1, window, 2).unwrap());
+        assert!(storage.take_tokens("user3:type2", 1, 1.0, 1, 3).unwrap());
+        assert_eq!(storage.usage().unwrap().0, 2);
+        assert!(!storage.check_and_record("user1:type1", 2, 1, window, 4).unwrap());
+        assert_eq!(storage.count("user2:type1", window, 4).unwrap(), 0);
+
+        // a batch keeps its own keys even if it touches more than the bound.
+        assert!(storage.check_and_record_all(&[("user4:type1", 1, window), ("user5:type1", 1, window), ("user6:type1", 1, window)], 5).unwrap());
+        assert_eq!(storage.usage().unwrap(), (3, 3));
+    }
+
+    #[test]
+    fn test_eviction_policy() {
+        // keeps only the two newest timestamps, regardless of the window.
+        let storage = InMemoryStorage::new().with_eviction_policy(|request_logs: &mut Vec<u64>, _window: Duration, _now: u64| {
+            let excess = request_logs.len().saturating_sub(2);
+            request_logs.drain(..excess);
+        });
+        let window = Duration::from_secs(60);
+
+        for now in 0..5 {
+            assert!(storage.check_and_record("user1:type1", 3, 1, window, now).unwrap());
+        }
+        assert_eq!(log_len(&storage, "user1:type1"), 3);
+        assert_eq!(storage.count("user1:type1", window, 5).unwrap(), 2);
+        assert_eq!(storage.timestamps("user1:type1", window, 5).unwrap(), vec![3, 4]);
+
+        // nothing is evicted by age, so timestamps older than the window keep counting.
+        assert!(storage.check_and_record("user1:type2", 1, 1, window, 0).unwrap());
+        assert!(!storage.check_and_record("user1:type2", 1, 1, window, 120_000).unwrap());
+    }
+}
@@ -0,0 +1,1110 @@
+use std::error::Error;
+use std::fmt;
+use std::sync::OnceLock;
+#[cfg(feature = "async")]
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+use crate::error::RateLimiterError;
+use super::Storage;
+#[cfg(feature = "async")]
+use super::StorageFuture;
+
+// Defines `now_in_millis`, which every script uses to read the time argument: either the
+// client's time, or 'server' to use the clock of Redis itself so that all app servers agree.
+macro_rules! now_in_millis {
+    () => {
+        r"
+    local function now_in_millis(client_time)
+        if client_time ~= 'server' then
+            return tonumber(client_time)
+        end
+        -- TIME is not deterministic, so the script must replicate its effects instead of itself.
+        redis.replicate_commands()
+        local server_time = redis.call('TIME')
+        return tonumber(server_time[1]) * 1000 + math.floor(tonumber(server_time[2]) / 1000)
+    end
+"
+    };
+}
+
+// Defines `count_buckets`, which the bucketed scripts use to evict expired buckets of a hash
+// and count the requests in the rest.
+macro_rules! count_buckets {
+    () => {
+        r"
+    -- requests count as made at the end of their bucket, so they never expire early. returns
+    -- the number of requests in the window and the end of the oldest bucket holding any.
+    local function count_buckets(user_redis_key, window_in_millis, current_time_in_millis, bucket_in_millis)
+        local buckets = redis.call('HGETALL', user_redis_key)
+        local request_count = 0
+        local oldest_bucket_end = nil
+        for i = 1, #buckets, 2 do
+            local bucket_end = tonumber(buckets[i]) + bucket_in_millis - 1
+            if bucket_end + window_in_millis > current_time_in_millis then
+                request_count = request_count + tonumber(buckets[i + 1])
+                if oldest_bucket_end == nil or bucket_end < oldest_bucket_end then
+                    oldest_bucket_end = bucket_end
+                end
+            else
+                redis.call('HDEL', user_redis_key, buckets[i])
+            end
+        end
+        return request_count, oldest_bucket_end
+    end
+"
+    };
+}
+
+// Defines `record_requests`, which the sliding window scripts use to add requests to a log.
+macro_rules! record_requests {
+    () => {
+        r"
+    -- members must be unique, so requests recorded in the same millisecond are numbered from
+    -- the count they were recorded at. a released request lowers the count without freeing
+    -- every number above it, so numbers still taken are skipped.
+    local function record_requests(user_redis_key, current_time_in_millis, request_count, cost)
+        local member_number = request_count
+        for _ = 1, cost do
+            member_number = member_number + 1
+            while redis.call('ZADD', user_redis_key, current_time_in_millis, current_time_in_millis .. '-' .. member_number) == 0 do
+                member_number = member_number + 1
+            end
+        end
+    end
+"
+    };
+}
+
+/// A Lua script whose hash is computed on first use and then shared by every call, instead of
+/// being recomputed each time a script is invoked.
+struct CachedScript {
+    source: &'static str,
+    script: OnceLock<redis::Script>,
+}
+
+impl CachedScript {
+    const fn new(source: &'static str) -> Self {
+        Self { source, script: OnceLock::new() }
+    }
+
+    /// Returns the Lua source, e.g. to load the script ahead of a pipeline of `EVALSHA`s.
+    fn source(&self) -> &'static str {
+        self.source
+    }
+}
+
+impl std::ops::Deref for CachedScript {
+    type Target = redis::Script;
+
+    fn deref(&self) -> &redis::Script {
+        self.script.get_or_init(|| redis::Script::new(self.source))
+    }
+}
+
+// Reference: https://engineering.grab.com/frequency-capping
+static SLIDING_WINDOW_SCRIPT: CachedScript = CachedScript::new(concat!(now_in_millis!(), record_requests!(), r"
+    local user_redis_key = KEYS[1]
+    local limit_value = tonumber(ARGV[1])
+    local window_in_millis = tonumber(ARGV[2])
+    local current_time_in_millis = now_in_millis(ARGV[3])
+    local cost = tonumber(ARGV[4])
+    local start_time_in_millis = current_time_in_millis - window_in_millis
+    local end_time_in_millis = current_time_in_millis
+    local eviction_time_in_millis = start_time_in_millis
+
+    -- a request expires a full window after it was made, so evict before counting, including
+    -- requests made exactly at the start of the window, as the in-memory storage does.
+    redis.call('ZREMRANGEBYSCORE', user_redis_key, '-inf', eviction_time_in_millis)
+    local request_count = tonumber(redis.call('ZCOUNT', user_redis_key, start_time_in_millis, end_time_in_millis))
+
+    if request_count + cost <= limit_value then
+        record_requests(user_redis_key, current_time_in_millis, request_count, cost)
+        -- the newest request expires a window from now, and so does the key if no other follows.
+        redis.call('PEXPIRE', user_redis_key, window_in_millis)
+        return {1, request_count + cost}
+    else
+        return {0, request_count}
+    end
+"));
+
+// Checks every key before recording in any of them, so a batch is all-or-nothing.
+static SLIDING_WINDOW_ALL_SCRIPT: CachedScript = CachedScript::new(concat!(now_in_millis!(), record_requests!(), r"
+    local current_time_in_millis = now_in_millis(ARGV[1])
+
+    local request_counts = {}
+    for i, user_redis_key in ipairs(KEYS) do
+        local limit_value = tonumber(ARGV[2 * i])
+        local start_time_in_millis = current_time_in_millis - tonumber(ARGV[2 * i + 1])
+
+        redis.call('ZREMRANGEBYSCORE', user_redis_key, '-inf', start_time_in_millis)
+        request_counts[i] = tonumber(redis.call('ZCOUNT', user_redis_key, start_time_in_millis, current_time_in_millis))
+        if request_counts[i] + 1 > limit_value then
+            return 0
+        end
+    end
+
+    for i, user_redis_key in ipairs(KEYS) do
+        record_requests(user_redis_key, current_time_in_millis, request_counts[i], 1)
+        redis.call('PEXPIRE', user_redis_key, tonumber(ARGV[2 * i + 1]))
+    end
+    return 1
+"));
+
+// Like the sliding window script, but records as many of the requests as fit instead of none.
+static TAKE_AVAILABLE_SCRIPT: CachedScript = CachedScript::new(concat!(now_in_millis!(), record_requests!(), r"
+    local user_redis_key = KEYS[1]
+    local limit_value = tonumber(ARGV[1])
+    local window_in_millis = tonumber(ARGV[2])
+    local current_time_in_millis = now_in_millis(ARGV[3])
+    local desired = tonumber(ARGV[4])
+    local start_time_in_millis = current_time_in_millis - window_in_millis
+
+    redis.call('ZREMRANGEBYSCORE', user_redis_key, '-inf', start_time_in_millis)
+    local request_count = tonumber(redis.call('ZCOUNT', user_redis_key, start_time_in_millis, current_time_in_millis))
+
+    local granted = math.max(0, math.min(desired, limit_value - request_count))
+    if granted > 0 then
+        record_requests(user_redis_key, current_time_in_millis, request_count, granted)
+        redis.call('PEXPIRE', user_redis_key, window_in_millis)
+    end
+    return granted
+"));
+
+static RELEASE_SCRIPT: CachedScript = CachedScript::new(r"
+    local user_redis_key = KEYS[1]
+    local timestamp = ARGV[1]
+
+    local requests = redis.call('ZRANGEBYSCORE', user_redis_key, timestamp, timestamp, 'LIMIT', 0, 1)
+    if #requests > 0 then
+        redis.call('ZREM', user_redis_key, requests[1])
+    end
+    return #requests
+");
+
+static COUNT_SCRIPT: CachedScript = CachedScript::new(concat!(now_in_millis!(), r"
+    local user_redis_key = KEYS[1]
+    local window_in_millis = tonumber(ARGV[1])
+    local end_time_in_millis = now_in_millis(ARGV[2])
+    local start_time_in_millis = end_time_in_millis - window_in_millis
+    local eviction_time_in_millis = start_time_in_millis
+
+    redis.call('ZREMRANGEBYSCORE', user_redis_key, '-inf', eviction_time_in_millis)
+    return redis.call('ZCOUNT', user_redis_key, start_time_in_millis, end_time_in_millis)
+"));
+
+static OLDEST_SCRIPT: CachedScript = CachedScript::new(concat!(now_in_millis!(), r"
+    local user_redis_key = KEYS[1]
+    local window_in_millis = tonumber(ARGV[1])
+    local end_time_in_millis = now_in_millis(ARGV[2])
+    local start_time_in_millis = end_time_in_millis - window_in_millis
+    local eviction_time_in_millis = start_time_in_millis
+
+    redis.call('ZREMRANGEBYSCORE', user_redis_key, '-inf', eviction_time_in_millis)
+    local oldest = redis.call('ZRANGEBYSCORE', user_redis_key, start_time_in_millis, end_time_in_millis, 'WITHSCORES', 'LIMIT', 0, 1)
+    if #oldest == 0 then
+        return nil
+    end
+    return tonumber(oldest[2])
+"));
+
+static TIMESTAMPS_SCRIPT: CachedScript = CachedScript::new(concat!(now_in_millis!(), r"
+    local user_redis_key = KEYS[1]
+    local window_in_millis = tonumber(ARGV[1])
+    local end_time_in_millis = now_in_millis(ARGV[2])
+    local start_time_in_millis = end_time_in_millis - window_in_millis
+    local eviction_time_in_millis = start_time_in_millis
+
+    redis.call('ZREMRANGEBYSCORE', user_redis_key, '-inf', eviction_time_in_millis)
+    local requests = redis.call('ZRANGEBYSCORE', user_redis_key, start_time_in_millis, end_time_in_millis, 'WITHSCORES')
+    local timestamps = {}
+    for i = 2, #requests, 2 do
+        table.insert(timestamps, tonumber(requests[i]))
+    end
+    return timestamps
+"));
+
+// The bucketed counterparts of the scripts above, which keep a hash of request counts per
+// bucket start instead of a sorted set member per request. The bucket length is the last argument.
+static BUCKETED_WINDOW_SCRIPT: CachedScript = CachedScript::new(concat!(now_in_millis!(), count_buckets!(), r"
+    local user_redis_key = KEYS[1]
+    local limit_value = tonumber(ARGV[1])
+    local window_in_millis = tonumber(ARGV[2])
+    local current_time_in_millis = now_in_millis(ARGV[3])
+    local cost = tonumber(ARGV[4])
+    local bucket_in_millis = tonumber(ARGV[5])
+
+    local request_count = count_buckets(user_redis_key, window_in_millis, current_time_in_millis, bucket_in_millis)
+    if request_count + cost <= limit_value then
+        local bucket_start = current_time_in_millis - current_time_in_millis % bucket_in_millis
+        redis.call('HINCRBY', user_redis_key, bucket_start, cost)
+        redis.call('PEXPIRE', user_redis_key, bucket_start + bucket_in_millis - 1 + window_in_millis - current_time_in_millis)
+        return {1, request_count + cost}
+    else
+        return {0, request_count}
+    end
+"));
+
+static BUCKETED_WINDOW_ALL_SCRIPT: CachedScript = CachedScript::new(concat!(now_in_millis!(), count_buckets!(), r"
+    local current_time_in_millis = now_in_millis(ARGV[1])
+    local bucket_in_millis = tonumber(ARGV[#ARGV])
+    local bucket_start = current_time_in_millis - current_time_in_millis % bucket_in_millis
+
+    for i, user_redis_key in ipairs(KEYS) do
+        local limit_value = tonumber(ARGV[2 * i])
+        local request_count = count_buckets(user_redis_key, tonumber(ARGV[2 * i + 1]), current_time_in_millis, bucket_in_millis)
+        if request_count + 1 > limit_value then
+            return 0
+        end
+    end
+
+    for i, user_redis_key in ipairs(KEYS) do
+        redis.call('HINCRBY', user_redis_key, bucket_start, 1)
+        redis.call('PEXPIRE', user_redis_key, bucket_start + bucket_in_millis - 1 + tonumber(ARGV[2 * i + 1]) - current_time_in_millis)
+    end
+    return 1
+"));
+
+static BUCKETED_TAKE_AVAILABLE_SCRIPT: CachedScript = CachedScript::new(concat!(now_in_millis!(), count_buckets!(), r"
+    local user_redis_key = KEYS[1]
+    local limit_value = tonumber(ARGV[1])
+    local window_in_millis = tonumber(ARGV[2])
+    local current_time_in_millis = now_in_millis(ARGV[3])
+    local desired = tonumber(ARGV[4])
+    local bucket_in_millis = tonumber(ARGV[5])
+
+    local request_count = count_buckets(user_redis_key, window_in_millis, current_time_in_millis, bucket_in_millis)
+    local granted = math.max(0, math.min(desired, limit_value - request_count))
+    if granted > 0 then
+        local bucket_start = current_time_in_millis - current_time_in_millis % bucket_in_millis
+        redis.call('HINCRBY', user_redis_key, bucket_start, granted)
+        redis.call('PEXPIRE', user_redis_key, bucket_start + bucket_in_millis - 1 + window_in_millis - current_time_in_millis)
+    end
+    return granted
+"));
+
+static BUCKETED_RELEASE_SCRIPT: CachedScript = CachedScript::new(r"
+    local user_redis_key = KEYS[1]
+    local timestamp = tonumber(ARGV[1])
+    local bucket_in_millis = tonumber(ARGV[2])
+
+    local bucket_start = timestamp - timestamp % bucket_in_millis
+    local request_count = tonumber(redis.call('HGET', user_redis_key, bucket_start))
+    if request_count == nil then
+        return 0
+    end
+    if request_count > 1 then
+        redis.call('HINCRBY', user_redis_key, bucket_start, -1)
+    else
+        redis.call('HDEL', user_redis_key, bucket_start)
+    end
+    return 1
+");
+
+static BUCKETED_COUNT_SCRIPT: CachedScript = CachedScript::new(concat!(now_in_millis!(), count_buckets!(), r"
+    local request_count = count_buckets(KEYS[1], tonumber(ARGV[1]), now_in_millis(ARGV[2]), tonumber(ARGV[3]))
+    return request_count
+"));
+
+static BUCKETED_OLDEST_SCRIPT: CachedScript = CachedScript::new(concat!(now_in_millis!(), count_buckets!(), r"
+    local _, oldest_bucket_end = count_buckets(KEYS[1], tonumber(ARGV[1]), now_in_millis(ARGV[2]), tonumber(ARGV[3]))
+    return oldest_bucket_end
+"));
+
+// Every request of a bucket is listed at the end of the bucket, as it is counted.
+static BUCKETED_TIMESTAMPS_SCRIPT: CachedScript = CachedScript::new(concat!(now_in_millis!(), count_buckets!(), r"
+    local user_redis_key = KEYS[1]
+    local bucket_in_millis = tonumber(ARGV[3])
+
+    count_buckets(user_redis_key, tonumber(ARGV[1]), now_in_millis(ARGV[2]), bucket_in_millis)
+    local buckets = redis.call('HGETALL', user_redis_key)
+    local timestamps = {}
+    for i = 1, #buckets, 2 do
+        local bucket_end = tonumber(buckets[i]) + bucket_in_millis - 1
+        for _ = 1, tonumber(buckets[i + 1]) do
+            table.insert(timestamps, bucket_end)
+        end
+    end
+    table.sort(timestamps)
+    return timestamps
+"));
+
+static TOKEN_BUCKET_SCRIPT: CachedScript = CachedScript::new(concat!(now_in_millis!(), r"
+    local user_redis_key = KEYS[1]
+    local capacity = tonumber(ARGV[1])
+    local refill_per_sec = tonumber(ARGV[2])
+    local current_time_in_millis = now_in_millis(ARGV[3])
+    local cost = tonumber(ARGV[4])
+
+    local bucket = redis.call('HMGET', user_redis_key, 'tokens', 'last_refill')
+    local tokens = tonumber(bucket[1]) or capacity
+    local last_refill = tonumber(bucket[2]) or current_time_in_millis
+
+    local elapsed_millis = math.max(0, current_time_in_millis - last_refill)
+    tokens = math.min(capacity, tokens + elapsed_millis * refill_per_sec / 1000)
+
+    local allowed = 0
+    if tokens >= cost then
+        tokens = tokens - cost
+        allowed = 1
+    end
+
+    redis.call('HSET', user_redis_key, 'tokens', tokens, 'last_refill', math.max(last_refill, current_time_in_millis))
+    -- once the bucket would be full again the key carries no information, so let it expire.
+    if refill_per_sec > 0 then
+        redis.call('PEXPIRE', user_redis_key, math.ceil((capacity - tokens) * 1000 / refill_per_sec) + 1)
+    end
+    return allowed
+"));
+
+static LEAKY_BUCKET_SCRIPT: CachedScript = CachedScript::new(concat!(now_in_millis!(), r"
+    local user_redis_key = KEYS[1]
+    local bucket_size = tonumber(ARGV[1])
+    local leak_per_sec = tonumber(ARGV[2])
+    local current_time_in_millis = now_in_millis(ARGV[3])
+    local cost = tonumber(ARGV[4])
+
+    local bucket = redis.call('HMGET', user_redis_key, 'level', 'last_leak')
+    local level = tonumber(bucket[1]) or 0
+    local last_leak = tonumber(bucket[2]) or current_time_in_millis
+
+    local elapsed_millis = math.max(0, current_time_in_millis - last_leak)
+    level = math.max(0, level - elapsed_millis * leak_per_sec / 1000)
+
+    local allowed = 0
+    if level + cost <= bucket_size then
+        level = level + cost
+        allowed = 1
+    end
+
+    redis.call('HSET', user_redis_key, 'level', level, 'last_leak', math.max(last_leak, current_time_in_millis))
+    -- once the bucket has drained the key carries no information, so let it expire.
+    if leak_per_sec > 0 then
+        redis.call('PEXPIRE', user_redis_key, math.ceil(level * 1000 / leak_per_sec) + 1)
+    end
+    return allowed
+"));
+
+static GCRA_SCRIPT: CachedScript = CachedScript::new(concat!(now_in_millis!(), r"
+    local user_redis_key = KEYS[1]
+    local burst = tonumber(ARGV[1])
+    local emission_interval = 1000 / tonumber(ARGV[2])
+    local current_time_in_millis = now_in_millis(ARGV[3])
+    local cost = tonumber(ARGV[4])
+
+    -- a theoretical arrival time in the past means the key has fully recovered.
+    local tat = math.max(tonumber(redis.call('HGET', user_redis_key, 'tat')) or 0, current_time_in_millis)
+    local new_tat = tat + cost * emission_interval
+
+    if new_tat - burst * emission_interval <= current_time_in_millis then
+        redis.call('HSET', user_redis_key, 'tat', new_tat)
+        -- once the theoretical arrival time has passed the key carries no information.
+        redis.call('PEXPIRE', user_redis_key, math.ceil(new_tat - current_time_in_millis) + 1)
+        return 1
+    else
+        return 0
+    end
+"));
+
+static FIXED_WINDOW_SCRIPT: CachedScript = CachedScript::new(concat!(now_in_millis!(), r"
+    local user_redis_key = KEYS[1]
+    local limit_value = tonumber(ARGV[1])
+    local window_in_millis = math.max(1, tonumber(ARGV[2]))
+    local current_time_in_millis = now_in_millis(ARGV[3])
+    local cost = tonumber(ARGV[4])
+    -- windows are aligned to the unix epoch, and the counter is useless once its window is over.
+    local window_id = math.floor(current_time_in_millis / window_in_millis)
+    local ttl_in_millis = (window_id + 1) * window_in_millis - current_time_in_millis
+
+    local counter = redis.call('HMGET', user_redis_key, 'window_id', 'count')
+    local request_count = 0
+    if tonumber(counter[1]) == window_id then
+        request_count = tonumber(counter[2])
+    end
+
+    if request_count + cost <= limit_value then
+        redis.call('HSET', user_redis_key, 'window_id', window_id, 'count', request_count + cost)
+        redis.call('PEXPIRE', user_redis_key, ttl_in_millis)
+        return 1
+    else
+        return 0
+    end
+"));
+
+static SLIDING_COUNTER_SCRIPT: CachedScript = CachedScript::new(concat!(now_in_millis!(), r"
+    local user_redis_key = KEYS[1]
+    local limit_value = tonumber(ARGV[1])
+    local window_in_millis = math.max(1, tonumber(ARGV[2]))
+    local current_time_in_millis = now_in_millis(ARGV[3])
+    local cost = tonumber(ARGV[4])
+    -- windows are aligned to the unix epoch, and the current count still weighs in during the
+    -- next window.
+    local window_id = math.floor(current_time_in_millis / window_in_millis)
+    local elapsed_in_millis = current_time_in_millis - window_id * window_in_millis
+    local ttl_in_millis = (window_id + 2) * window_in_millis - current_time_in_millis
+
+    local counter = redis.call('HMGET', user_redis_key, 'window_id', 'previous', 'current')
+    local stored_window_id = tonumber(counter[1])
+    local previous_count = 0
+    local current_count = 0
+    if stored_window_id == window_id then
+        previous_count = tonumber(counter[2])
+        current_count = tonumber(counter[3])
+    elseif stored_window_id == window_id - 1 then
+        previous_count = tonumber(counter[3])
+    end
+
+    local estimate = previous_count * (window_in_millis - elapsed_in_millis) / window_in_millis + current_count
+    if estimate + cost <= limit_value then
+        redis.call('HSET', user_redis_key, 'window_id', window_id, 'previous', previous_count, 'current', current_count + cost)
+        redis.call('PEXPIRE', user_redis_key, ttl_in_millis)
+        return 1
+    else
+        return 0
+    end
+"));
+
+/// The connections a [`RedisStorage`] draws from.
+#[derive(Clone)]
+enum Pool {
+    Single(r2d2::Pool<redis::Client>),
+    #[cfg(feature = "cluster")]
+    Cluster(r2d2::Pool<redis::cluster::ClusterClient>),
+}
+
+/// Sets read and write timeouts on every connection a single-node pool opens.
+#[derive(Debug)]
+struct Timeouts(Duration);
+
+impl r2d2::CustomizeConnection<redis::Connection, redis::RedisError> for Timeouts {
+    fn on_acquire(&self, con: &mut redis::Connection) -> Result<(), redis::RedisError> {
+        con.set_read_timeout(Some(self.0))?;
+        con.set_write_timeout(Some(self.0))
+    }
+}
+
+/// Builds the pool of a single-node storage, bounding every call by `timeout` if one is set.
+fn single_pool(client: redis::Client, max_size: u32, timeout: Option<Duration>) -> r2d2::Pool<redis::Client> {
+    let mut builder = r2d2::Pool::builder()
+        .max_size(max_size)
+        .connection_timeout(RedisStorage::DEFAULT_CONNECTION_TIMEOUT)
+        // checking out a connection should not cost an extra PING round trip; broken
+        // connections are still detected and replaced by the pool.
+        .test_on_check_out(false);
+    if let Some(timeout) = timeout {
+        builder = builder.connection_timeout(timeout).connection_customizer(Box::new(Timeouts(timeout)));
+    }
+    builder.build_unchecked(client)
+}
+
+/// A connection checked out of a [`Pool`].
+enum PooledConnection {
+    Single(r2d2::PooledConnection<redis::Client>),
+    #[cfg(feature = "cluster")]
+    Cluster(Box<r2d2::PooledConnection<redis::cluster::ClusterClient>>),
+}
+
+impl Pool {
+    fn get(&self) -> Result<PooledConnection, r2d2::Error> {
+        match self {
+            Pool::Single(pool) => pool.get().map(PooledConnection::Single),
+            #[cfg(feature = "cluster")]
+            Pool::Cluster(pool) => pool.get().map(|con| PooledConnection::Cluster(Box::new(con))),
+        }
+    }
+}
+
+impl std::ops::Deref for PooledConnection {
+    type Target = dyn redis::ConnectionLike;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            PooledConnection::Single(con) => &**con,
+            #[cfg(feature = "cluster")]
+            PooledConnection::Cluster(con) => &***con,
+        }
+    }
+}
+
+impl std::ops::DerefMut for PooledConnection {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        match self {
+            PooledConnection::Single(con) => &mut **con,
+            #[cfg(feature = "cluster")]
+            PooledConnection::Cluster(con) => &mut ***con,
+        }
+    }
+}
+
+/// Keeps request logs in Redis sorted sets (and buckets and counters in hashes), one per key.
+///
+/// Every key is given a TTL of however long its state matters, e.g. one window after the last
+/// recorded request for a log, so keys of users who stop calling expire on their own.
+///
+/// With [`with_buckets`](RedisStorage::with_buckets), sliding window logs are kept as hashes
+/// of request counts per bucket instead.
+///
+/// Every script except the one behind [`Storage::check_and_record_all`] touches exactly one key,
+/// so each call stays within a single hash slot and works unchanged against a Redis Cluster
+/// (see [`RedisStorage::cluster`]). On a cluster, `check_and_record_all` returns an error for
+/// keys in different hash slots, e.g. those of tiered configs and shared budgets.
+#[derive(Clone)]
+pub struct RedisStorage {
+    pool: Pool,
+    /// Whether the scripts read the time from Redis instead of using the caller's `now`.
+    server_time: bool,
+    /// The length of the buckets sliding windows count requests in, if they are bucketed.
+    bucket_millis: Option<u64>,
+    /// How long a call may wait on Redis, if bounded.
+    timeout: Option<Duration>,
+    /// The client behind the pool and the multiplexed connection; `None` for cluster storages.
+    client: Option<redis::Client>,
+    #[cfg(feature = "async")]
+    connection: Arc<Mutex<Option<redis::aio::MultiplexedConnection>>>,
+}
+
+impl RedisStorage {
+    /// The number of pooled connections used by [`RedisStorage::new`].
+    pub const DEFAULT_POOL_SIZE: u32 = 10;
+
+    /// How long a call waits for a pooled connection, including opening one, unless
+    /// [`with_timeout`](RedisStorage::with_timeout) bounds it.
+    pub const DEFAULT_CONNECTION_TIMEOUT: Duration = Duration::from_secs(2);
+
+    /// Creates a Redis storage on top of an existing client, with a pool of
+    /// [`DEFAULT_POOL_SIZE`](RedisStorage::DEFAULT_POOL_SIZE) connections.
+    pub fn new(client: redis::Client) -> Self {
+        Self::with_pool_size(client, Self::DEFAULT_POOL_SIZE)
+    }
+
+    /// Creates a Redis storage on top of an existing client, reusing up to `max_size`
+    /// connections across calls.
+    ///
+    /// Connections are opened lazily, so this never talks to Redis.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_size` is 0.
+    pub fn with_pool_size(client: redis::Client, max_size: u32) -> Self {
+        Self {
+            pool: Pool::Single(single_pool(client.clone(), max_size, None)),
+            server_time: false,
+            bucket_millis: None,
+            timeout: None,
+            client: Some(client),
+            #[cfg(feature = "async")]
+            connection: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Creates a Redis storage on top of a Redis Cluster client, with a pool of
+    /// [`DEFAULT_POOL_SIZE`](RedisStorage::DEFAULT_POOL_SIZE) cluster connections.
+    ///
+    /// Connections are opened lazily, so this never talks to Redis. With the `async` feature,
+    /// the `*_async` methods run the blocking cluster calls on tokio's blocking thread pool.
+    /// [`Storage::remove_matching`] is not supported, since `SCAN` only walks a single node.
+    #[cfg(feature = "cluster")]
+    pub fn cluster(client: redis::cluster::ClusterClient) -> Self {
+        let pool = r2d2::Pool::builder()
+            .max_size(Self::DEFAULT_POOL_SIZE)
+            .connection_timeout(Self::DEFAULT_CONNECTION_TIMEOUT)
+            .test_on_check_out(false)
+            .build_unchecked(client);
+
+        Self {
+            pool: Pool::Cluster(pool),
+            server_time: false,
+            bucket_millis: None,
+            timeout: None,
+            client: None,
+            #[cfg(feature = "async")]
+            connection: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Makes the scripts timestamp requests with the clock of the Redis server instead of the
+    /// `now` they are given, so app servers with skewed clocks still agree on every window.
+    ///
+    /// On a Redis Cluster each node uses its own clock, which is fine as long as a key never
+    /// moves between nodes with skewed clocks.
+    pub fn with_server_time(mut self, enabled: bool) -> Self {
+        self.server_time = enabled;
+        self
+    }
+
+    /// Keeps each sliding window log as a hash of request counts per `bucket_millis` long
+    /// bucket instead of a sorted set member per request.
+    ///
+    /// A log then holds at most one field per bucket in the window, however high the capacity,
+    /// at the cost of precision: requests count as made at the end of their bucket, so they
+    /// free up capacity up to one bucket late, but the limit is never exceeded. Existing logs
+    /// are sorted sets, which the bucketed scripts cannot read, so switch with fresh keys, e.g.
+    /// under a new key prefix.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bucket_millis` is 0.
+    pub fn with_buckets(mut self, bucket_millis: u64) -> Self {
+        assert!(bucket_millis > 0, "buckets must be at least one millisecond long");
+        self.bucket_millis = Some(bucket_millis);
+        self
+    }
+
+    /// Bounds every call by `timeout`: connecting, waiting for a pooled connection, and each
+    /// read and write on it.
+    ///
+    /// A call that runs out of time fails with
+    /// [`RateLimiterError::Timeout`](crate::RateLimiterError::Timeout), or with
+    /// [`RateLimiterError::Storage`](crate::RateLimiterError::Storage) naming the last error if
+    /// it ran out of time because connecting kept failing, so a slow Redis cannot
+    /// hang request handlers; combine it with a [`FailureMode`](crate::FailureMode) to keep
+    /// deciding requests. It has no effect on cluster storages, whose timeouts are set with
+    /// `connection_timeout` and `response_timeout` on the `ClusterClientBuilder` instead.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        if let (Pool::Single(pool), Some(client)) = (&self.pool, &self.client) {
+            // the pool opens connections lazily, so replacing it never drops a live one.
+            self.pool = Pool::Single(single_pool(client.clone(), pool.max_size(), Some(timeout)));
+        }
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Returns the shared multiplexed connection, opening it on first use.
+    ///
+    /// Returns `None` for cluster storages, which have no multiplexed connection.
+    #[cfg(feature = "async")]
+    async fn multiplexed_connection(&self) -> Result<Option<redis::aio::MultiplexedConnection>, Box<dyn Error + Send + Sync>> {
+        let client = match &self.client {
+            Some(client) => client,
+            None => return Ok(None),
+        };
+
+        let cached = self.connection.lock().unwrap_or_else(PoisonError::into_inner).clone();
+        if let Some(con) = cached {
+            return Ok(Some(con));
+        }
+
+        let con = match self.timeout {
+            Some(timeout) => client.get_multiplexed_async_connection_with_timeouts(timeout, timeout).await?,
+            None => client.get_multiplexed_async_connection().await?,
+        };
+        *self.connection.lock().unwrap_or_else(PoisonError::into_inner) = Some(con.clone());
+        Ok(Some(con))
+    }
+
+    /// Runs a blocking storage call on tokio's blocking thread pool.
+    #[cfg(feature = "async")]
+    async fn blocking<F>(&self, f: F) -> Result<bool, Box<dyn Error + Send + Sync>>
+    where
+        F: FnOnce(&RedisStorage) -> Result<bool, Box<dyn Error>> + Send + 'static,
+    {
+        let storage = self.clone();
+        tokio::task::spawn_blocking(move || f(&storage).map_err(|e| e.to_string()))
+            .await?
+            .map_err(|e| RateLimiterError::Message(e).into())
+    }
+}
+
+impl RedisStorage {
+    /// Returns the time argument of the scripts: `now`, or `server` to use the Redis clock.
+    fn time_arg(&self, now: u64) -> String {
+        if self.server_time {
+            "server".to_string()
+        } else {
+            now.to_string()
+        }
+    }
+
+    /// Returns the script that checks and records requests in a sliding window log, depending
+    /// on whether logs are bucketed.
+    fn sliding_window_script(&self) -> &'static CachedScript {
+        match self.bucket_millis {
+            Some(_) => &BUCKETED_WINDOW_SCRIPT,
+            None => &SLIDING_WINDOW_SCRIPT,
+        }
+    }
+
+    /// Returns the script that records as many requests as fit in a sliding window log.
+    fn take_available_script(&self) -> &'static CachedScript {
+        match self.bucket_millis {
+            Some(_) => &BUCKETED_TAKE_AVAILABLE_SCRIPT,
+            None => &TAKE_AVAILABLE_SCRIPT,
+        }
+    }
+
+    /// Fills in the arguments of [`sliding_window_script`](RedisStorage::sliding_window_script),
+    /// which [`take_available_script`](RedisStorage::take_available_script) shares.
+    fn sliding_window_invocation<'a>(&self, script: &'a redis::Script, key: &str, capacity: u32, cost: u32, window: Duration, now: u64) -> redis::ScriptInvocation<'a> {
+        let mut invocation = script.prepare_invoke();
+        invocation.arg(capacity)
+                  .arg(window.as_millis() as u64)
+                  .arg(self.time_arg(now))
+                  .arg(cost)
+                  .key(key);
+        if let Some(bucket_millis) = self.bucket_millis {
+            invocation.arg(bucket_millis);
+        }
+        invocation
+    }
+
+    /// Fills in the arguments of [`TOKEN_BUCKET_SCRIPT`].
+    fn token_bucket_invocation<'a>(&self, script: &'a redis::Script, key: &str, capacity: u32, refill_per_sec: f64, cost: u32, now: u64) -> redis::ScriptInvocation<'a> {
+        let mut invocation = script.prepare_invoke();
+        invocation.arg(capacity)
+                  .arg(refill_per_sec)
+                  .arg(self.time_arg(now))
+                  .arg(cost)
+                  .key(key);
+        invocation
+    }
+
+    /// Fills in the arguments of [`LEAKY_BUCKET_SCRIPT`].
+    fn leaky_bucket_invocation<'a>(&self, script: &'a redis::Script, key: &str, bucket_size: u32, leak_per_sec: f64, cost: u32, now: u64) -> redis::ScriptInvocation<'a> {
+        let mut invocation = script.prepare_invoke();
+        invocation.arg(bucket_size)
+                  .arg(leak_per_sec)
+                  .arg(self.time_arg(now))
+                  .arg(cost)
+                  .key(key);
+        invocation
+    }
+
+    /// Fills in the arguments of [`GCRA_SCRIPT`].
+    fn gcra_invocation<'a>(&self, script: &'a redis::Script, key: &str, burst: u32, rate_per_sec: f64, cost: u32, now: u64) -> redis::ScriptInvocation<'a> {
+        let mut invocation = script.prepare_invoke();
+        invocation.arg(burst)
+                  .arg(rate_per_sec)
+                  .arg(self.time_arg(now))
+                  .arg(cost)
+                  .key(key);
+        invocation
+    }
+
+    /// Fills in the arguments of [`FIXED_WINDOW_SCRIPT`], which [`SLIDING_COUNTER_SCRIPT`] shares.
+    fn fixed_window_invocation<'a>(&self, script: &'a redis::Script, key: &str, capacity: u32, cost: u32, window: Duration, now: u64) -> redis::ScriptInvocation<'a> {
+        let mut invocation = script.prepare_invoke();
+        invocation.arg(capacity)
+                  .arg(window.as_millis() as u64)
+                  .arg(self.time_arg(now))
+                  .arg(cost)
+                  .key(key);
+        invocation
+    }
+
+    /// Fills in the arguments shared by [`COUNT_SCRIPT`], [`OLDEST_SCRIPT`] and
+    /// [`TIMESTAMPS_SCRIPT`], and their bucketed counterparts.
+    fn window_invocation<'a>(&self, script: &'a redis::Script, key: &str, window: Duration, now: u64) -> redis::ScriptInvocation<'a> {
+        let mut invocation = script.prepare_invoke();
+        invocation.arg(window.as_millis() as u64)
+                  .arg(self.time_arg(now))
+                  .key(key);
+        if let Some(bucket_millis) = self.bucket_millis {
+            invocation.arg(bucket_millis);
+        }
+        invocation
+    }
+}
+
+/// Shows how the storage talks to Redis, but not the connection info, which may hold a password.
+impl fmt::Debug for RedisStorage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (topology, pool_size) = match &self.pool {
+            Pool::Single(pool) => ("single", pool.max_size()),
+            #[cfg(feature = "cluster")]
+            Pool::Cluster(pool) => ("cluster", pool.max_size()),
+        };
+        f.debug_struct("RedisStorage")
+            .field("topology", &topology)
+            .field("pool_size", &pool_size)
+            .field("server_time", &self.server_time)
+            .field("bucket_millis", &self.bucket_millis)
+            .field("timeout", &self.timeout)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Storage for RedisStorage {
+    fn check_and_record(&self, key: &str, capacity: u32, cost: u32, window: Duration, now: u64) -> Result<bool, Box<dyn Error>> {
+        let (is_allowed, _) = self.check_and_record_with_count(key, capacity, cost, window, now)?;
+        Ok(is_allowed)
+    }
+
+    fn check_and_record_with_count(&self, key: &str, capacity: u32, cost: u32, window: Duration, now: u64) -> Result<(bool, u32), Box<dyn Error>> {
+        let script = self.sliding_window_script();
+        let (result, request_count): (i32, u32) = self.sliding_window_invocation(script, key, capacity, cost, window, now).invoke(&mut *self.pool.get()?)?;
+        Ok((result == 1, request_count))
+    }
+
+    fn count(&self, key: &str, window: Duration, now: u64) -> Result<u32, Box<dyn Error>> {
+        let script = if self.bucket_millis.is_some() { &BUCKETED_COUNT_SCRIPT } else { &COUNT_SCRIPT };
+        let request_count: u32 = self.window_invocation(script, key, window, now).invoke(&mut *self.pool.get()?)?;
+        Ok(request_count)
+    }
+
+    fn oldest(&self, key: &str, window: Duration, now: u64) -> Result<Option<u64>, Box<dyn Error>> {
+        let script = if self.bucket_millis.is_some() { &BUCKETED_OLDEST_SCRIPT } else { &OLDEST_SCRIPT };
+        let oldest: Option<u64> = self.window_invocation(script, key, window, now).invoke(&mut *self.pool.get()?)?;
+        Ok(oldest)
+    }
+
+    fn remove(&self, key: &str) -> Result<(), Box<dyn Error>> {
+        redis::cmd("DEL").arg(key).query::<()>(&mut *self.pool.get()?)?;
+        Ok(())
+    }
+
+    fn release(&self, key: &str, timestamp: u64) -> Result<(), Box<dyn Error>> {
+        // requests are timestamped by Redis, so the caller's time does not find them.
+        if self.server_time {
+            return Err(Box::new(RateLimiterError::Message("releasing requests is not supported with server time".to_string())));
+        }
+        let script = if self.bucket_millis.is_some() { &BUCKETED_RELEASE_SCRIPT } else { &RELEASE_SCRIPT };
+        let mut invocation = script.prepare_invoke();
+        invocation.arg(timestamp).key(key);
+        if let Some(bucket_millis) = self.bucket_millis {
+            invocation.arg(bucket_millis);
+        }
+        invocation.invoke::<()>(&mut *self.pool.get()?)?;
+        Ok(())
+    }
+
+    fn timestamps(&self, key: &str, window: Duration, now: u64) -> Result<Vec<u64>, Box<dyn Error>> {
+        let script = if self.bucket_millis.is_some() { &BUCKETED_TIMESTAMPS_SCRIPT } else { &TIMESTAMPS_SCRIPT };
+        let timestamps: Vec<u64> = self.window_invocation(script, key, window, now).invoke(&mut *self.pool.get()?)?;
+        Ok(timestamps)
+    }
+
+    fn remove_matching(&self, prefix: &str, suffix: &str) -> Result<u64, Box<dyn Error>> {
+        #[cfg(feature = "cluster")]
+        if let Pool::Cluster(_) = self.pool {
+            return Err(Box::new(RateLimiterError::Message("removing keys by pattern is not supported on Redis Cluster".to_string())));
+        }
+
+        let pattern = format!("{}*{}", escape_glob(prefix), escape_glob(suffix));
+        let mut con = self.pool.get()?;
+
+        // SCAN walks the keyspace in batches, so Redis is never blocked the way KEYS would block it.
+        let mut cursor: u64 = 0;
+        let mut removed = 0;
+        loop {
+            let (next_cursor, keys): (u64, Vec<String>) = redis::cmd("SCAN")
+                .arg(cursor)
+                .arg("MATCH")
+                .arg(&pattern)
+                .arg("COUNT")
+                .arg(1000)
+                .query(&mut *con)?;
+            if !keys.is_empty() {
+                removed += redis::cmd("DEL").arg(&keys).query::<u64>(&mut *con)?;
+            }
+            if next_cursor == 0 {
+                return Ok(removed);
+            }
+            cursor = next_cursor;
+        }
+    }
+
+    fn check_and_record_each(&self, checks: &[(&str, u32, Duration)], now: u64) -> Result<Vec<bool>, Box<dyn Error>> {
+        // a pipeline spans hash slots, so on a cluster every check is sent on its own.
+        #[cfg(feature = "cluster")]
+        if let Pool::Cluster(_) = self.pool {
+            return checks.iter().map(|&(key, capacity, window)| self.check_and_record(key, capacity, 1, window, now)).collect();
+        }
+        if checks.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // the script is loaded in the same pipeline, so EVALSHA never misses it.
+        let script = self.sliding_window_script();
+        let mut pipeline = redis::pipe();
+        pipeline.cmd("SCRIPT").arg("LOAD").arg(script.source()).ignore();
+        for &(key, capacity, window) in checks {
+            pipeline.cmd("EVALSHA")
+                    .arg(script.get_hash())
+                    .arg(1)
+                    .arg(key)
+                    .arg(capacity)
+                    .arg(window.as_millis() as u64)
+                    .arg(self.time_arg(now))
+                    .arg(1);
+            if let Some(bucket_millis) = self.bucket_millis {
+                pipeline.arg(bucket_millis);
+            }
+        }
+
+        let results: Vec<(i32, u32)> = pipeline.query(&mut *self.pool.get()?)?;
+        Ok(results.into_iter().map(|(result, _)| result == 1).collect())
+    }
+
+    fn kind(&self) -> &'static str {
+        "redis"
+    }
+
+    fn check_and_record_all(&self, checks: &[(&str, u32, Duration)], now: u64) -> Result<bool, Box<dyn Error>> {
+        // a script may only touch one hash slot, so a cluster would answer with CROSSSLOT.
+        #[cfg(feature = "cluster")]
+        if let Pool::Cluster(_) = self.pool {
+            let mut slots = checks.iter().map(|&(key, _, _)| redis::cluster_routing::get_slot(key.as_bytes()));
+            if let Some(slot) = slots.next() {
+                if slots.any(|other| other != slot) {
+                    return Err(Box::new(RateLimiterError::Message("checking keys in several hash slots at once is not supported on Redis Cluster".to_string())));
+                }
+            }
+        }
+        let script = if self.bucket_millis.is_some() { &BUCKETED_WINDOW_ALL_SCRIPT } else { &SLIDING_WINDOW_ALL_SCRIPT };
+        let mut invocation = script.prepare_invoke();
+        invocation.arg(self.time_arg(now));
+        for &(key, capacity, window) in checks {
+            invocation.arg(capacity).arg(window.as_millis() as u64).key(key);
+        }
+        if let Some(bucket_millis) = self.bucket_millis {
+            invocation.arg(bucket_millis);
+        }
+
+        let result: i32 = invocation.invoke(&mut *self.pool.get()?)?;
+        Ok(result == 1)
+    }
+
+    fn take_available(&self, key: &str, capacity: u32, desired: u32, window: Duration, now: u64) -> Result<u32, Box<dyn Error>> {
+        let script = self.take_available_script();
+        let granted: u32 = self.sliding_window_invocation(script, key, capacity, desired, window, now).invoke(&mut *self.pool.get()?)?;
+        Ok(granted)
+    }
+
+    fn take_tokens(&self, key: &str, capacity: u32, refill_per_sec: f64, cost: u32, now: u64) -> Result<bool, Box<dyn Error>> {
+        let script = &TOKEN_BUCKET_SCRIPT;
+        let result: i32 = self.token_bucket_invocation(script, key, capacity, refill_per_sec, cost, now).invoke(&mut *self.pool.get()?)?;
+        Ok(result == 1)
+    }
+
+    fn increment_window(&self, key: &str, capacity: u32, cost: u32, window: Duration, now: u64) -> Result<bool, Box<dyn Error>> {
+        let script = &FIXED_WINDOW_SCRIPT;
+        let result: i32 = self.fixed_window_invocation(script, key, capacity, cost, window, now).invoke(&mut *self.pool.get()?)?;
+        Ok(result == 1)
+    }
+
+    fn increment_sliding_counter(&self, key: &str, capacity: u32, cost: u32, window: Duration, now: u64) -> Result<bool, Box<dyn Error>> {
+        let script = &SLIDING_COUNTER_SCRIPT;
+        let result: i32 = self.fixed_window_invocation(script, key, capacity, cost, window, now).invoke(&mut *self.pool.get()?)?;
+        Ok(result == 1)
+    }
+
+    fn leak_and_fill(&self, key: &str, bucket_size: u32, leak_per_sec: f64, cost: u32, now: u64) -> Result<bool, Box<dyn Error>> {
+        let script = &LEAKY_BUCKET_SCRIPT;
+        let result: i32 = self.leaky_bucket_invocation(script, key, bucket_size, leak_per_sec, cost, now).invoke(&mut *self.pool.get()?)?;
+        Ok(result == 1)
+    }
+
+    fn advance_tat(&self, key: &str, burst: u32, rate_per_sec: f64, cost: u32, now: u64) -> Result<bool, Box<dyn Error>> {
+        let script = &GCRA_SCRIPT;
+        let result: i32 = self.gcra_invocation(script, key, burst, rate_per_sec, cost, now).invoke(&mut *self.pool.get()?)?;
+        Ok(result == 1)
+    }
+
+    #[cfg(feature = "async")]
+    fn check_and_record_async<'a>(&'a self, key: &'a str, capacity: u32, cost: u32, window: Duration, now: u64) -> StorageFuture<'a, bool> {
+        Box::pin(async move {
+            let mut con = match self.multiplexed_connection().await? {
+                Some(con) => con,
+                None => {
+                    let key = key.to_string();
+                    return self.blocking(move |storage| storage.check_and_record(&key, capacity, cost, window, now)).await;
+                }
+            };
+            let script = self.sliding_window_script();
+            let (result, _): (i32, u32) = self.sliding_window_invocation(script, key, capacity, cost, window, now).invoke_async(&mut con).await?;
+            Ok(result == 1)
+        })
+    }
+
+    #[cfg(feature = "async")]
+    fn take_tokens_async<'a>(&'a self, key: &'a str, capacity: u32, refill_per_sec: f64, cost: u32, now: u64) -> StorageFuture<'a, bool> {
+        Box::pin(async move {
+            let mut con = match self.multiplexed_connection().await? {
+                Some(con) => con,
+                None => {
+                    let key = key.to_string();
+                    return self.blocking(move |storage| storage.take_tokens(&key, capacity, refill_per_sec, cost, now)).await;
+                }
+            };
+            let script = &TOKEN_BUCKET_SCRIPT;
+            let result: i32 = self.token_bucket_invocation(script, key, capacity, refill_per_sec, cost, now).invoke_async(&mut con).await?;
+            Ok(result == 1)
+        })
+    }
+
+    #[cfg(feature = "async")]
+    fn increment_window_async<'a>(&'a self, key: &'a str, capacity: u32, cost: u32, window: Duration, now: u64) -> StorageFuture<'a, bool> {
+        Box::pin(async move {
+            let mut con = match self.multiplexed_connection().await? {
+                Some(con) => con,
+                None => {
+                    let key = key.to_string();
+                    return self.blocking(move |storage| storage.increment_window(&key, capacity, cost, window, now)).await;
+                }
+            };
+            let script = &FIXED_WINDOW_SCRIPT;
+            let result: i32 = self.fixed_window_invocation(script, key, capacity, cost, window, now).invoke_async(&mut con).await?;
+            Ok(result == 1)
+        })
+    }
+
+    #[cfg(feature = "async")]
+    fn increment_sliding_counter_async<'a>(&'a self, key: &'a str, capacity: u32, cost: u32, window: Duration, now: u64) -> StorageFuture<'a, bool> {
+        Box::pin(async move {
+            let mut con = match self.multiplexed_connection().await? {
+                Some(con) => con,
+                None => {
+                    let key = key.to_string();
+                    return self.blocking(move |storage| storage.increment_sliding_counter(&key, capacity, cost, window, now)).await;
+                }
+            };
+            let script = &SLIDING_COUNTER_SCRIPT;
+            let result: i32 = self.fixed_window_invocation(script, key, capacity, cost, window, now).invoke_async(&mut con).await?;
+            Ok(result == 1)
+        })
+    }
+
+    #[cfg(feature = "async")]
+    fn leak_and_fill_async<'a>(&'a self, key: &'a str, bucket_size: u32, leak_per_sec: f64, cost: u32, now: u64) -> StorageFuture<'a, bool> {
+        Box::pin(async move {
+            let mut con = match self.multiplexed_connection().await? {
+                Some(con) => con,
+                None => {
+                    let key = key.to_string();
+                    return self.blocking(move |storage| storage.leak_and_fill(&key, bucket_size, leak_per_sec, cost, now)).await;
+                }
+            };
+            let script = &LEAKY_BUCKET_SCRIPT;
+            let result: i32 = self.leaky_bucket_invocation(script, key, bucket_size, leak_per_sec, cost, now).invoke_async(&mut con).await?;
+            Ok(result == 1)
+        })
+    }
+
+    #[cfg(feature = "async")]
+    fn advance_tat_async<'a>(&'a self, key: &'a str, burst: u32, rate_per_sec: f64, cost: u32, now: u64) -> StorageFuture<'a, bool> {
+        Box::pin(async move {
+            let mut con = match self.multiplexed_connection().await? {
+                Some(con) => con,
+                None => {
+                    let key = key.to_string();
+                    return self.blocking(move |storage| storage.advance_tat(&key, burst, rate_per_sec, cost, now)).await;
+                }
+            };
+            let script = &GCRA_SCRIPT;
+            let result: i32 = self.gcra_invocation(script, key, burst, rate_per_sec, cost, now).invoke_async(&mut con).await?;
+            Ok(result == 1)
+        })
+    }
+}
+
+/// Escapes the characters that have a special meaning in Redis glob patterns.
+fn escape_glob(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
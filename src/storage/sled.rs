@@ -0,0 +1,497 @@
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+use std::time::Duration;
+
+use sled::transaction::{TransactionError, TransactionResult};
+
+use crate::clock::{Clock, SystemClock};
+use crate::sliding_window::in_window;
+use super::{slide_counter, Storage};
+
+const LOG_TAG: u8 = 0;
+const BUCKET_TAG: u8 = 1;
+const COUNTER_TAG: u8 = 2;
+const LEAKY_TAG: u8 = 3;
+const TAT_TAG: u8 = 4;
+const SLIDING_COUNTER_TAG: u8 = 5;
+
+/// The state kept for a single key, as persisted on disk.
+///
+/// Logs and counters remember their window so expired state can be evicted when the
+/// database is reopened, before any request tells us the window again.
+enum Entry {
+    Log { window_millis: u64, timestamps: Vec<u64> },
+    Bucket { tokens: f64, last_refill: u64 },
+    Counter { window_millis: u64, window_id: u64, count: u32 },
+    SlidingCounter { window_millis: u64, window_id: u64, previous: u32, current: u32 },
+    Leaky { level: f64, last_leak: u64 },
+    Tat(f64),
+}
+
+impl Entry {
+    /// Encodes the entry as a tag byte followed by big-endian fields.
+    fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        match self {
+            Entry::Log { window_millis, timestamps } => {
+                bytes.push(LOG_TAG);
+                bytes.extend_from_slice(&window_millis.to_be_bytes());
+                for timestamp in timestamps {
+                    bytes.extend_from_slice(&timestamp.to_be_bytes());
+                }
+            },
+            Entry::Bucket { tokens, last_refill } => {
+                bytes.push(BUCKET_TAG);
+                bytes.extend_from_slice(&tokens.to_bits().to_be_bytes());
+                bytes.extend_from_slice(&last_refill.to_be_bytes());
+            },
+            Entry::Counter { window_millis, window_id, count } => {
+                bytes.push(COUNTER_TAG);
+                bytes.extend_from_slice(&window_millis.to_be_bytes());
+                bytes.extend_from_slice(&window_id.to_be_bytes());
+                bytes.extend_from_slice(&count.to_be_bytes());
+            },
+            Entry::SlidingCounter { window_millis, window_id, previous, current } => {
+                bytes.push(SLIDING_COUNTER_TAG);
+                bytes.extend_from_slice(&window_millis.to_be_bytes());
+                bytes.extend_from_slice(&window_id.to_be_bytes());
+                bytes.extend_from_slice(&previous.to_be_bytes());
+                bytes.extend_from_slice(&current.to_be_bytes());
+            },
+            Entry::Leaky { level, last_leak } => {
+                bytes.push(LEAKY_TAG);
+                bytes.extend_from_slice(&level.to_bits().to_be_bytes());
+                bytes.extend_from_slice(&last_leak.to_be_bytes());
+            },
+            Entry::Tat(tat) => {
+                bytes.push(TAT_TAG);
+                bytes.extend_from_slice(&tat.to_bits().to_be_bytes());
+            },
+        }
+        bytes
+    }
+
+    /// Decodes an entry written by [`Entry::encode`], or `None` if the bytes are malformed.
+    fn decode(bytes: &[u8]) -> Option<Entry> {
+        let (&tag, fields) = bytes.split_first()?;
+        let u64_at = |index: usize| -> Option<u64> {
+            let field = fields.get(index * 8..index * 8 + 8)?;
+            Some(u64::from_be_bytes(field.try_into().ok()?))
+        };
+
+        match tag {
+            LOG_TAG if fields.len() % 8 == 0 && !fields.is_empty() => Some(Entry::Log {
+                window_millis: u64_at(0)?,
+                timestamps: (1..fields.len() / 8).map(u64_at).collect::<Option<_>>()?,
+            }),
+            BUCKET_TAG if fields.len() == 16 => Some(Entry::Bucket {
+                tokens: f64::from_bits(u64_at(0)?),
+                last_refill: u64_at(1)?,
+            }),
+            COUNTER_TAG if fields.len() == 20 => Some(Entry::Counter {
+                window_millis: u64_at(0)?,
+                window_id: u64_at(1)?,
+                count: u32::from_be_bytes(fields[16..20].try_into().ok()?),
+            }),
+            SLIDING_COUNTER_TAG if fields.len() == 24 => Some(Entry::SlidingCounter {
+                window_millis: u64_at(0)?,
+                window_id: u64_at(1)?,
+                previous: u32::from_be_bytes(fields[16..20].try_into().ok()?),
+                current: u32::from_be_bytes(fields[20..24].try_into().ok()?),
+            }),
+            LEAKY_TAG if fields.len() == 16 => Some(Entry::Leaky {
+                level: f64::from_bits(u64_at(0)?),
+                last_leak: u64_at(1)?,
+            }),
+            TAT_TAG if fields.len() == 8 => Some(Entry::Tat(f64::from_bits(u64_at(0)?))),
+            _ => None,
+        }
+    }
+
+    /// Drops whatever part of the entry has expired at `now`, returning `None` if nothing is left.
+    fn evict_expired(self, now: u64) -> Option<Entry> {
+        match self {
+            Entry::Log { window_millis, mut timestamps } => {
+                timestamps.retain(|&timestamp| in_window(timestamp, Duration::from_millis(window_millis), now));
+                (!timestamps.is_empty()).then_some(Entry::Log { window_millis, timestamps })
+            },
+            Entry::Counter { window_millis, window_id, .. } if window_id != now / window_millis.max(1) => None,
+            // the current count still weighs in during the next window.
+            Entry::SlidingCounter { window_millis, window_id, .. } if window_id + 1 < now / window_millis.max(1) => None,
+            // a theoretical arrival time in the past is the same as no state at all.
+            Entry::Tat(tat) if tat <= now as f64 => None,
+            entry => Some(entry),
+        }
+    }
+}
+
+/// Keeps request logs in a [sled](https://docs.rs/sled) database on disk, so limits survive
+/// process restarts.
+///
+/// Opening the database evicts every expired timestamp and counter, so clients cannot burst
+/// after a restart, and a long downtime does not leave stale entries behind. Sled flushes to
+/// disk in the background, so the last few hundred milliseconds of requests may be lost on a
+/// crash.
+#[derive(Clone)]
+pub struct SledStorage {
+    db: sled::Db,
+}
+
+impl SledStorage {
+    /// Opens (or creates) the database at `path` and evicts the state that expired while it
+    /// was closed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database cannot be opened, e.g. because another process holds it.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Box<dyn Error>> {
+        let storage = Self { db: sled::open(path)? };
+        storage.evict_expired(SystemClock.now_millis())?;
+        Ok(storage)
+    }
+
+    /// Removes every expired timestamp and counter, deleting keys that have nothing left.
+    fn evict_expired(&self, now: u64) -> Result<(), Box<dyn Error>> {
+        for item in self.db.iter() {
+            let (key, value) = item?;
+            match Entry::decode(&value).and_then(|entry| entry.evict_expired(now)) {
+                Some(entry) => self.db.insert(key, entry.encode())?,
+                None => self.db.remove(key)?,
+            };
+        }
+        Ok(())
+    }
+
+    /// Atomically replaces the entry at `key` with the one computed by `f`, returning the
+    /// decision `f` made.
+    ///
+    /// `f` may run several times when other threads race on the same key, so it must only
+    /// depend on the entry it is given.
+    fn update<T>(&self, key: &str, f: impl Fn(Option<Entry>) -> (Entry, T)) -> Result<T, Box<dyn Error>> {
+        let previous = self.db.fetch_and_update(key, |bytes| {
+            let (entry, _) = f(bytes.and_then(Entry::decode));
+            Some(entry.encode())
+        })?;
+        // the update stored exactly what `f` computes from the previous entry, so its
+        // decision can be recovered from it.
+        let (_, decision) = f(previous.as_deref().and_then(Entry::decode));
+        Ok(decision)
+    }
+
+    /// Returns the timestamps of the log at `key` that fall in the window ending at `now`.
+    fn timestamps(&self, key: &str, window: Duration, now: u64) -> Result<Vec<u64>, Box<dyn Error>> {
+        match self.db.get(key)?.as_deref().and_then(Entry::decode) {
+            Some(Entry::Log { timestamps, .. }) => Ok(timestamps
+                .into_iter()
+                .filter(|&timestamp| in_window(timestamp, window, now) && timestamp <= now)
+                .collect()),
+            _ => Ok(Vec::new()),
+        }
+    }
+}
+
+/// Summarizes the database by its number of keys, leaving out the keys themselves, which name users.
+impl fmt::Debug for SledStorage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SledStorage").field("keys", &self.db.len()).finish()
+    }
+}
+
+impl Storage for SledStorage {
+    fn check_and_record(&self, key: &str, capacity: u32, cost: u32, window: Duration, now: u64) -> Result<bool, Box<dyn Error>> {
+        let window_millis = window.as_millis() as u64;
+        self.update(key, |entry| {
+            let mut timestamps = match entry {
+                Some(Entry::Log { timestamps, .. }) => timestamps,
+                _ => Vec::new(),
+            };
+            timestamps.retain(|&timestamp| in_window(timestamp, window, now));
+
+            let request_count = timestamps.iter().filter(|&&timestamp| timestamp <= now).count();
+            let is_allowed = request_count + cost as usize <= capacity as usize;
+            if is_allowed {
+                timestamps.resize(timestamps.len() + cost as usize, now);
+            }
+            (Entry::Log { window_millis, timestamps }, is_allowed)
+        })
+    }
+
+    fn count(&self, key: &str, window: Duration, now: u64) -> Result<u32, Box<dyn Error>> {
+        Ok(self.timestamps(key, window, now)?.len() as u32)
+    }
+
+    fn oldest(&self, key: &str, window: Duration, now: u64) -> Result<Option<u64>, Box<dyn Error>> {
+        Ok(self.timestamps(key, window, now)?.into_iter().min())
+    }
+
+    fn remove(&self, key: &str) -> Result<(), Box<dyn Error>> {
+        self.db.remove(key)?;
+        Ok(())
+    }
+
+    fn release(&self, key: &str, timestamp: u64) -> Result<(), Box<dyn Error>> {
+        self.db.fetch_and_update(key, |bytes| {
+            let bytes = bytes?;
+            match Entry::decode(bytes) {
+                Some(Entry::Log { window_millis, mut timestamps }) => {
+                    if let Some(position) = timestamps.iter().rposition(|&logged| logged == timestamp) {
+                        timestamps.remove(position);
+                    }
+                    Some(Entry::Log { window_millis, timestamps }.encode())
+                },
+                _ => Some(bytes.to_vec()),
+            }
+        })?;
+        Ok(())
+    }
+
+    fn timestamps(&self, key: &str, window: Duration, now: u64) -> Result<Vec<u64>, Box<dyn Error>> {
+        let mut timestamps = SledStorage::timestamps(self, key, window, now)?;
+        timestamps.sort_unstable();
+        Ok(timestamps)
+    }
+
+    fn remove_matching(&self, prefix: &str, suffix: &str) -> Result<u64, Box<dyn Error>> {
+        let mut removed = 0;
+        for item in self.db.scan_prefix(prefix) {
+            let (key, _) = item?;
+            if key.len() >= prefix.len() + suffix.len() && key.ends_with(suffix.as_bytes()) && self.db.remove(&key)?.is_some() {
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    fn usage(&self) -> Result<(usize, usize), Box<dyn Error>> {
+        let (mut keys, mut entries) = (0, 0);
+        for item in self.db.iter() {
+            let (_, bytes) = item?;
+            keys += 1;
+            entries += match Entry::decode(&bytes) {
+                Some(Entry::Log { timestamps, .. }) => timestamps.len(),
+                _ => 1,
+            };
+        }
+        Ok((keys, entries))
+    }
+
+    fn kind(&self) -> &'static str {
+        "sled"
+    }
+
+    fn check_and_record_all(&self, checks: &[(&str, u32, Duration)], now: u64) -> Result<bool, Box<dyn Error>> {
+        let result: TransactionResult<bool, ()> = self.db.transaction(|tx| {
+            let mut logs = Vec::with_capacity(checks.len());
+            for &(key, capacity, window) in checks {
+                let window_millis = window.as_millis() as u64;
+                let mut timestamps = match tx.get(key)?.as_deref().and_then(Entry::decode) {
+                    Some(Entry::Log { timestamps, .. }) => timestamps,
+                    _ => Vec::new(),
+                };
+                timestamps.retain(|&timestamp| in_window(timestamp, window, now));
+
+                let request_count = timestamps.iter().filter(|&&timestamp| timestamp <= now).count();
+                if request_count + 1 > capacity as usize {
+                    return Ok(false);
+                }
+                timestamps.push(now);
+                logs.push((key, Entry::Log { window_millis, timestamps }));
+            }
+
+            // every log has room, so the request is recorded in all of them.
+            for (key, entry) in &logs {
+                tx.insert(*key, entry.encode())?;
+            }
+            Ok(true)
+        });
+
+        match result {
+            Ok(is_allowed) => Ok(is_allowed),
+            Err(TransactionError::Storage(e)) => Err(Box::new(e)),
+            Err(TransactionError::Abort(())) => unreachable!("the transaction never aborts"),
+        }
+    }
+
+    fn take_available(&self, key: &str, capacity: u32, desired: u32, window: Duration, now: u64) -> Result<u32, Box<dyn Error>> {
+        let window_millis = window.as_millis() as u64;
+        self.update(key, |entry| {
+            let mut timestamps = match entry {
+                Some(Entry::Log { timestamps, .. }) => timestamps,
+                _ => Vec::new(),
+            };
+            timestamps.retain(|&timestamp| in_window(timestamp, window, now));
+
+            let request_count = timestamps.iter().filter(|&&timestamp| timestamp <= now).count() as u32;
+            let granted = desired.min(capacity.saturating_sub(request_count));
+            timestamps.resize(timestamps.len() + granted as usize, now);
+            (Entry::Log { window_millis, timestamps }, granted)
+        })
+    }
+
+    fn take_tokens(&self, key: &str, capacity: u32, refill_per_sec: f64, cost: u32, now: u64) -> Result<bool, Box<dyn Error>> {
+        self.update(key, |entry| {
+            let (tokens, last_refill) = match entry {
+                Some(Entry::Bucket { tokens, last_refill }) => (tokens, last_refill),
+                _ => (capacity as f64, now),
+            };
+
+            // refill based on the time elapsed since the last request, never above capacity.
+            let elapsed_millis = now.saturating_sub(last_refill);
+            let tokens = (tokens + elapsed_millis as f64 * refill_per_sec / 1000.0).min(capacity as f64);
+            let last_refill = last_refill.max(now);
+
+            if tokens >= cost as f64 {
+                (Entry::Bucket { tokens: tokens - cost as f64, last_refill }, true)
+            } else {
+                (Entry::Bucket { tokens, last_refill }, false)
+            }
+        })
+    }
+
+    fn leak_and_fill(&self, key: &str, bucket_size: u32, leak_per_sec: f64, cost: u32, now: u64) -> Result<bool, Box<dyn Error>> {
+        self.update(key, |entry| {
+            let (level, last_leak) = match entry {
+                Some(Entry::Leaky { level, last_leak }) => (level, last_leak),
+                _ => (0.0, now),
+            };
+
+            // drain based on the time elapsed since the last request, never below empty.
+            let elapsed_millis = now.saturating_sub(last_leak);
+            let level = (level - elapsed_millis as f64 * leak_per_sec / 1000.0).max(0.0);
+            let last_leak = last_leak.max(now);
+
+            if level + cost as f64 <= bucket_size as f64 {
+                (Entry::Leaky { level: level + cost as f64, last_leak }, true)
+            } else {
+                (Entry::Leaky { level, last_leak }, false)
+            }
+        })
+    }
+
+    fn advance_tat(&self, key: &str, burst: u32, rate_per_sec: f64, cost: u32, now: u64) -> Result<bool, Box<dyn Error>> {
+        let emission_interval = 1000.0 / rate_per_sec;
+
+        self.update(key, |entry| {
+            let tat = match entry {
+                Some(Entry::Tat(tat)) => tat.max(now as f64),
+                _ => now as f64,
+            };
+
+            let new_tat = tat + cost as f64 * emission_interval;
+            if new_tat - burst as f64 * emission_interval <= now as f64 {
+                (Entry::Tat(new_tat), true)
+            } else {
+                (Entry::Tat(tat), false)
+            }
+        })
+    }
+
+    fn increment_window(&self, key: &str, capacity: u32, cost: u32, window: Duration, now: u64) -> Result<bool, Box<dyn Error>> {
+        let window_millis = window.as_millis() as u64;
+        let current_window_id = now / window_millis.max(1);
+
+        self.update(key, |entry| {
+            // a new window started since the last request, so the count starts over.
+            let count = match entry {
+                Some(Entry::Counter { window_id, count, .. }) if window_id == current_window_id => count,
+                _ => 0,
+            };
+
+            let is_allowed = count.saturating_add(cost) <= capacity;
+            let count = if is_allowed { count + cost } else { count };
+            (Entry::Counter { window_millis, window_id: current_window_id, count }, is_allowed)
+        })
+    }
+
+    fn increment_sliding_counter(&self, key: &str, capacity: u32, cost: u32, window: Duration, now: u64) -> Result<bool, Box<dyn Error>> {
+        let window_millis = window.as_millis() as u64;
+
+        self.update(key, |entry| {
+            let counts = match entry {
+                Some(Entry::SlidingCounter { window_id, previous, current, .. }) => Some((window_id, previous, current)),
+                _ => None,
+            };
+
+            let ((window_id, previous, current), is_allowed) = slide_counter(counts, capacity, cost, window, now);
+            (Entry::SlidingCounter { window_millis, window_id, previous, current }, is_allowed)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A database path under the system temp dir, removed when dropped.
+    struct TempPath(std::path::PathBuf);
+
+    impl TempPath {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("ratelimiter-rs-{}-{}", name, std::process::id()));
+            let _ = std::fs::remove_dir_all(&path);
+            Self(path)
+        }
+    }
+
+    impl Drop for TempPath {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn test_state_survives_reopen() {
+        let path = TempPath::new("reopen");
+        let now = SystemClock.now_millis();
+        let window = Duration::from_secs(60);
+
+        let storage = SledStorage::open(&path.0).unwrap();
+        assert!(storage.check_and_record("user1:type1", 2, 2, window, now).unwrap());
+        assert!(storage.increment_window("user1:type2", 1, 1, window, now).unwrap());
+        assert!(storage.take_tokens("user1:type3", 1, 0.001, 1, now).unwrap());
+        drop(storage);
+
+        let storage = SledStorage::open(&path.0).unwrap();
+        assert_eq!(storage.count("user1:type1", window, now).unwrap(), 2);
+        assert!(!storage.check_and_record("user1:type1", 2, 1, window, now).unwrap());
+        assert!(!storage.increment_window("user1:type2", 1, 1, window, now).unwrap());
+        assert!(!storage.take_tokens("user1:type3", 1, 0.001, 1, now).unwrap());
+    }
+
+    #[test]
+    fn test_check_and_record_all() {
+        let path = TempPath::new("all");
+        let now = SystemClock.now_millis();
+        let window = Duration::from_secs(60);
+
+        let storage = SledStorage::open(&path.0).unwrap();
+        assert!(storage.check_and_record_all(&[("user1:type1", 2, window), ("user1:type2", 1, window)], now).unwrap());
+        assert!(!storage.check_and_record_all(&[("user1:type1", 2, window), ("user1:type2", 1, window)], now).unwrap());
+        assert_eq!(storage.count("user1:type1", window, now).unwrap(), 1);
+        assert_eq!(storage.count("user1:type2", window, now).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_open_evicts_expired_state() {
+        let path = TempPath::new("evict");
+        let now = SystemClock.now_millis();
+        let window = Duration::from_secs(60);
+        let long_ago = now - 10 * 60 * 1000;
+
+        let storage = SledStorage::open(&path.0).unwrap();
+        storage.check_and_record("user1:type1", 5, 1, window, long_ago).unwrap();
+        storage.check_and_record("user1:type1", 5, 1, window, now).unwrap();
+        storage.check_and_record("user2:type1", 5, 1, window, long_ago).unwrap();
+        storage.increment_window("user1:type2", 5, 1, window, long_ago).unwrap();
+        drop(storage);
+
+        let storage = SledStorage::open(&path.0).unwrap();
+        match storage.db.get("user1:type1").unwrap().as_deref().and_then(Entry::decode) {
+            Some(Entry::Log { timestamps, .. }) => assert_eq!(timestamps, vec![now]),
+            _ => panic!("expected a request log"),
+        }
+        assert!(!storage.db.contains_key("user2:type1").unwrap());
+        assert!(!storage.db.contains_key("user1:type2").unwrap());
+    }
+}
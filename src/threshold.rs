@@ -0,0 +1,40 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, PoisonError};
+
+type Callback = dyn Fn(&str, f64) + Send + Sync;
+
+/// A callback for users whose utilization of a request type reaches `fraction`, registered
+/// with [`RateLimiter::on_threshold`](crate::RateLimiter::on_threshold).
+#[derive(Clone)]
+pub(crate) struct Threshold {
+    fraction: f64,
+    callback: Arc<Callback>,
+    // user id -> when the callback last fired for them, in milliseconds
+    fired: Arc<Mutex<HashMap<String, u64>>>,
+}
+
+impl Threshold {
+    pub(crate) fn new(fraction: f64, callback: impl Fn(&str, f64) + Send + Sync + 'static) -> Self {
+        Self { fraction, callback: Arc::new(callback), fired: Arc::new(Mutex::new(HashMap::new())) }
+    }
+
+    /// Calls the callback if `utilization` reached the threshold, unless it already fired for
+    /// `user_id` within the last `window_millis`.
+    pub(crate) fn observe(&self, user_id: &str, utilization: f64, window_millis: u64, now_millis: u64) {
+        if utilization < self.fraction {
+            return;
+        }
+        {
+            let mut fired = self.fired.lock().unwrap_or_else(PoisonError::into_inner);
+            if fired.get(user_id).is_some_and(|&fired_at| now_millis.saturating_sub(fired_at) < window_millis) {
+                return;
+            }
+            // crossings are rare next to requests, so users whose window is over are only
+            // swept when another one crosses.
+            fired.retain(|_, fired_at| now_millis.saturating_sub(*fired_at) < window_millis);
+            fired.insert(user_id.to_string(), now_millis);
+        }
+        // the lock is released first, so the callback may check requests itself.
+        (self.callback)(user_id, utilization);
+    }
+}
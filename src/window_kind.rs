@@ -0,0 +1,12 @@
+/// How the window of a config is placed in time, for
+/// [`RateLimiter::add_config_with_kind`](crate::RateLimiter::add_config_with_kind).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WindowKind {
+    /// The window ends at every request, so a request frees up capacity exactly one window
+    /// after it was made.
+    #[default]
+    Rolling,
+    /// Windows start every `now - (now % window)` milliseconds since the unix epoch, so the
+    /// limit resets for everyone at once, e.g. at the top of every UTC hour.
+    Aligned,
+}